@@ -15,7 +15,7 @@ fn main() -> Result<()> {
                 let code = vm
                     .compile_in_memory("<bin>", "print('Compiled code!')")
                     .ok()?;
-                Some(Module::binary(code, "<bin>".to_owned()))
+                Module::binary(code, "<bin>".to_owned()).ok()
             } else {
                 None
             }
@@ -18,10 +18,7 @@ fn main() -> Result<()> {
                 end
                 ";
 
-                Some(Module::source(
-                    code.to_owned(),
-                    "hello_world.jsr".to_owned(),
-                ))
+                Module::source(code.to_owned(), "hello_world.jsr".to_owned()).ok()
             } else {
                 None
             }
@@ -2,16 +2,7 @@ use jstar::{self, conf::Conf, error::Result, vm::VM};
 use std::io::{self, BufRead, Write};
 
 fn main() -> Result<()> {
-    let conf = Conf::new().error_callback(Box::new(|_, file, line, msg| {
-        if let Some(line) = line {
-            eprintln!("Error {file} [line:{line}]:");
-        } else {
-            eprintln!("Error {file}:");
-        }
-        eprintln!("{msg}");
-    }));
-
-    let vm = VM::new(conf).init_runtime();
+    let vm = VM::new(Conf::new()).init_runtime();
 
     let mut stdin = io::stdin().lock();
     loop {
@@ -23,7 +14,9 @@ fn main() -> Result<()> {
             break;
         }
 
-        let _ = vm.eval("<repl>", &line);
+        if let Err(err) = vm.eval("<repl>", &line) {
+            eprintln!("{}", vm.format_error(&err));
+        }
     }
 
     Ok(())
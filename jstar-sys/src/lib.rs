@@ -7,6 +7,7 @@ pub enum JStarVM {}
 pub type JStarNative = extern "C" fn(*mut JStarVM) -> bool;
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy)]
 pub enum JStarResult {
     Success,
     SyntaxErr,
@@ -120,7 +121,7 @@ extern "C" {
     ) -> JStarResult;
 
     pub fn jsrCall(vm: *mut JStarVM, argc: u8) -> JStarResult;
-    pub fn jsrCallMethod(vm: *mut JStarVM, name: *const c_char, argc: u8);
+    pub fn jsrCallMethod(vm: *mut JStarVM, name: *const c_char, argc: u8) -> JStarResult;
 }
 
 // -----------------------------------------------------------------------------
@@ -140,7 +141,11 @@ extern "C" {
     pub fn jsrPushTuple(vm: *mut JStarVM, size: usize);
     pub fn jsrPushTable(vm: *mut JStarVM);
     pub fn jsrPushValue(vm: *mut JStarVM, slot: c_int);
-    pub fn jsrPushUserdata(vm: *mut JStarVM, size: usize, finalize: UserdataFinalizeCB);
+    pub fn jsrPushUserdata(
+        vm: *mut JStarVM,
+        size: usize,
+        finalize: UserdataFinalizeCB,
+    ) -> *mut c_void;
     pub fn jsrPushNative(
         vm: *mut JStarVM,
         module: *const c_char,
@@ -169,13 +174,64 @@ extern "C" {
     pub fn jsrGetHandle(vm: *mut JStarVM, slot: c_int) -> *mut c_void;
     pub fn jsrGetStringSz(vm: *mut JStarVM, slot: c_int) -> usize;
     pub fn jsrGetString(vm: *mut JStarVM, slot: c_int) -> *const c_char;
+    pub fn jsrGetUserdata(vm: *mut JStarVM, slot: c_int) -> *mut c_void;
+}
+// -----------------------------------------------------------------------------
+// LIST API
+// -----------------------------------------------------------------------------
+
+extern "C" {
+    pub fn jsrListAppend(vm: *mut JStarVM, slot: c_int);
+    pub fn jsrListInsert(vm: *mut JStarVM, i: usize, slot: c_int);
+    pub fn jsrListRemove(vm: *mut JStarVM, i: usize, slot: c_int);
+    pub fn jsrListGet(vm: *mut JStarVM, i: usize, slot: c_int);
+    pub fn jsrListSet(vm: *mut JStarVM, i: usize, slot: c_int);
+    pub fn jsrListGetLength(vm: *mut JStarVM, slot: c_int) -> usize;
+}
+
+// -----------------------------------------------------------------------------
+// TUPLE API
+// -----------------------------------------------------------------------------
+
+extern "C" {
+    pub fn jsrTupleGet(vm: *mut JStarVM, i: usize, slot: c_int);
+    pub fn jsrTupleGetLength(vm: *mut JStarVM, slot: c_int) -> usize;
+}
+
+// -----------------------------------------------------------------------------
+// ITERABLE PROTOCOL
+// -----------------------------------------------------------------------------
+
+extern "C" {
+    pub fn jsrIter(vm: *mut JStarVM, iterable: c_int, res: c_int, err: *mut bool) -> bool;
+    pub fn jsrNext(vm: *mut JStarVM, iterable: c_int, res: c_int) -> bool;
 }
+
 // -----------------------------------------------------------------------------
 // EXCEPTION API
 // -----------------------------------------------------------------------------
 
 extern "C" {
     pub fn jsrRaise(vm: *mut JStarVM, cls: *const c_char, err: *const c_char, ...);
+    pub fn jsrPrintStacktrace(vm: *mut JStarVM, slot: c_int);
+    pub fn jsrGetStacktrace(vm: *mut JStarVM, slot: c_int);
+}
+
+// -----------------------------------------------------------------------------
+// OPERATOR API
+// -----------------------------------------------------------------------------
+
+extern "C" {
+    pub fn jsrIs(vm: *mut JStarVM, slot: c_int, class_slot: c_int) -> bool;
+}
+
+// -----------------------------------------------------------------------------
+// FIELD API
+// -----------------------------------------------------------------------------
+
+extern "C" {
+    pub fn jsrGetField(vm: *mut JStarVM, slot: c_int, name: *const c_char) -> bool;
+    pub fn jsrSetField(vm: *mut JStarVM, slot: c_int, name: *const c_char) -> bool;
 }
 
 // -----------------------------------------------------------------------------
@@ -319,8 +375,21 @@ impl Default for JStarBuffer {
 }
 
 extern "C" {
+    pub fn jsrBufferInit(vm: *mut JStarVM, b: *mut JStarBuffer);
+    pub fn jsrBufferInitCapacity(vm: *mut JStarVM, b: *mut JStarBuffer, capacity: usize);
+    pub fn jsrBufferAppend(b: *mut JStarBuffer, str: *const c_char, len: usize);
+    pub fn jsrBufferAppendStr(b: *mut JStarBuffer, str: *const c_char);
+    pub fn jsrBufferAppendf(b: *mut JStarBuffer, fmt: *const c_char, ...);
+    pub fn jsrBufferTrunc(b: *mut JStarBuffer, len: usize);
+    pub fn jsrBufferCut(b: *mut JStarBuffer, len: usize);
+    pub fn jsrBufferReplaceChar(b: *mut JStarBuffer, start: usize, c: c_char, r: c_char);
+    pub fn jsrBufferPrepend(b: *mut JStarBuffer, str: *const c_char, len: usize);
+    pub fn jsrBufferPrependStr(b: *mut JStarBuffer, str: *const c_char);
+    pub fn jsrBufferAppendChar(b: *mut JStarBuffer, c: c_char);
+    pub fn jsrBufferShrinkToFit(b: *mut JStarBuffer);
+    pub fn jsrBufferClear(b: *mut JStarBuffer);
     pub fn jsrBufferPush(b: *mut JStarBuffer);
     pub fn jsrBufferFree(b: *mut JStarBuffer);
 }
 
-// omitted: JStarBuffer API
+// omitted: jsrBufferAppendvf
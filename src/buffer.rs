@@ -0,0 +1,158 @@
+use crate::{ffi, vm::VM};
+
+use std::{
+    io::{self, Write},
+    mem::ManuallyDrop,
+    ops::Deref,
+    os::raw::c_char,
+};
+
+/// [Buffer] is a growable byte buffer whose memory is managed by the J* allocator.
+///
+/// It is a safe wrapper around a [ffi::JStarBuffer], created with [VM::new_buffer]. Bytes can be
+/// appended through the [Write] implementation or the methods below, and the contents can be read
+/// as a byte slice, thanks to the [`Deref<Target = [u8]>`](Deref) implementation. For example, a
+/// buffer can be passed directly to [VM::eval] without copying its contents to a [Vec].
+///
+/// When the bytes are meant to become a J* `String`, [Buffer::push] hands the buffer's memory over
+/// to the `String` without copying it.
+///
+/// ```
+/// # use jstar::{conf::Conf, vm::VM};
+/// # use std::io::Write;
+/// # let vm = VM::new(Conf::new()).init_runtime();
+/// let mut buf = vm.new_buffer();
+/// write!(buf, "var answer = {}", 42).unwrap();
+/// vm.eval("<buffer>", &*buf).unwrap();
+/// ```
+pub struct Buffer<'vm> {
+    buf: ffi::JStarBuffer,
+    vm: &'vm VM<'vm>,
+}
+
+impl<'vm> Buffer<'vm> {
+    /// Construct a new [Buffer] starting from a J* buffer initialized by `vm`.
+    pub(crate) fn new(buf: ffi::JStarBuffer, vm: &'vm VM<'vm>) -> Self {
+        Buffer { buf, vm }
+    }
+
+    /// Appends `bytes` at the end of the buffer.
+    pub fn append(&mut self, bytes: &[u8]) {
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe {
+            ffi::jsrBufferAppend(&mut self.buf, bytes.as_ptr() as *const c_char, bytes.len())
+        };
+    }
+
+    /// Appends a single byte at the end of the buffer.
+    pub fn append_byte(&mut self, byte: u8) {
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe { ffi::jsrBufferAppendChar(&mut self.buf, byte as c_char) };
+    }
+
+    /// Inserts `bytes` at the start of the buffer.
+    pub fn prepend(&mut self, bytes: &[u8]) {
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe {
+            ffi::jsrBufferPrepend(&mut self.buf, bytes.as_ptr() as *const c_char, bytes.len())
+        };
+    }
+
+    /// Shortens the buffer, keeping the first `len` bytes.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `len` is greater than the length of the buffer.
+    pub fn truncate(&mut self, len: usize) {
+        assert!(len <= self.len(), "`len` out of bounds");
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe { ffi::jsrBufferTrunc(&mut self.buf, len) };
+    }
+
+    /// Removes the first `len` bytes of the buffer, shifting the remaining ones to the start.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `len` is greater than the length of the buffer.
+    pub fn cut(&mut self, len: usize) {
+        assert!(len <= self.len(), "`len` out of bounds");
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe { ffi::jsrBufferCut(&mut self.buf, len) };
+    }
+
+    /// Replaces all the occurrences of `from` with `to`, starting at offset `start`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `start` is greater than the length of the buffer.
+    pub fn replace_byte(&mut self, start: usize, from: u8, to: u8) {
+        assert!(start <= self.len(), "`start` out of bounds");
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe { ffi::jsrBufferReplaceChar(&mut self.buf, start, from as c_char, to as c_char) };
+    }
+
+    /// Removes all the bytes from the buffer, keeping its allocated memory.
+    pub fn clear(&mut self) {
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe { ffi::jsrBufferClear(&mut self.buf) };
+    }
+
+    /// Shrinks the memory allocated by the buffer to fit its contents.
+    pub fn shrink_to_fit(&mut self) {
+        // SAFETY: `self.buf` is a valid and initialized J* buffer
+        unsafe { ffi::jsrBufferShrinkToFit(&mut self.buf) };
+    }
+
+    /// Pushes the contents of the buffer onto the VM stack as a J* `String`, consuming the
+    /// buffer. The memory of the buffer is reused by the `String`, so no copy takes place.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push(self) {
+        assert!(self.vm.validate_stack(), "VM stack overflow");
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this.buf` is a valid and initialized J* buffer. `jsrBufferPush` takes
+        // ownership of its memory, so it must not be freed (hence the `ManuallyDrop`)
+        unsafe { ffi::jsrBufferPush(&mut this.buf) };
+    }
+}
+
+impl Deref for Buffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.buf.data.is_null() {
+            return &[];
+        }
+        // SAFETY: `self.buf` is a valid and initialized J* buffer, so `data` points to at least
+        // `size` initialized bytes. The slice borrows the buffer, so it cannot be mutated or freed
+        // while the slice is alive
+        unsafe { std::slice::from_raw_parts(self.buf.data as *const u8, self.buf.size) }
+    }
+}
+
+impl AsRef<[u8]> for Buffer<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Write for Buffer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.append(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Buffer<'_> {
+    fn drop(&mut self) {
+        // SAFETY: `self.buf` is a valid and initialized J* buffer that hasn't been pushed
+        unsafe { ffi::jsrBufferFree(&mut self.buf) };
+    }
+}
@@ -23,6 +23,47 @@ pub type ErrorCallback<'a> = Box<dyn FnMut(Error, &str, Option<i32>, &str) + 'a>
 /// `Some(Module)` if the module was found, `None` otherwise.
 pub type ImportCallback<'a> = Box<dyn FnMut(&mut VM, &str) -> Option<Module> + 'a>;
 
+/// Callback invoked after each import resolution attempt, for logging or debugging purposes
+///
+/// # Arguments
+///
+/// * `module_name` - A string that contains the full path of the import as it appears in the J*
+///   code
+/// * `resolved`    - Whether or not the module was found by the [ImportCallback]
+pub type ImportObserver<'a> = Box<dyn FnMut(&str, bool) + 'a>;
+
+/// The rate at which the J* heap grows after a GC pass.
+///
+/// After each collection the J* vm schedules the next one when the heap reaches the size of the
+/// live objects multiplied by this factor. Higher rates trade memory for fewer collections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GrowRate(i32);
+
+impl GrowRate {
+    /// Creates a [GrowRate] that multiplies the heap size by `factor`.
+    ///
+    /// # Returns
+    ///
+    /// `Some(GrowRate)` if `factor` is greater than 1, `None` otherwise. Lower factors would
+    /// schedule the next collection at or below the current heap size, triggering a collection
+    /// on every allocation.
+    pub fn factor(factor: i32) -> Option<Self> {
+        (factor > 1).then_some(GrowRate(factor))
+    }
+
+    /// Returns the factor the heap size is multiplied by.
+    pub fn as_factor(&self) -> i32 {
+        self.0
+    }
+}
+
+impl Default for GrowRate {
+    /// The default rate provided by the J* C API.
+    fn default() -> Self {
+        GrowRate(ffi::JStarConf::default().heap_grow_rate)
+    }
+}
+
 /// Struct containing a set of configurations for the J* vm.
 pub struct Conf<'a> {
     /// The initial stack size of the vm (in bytes)
@@ -30,11 +71,13 @@ pub struct Conf<'a> {
     /// Threshold at which the first GC collection will happen (in bytes)
     pub first_gc_collection_point: usize,
     /// The rate at which the heap will grow after a GC pass
-    pub heap_grow_rate: i32,
+    pub heap_grow_rate: GrowRate,
     /// Function called when an error occurs
     pub error_callback: Option<ErrorCallback<'a>>,
     /// Function called to resolve a module
     pub import_callback: Option<ImportCallback<'a>>,
+    /// Function called after each attempt to resolve a module
+    pub import_observer: Option<ImportObserver<'a>>,
 }
 
 impl<'a> Conf<'a> {
@@ -44,9 +87,10 @@ impl<'a> Conf<'a> {
         Conf {
             starting_stack_sz: jstar_conf.starting_stack_sz,
             first_gc_collection_point: jstar_conf.first_gc_collection_point,
-            heap_grow_rate: jstar_conf.heap_grow_rate,
+            heap_grow_rate: GrowRate(jstar_conf.heap_grow_rate),
             error_callback: None,
             import_callback: None,
+            import_observer: None,
         }
     }
 
@@ -63,7 +107,7 @@ impl<'a> Conf<'a> {
     }
 
     /// Set the heap grow rate returns self for chaining
-    pub fn heap_grow_rate(mut self, rate: i32) -> Self {
+    pub fn heap_grow_rate(mut self, rate: GrowRate) -> Self {
         self.heap_grow_rate = rate;
         self
     }
@@ -79,10 +123,56 @@ impl<'a> Conf<'a> {
         self.import_callback = Some(import_cb);
         self
     }
+
+    /// Set the import observer returns self for chaining.
+    ///
+    /// The observer is purely informational: it is called after the import callback (if any) with
+    /// the name of the module and whether it was resolved, and has no way to influence the import.
+    /// Modules already loaded and built-in modules are resolved by J* without attempting an import,
+    /// so they are not observed.
+    pub fn import_observer(mut self, observer: ImportObserver<'a>) -> Self {
+        self.import_observer = Some(observer);
+        self
+    }
 }
 
 impl Default for Conf<'_> {
+    /// Equivalent to [Conf::new], i.e. uses the default values provided by the J* C API.
     fn default() -> Self {
         Conf::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn default_matches_new() {
+        let default = Conf::default();
+        let new = Conf::new();
+
+        assert!(default.starting_stack_sz > 0);
+        assert_eq!(default.starting_stack_sz, new.starting_stack_sz);
+        assert_eq!(
+            default.first_gc_collection_point,
+            new.first_gc_collection_point
+        );
+        assert_eq!(default.heap_grow_rate, new.heap_grow_rate);
+        assert_eq!(default.heap_grow_rate, GrowRate::default());
+    }
+
+    #[test]
+    fn grow_rate() {
+        let rate = GrowRate::factor(4).unwrap();
+        assert_eq!(rate.as_factor(), 4);
+        assert_eq!(Conf::new().heap_grow_rate(rate).heap_grow_rate, rate);
+        assert!(GrowRate::default().as_factor() > 1);
+
+        assert!(GrowRate::factor(1).is_none());
+        assert!(GrowRate::factor(0).is_none());
+        assert!(GrowRate::factor(-2).is_none());
+    }
+}
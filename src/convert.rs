@@ -1,19 +1,21 @@
 use crate::{
-    error::Result,
+    error::{Error, Result},
     vm::{Index, VM},
 };
 
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    hash::{BuildHasher, Hash},
+    rc::Rc,
+};
+
 macro_rules! to_jstar_number_impl {
     ($($t:ty),*) => {
         $(impl ToJStar for $t {
             fn to_jstar(&self, vm: &VM) {
                 vm.push_number(*self as f64);
             }
-        }
-        impl ToJStar for &$t {
-            fn to_jstar(&self, vm: &VM) {
-                (*self).to_jstar(vm);
-            }
         })*
     };
 }
@@ -32,14 +34,220 @@ macro_rules! from_jstar_number_impl {
     };
 }
 
+macro_rules! range_impl {
+    ($($t:ty),*) => {
+        $(impl ToJStar for std::ops::Range<$t> {
+            /// Pushes the range onto the J* stack as a `(start, end)` `Tuple`.
+            fn to_jstar(&self, vm: &VM) {
+                vm.ensure_stack(2);
+                vm.push_number(self.start as f64);
+                vm.push_number(self.end as f64);
+                vm.push_tuple(2);
+            }
+        }
+
+        impl<'vm> FromJStar<'vm> for std::ops::Range<$t> {
+            /// Gets a half-open range from a `(start, end)` `Tuple` of integers.
+            /// Returns `None` if the value is not such a `Tuple`, if `start > end` or if the bounds
+            /// don't fit in the integer type.
+            fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+                let (start, end) = get_int_pair(vm, slot)?;
+                let fits = |n: f64| n >= <$t>::MIN as f64 && n < <$t>::MAX as f64 + 1.0;
+                (start <= end && fits(start) && fits(end)).then_some(start as $t..end as $t)
+            }
+
+            /// Gets a half-open range from a `(start, end)` `Tuple` of integers.
+            /// If the value is not such a `Tuple` this method leaves a `TypeException` on top of
+            /// the stack, if `start > end` or if the bounds don't fit in the integer type it
+            /// leaves an `InvalidArgException`.
+            fn from_jstar_checked(
+                vm: &'vm VM,
+                slot: Index,
+                name: &str,
+            ) -> $crate::error::Result<Self> {
+                let Some((start, end)) = get_int_pair(vm, slot) else {
+                    vm.raise(
+                        "TypeException",
+                        &format!("{name} must be a Tuple of two integers"),
+                    );
+                    return Err($crate::error::Error::Runtime);
+                };
+                if start > end {
+                    vm.raise(
+                        "InvalidArgException",
+                        &format!("{name} has start {start} greater than end {end}"),
+                    );
+                    return Err($crate::error::Error::Runtime);
+                }
+                // `MIN` is a power of two (or zero) and `MAX + 1` rounds to the next one, so both
+                // bounds are exact even for 64 bit types
+                let fits = |n: f64| n >= <$t>::MIN as f64 && n < <$t>::MAX as f64 + 1.0;
+                if !fits(start) || !fits(end) {
+                    vm.raise(
+                        "InvalidArgException",
+                        &format!("{name} is out of range for {}", stringify!($t)),
+                    );
+                    return Err($crate::error::Error::Runtime);
+                }
+                Ok(start as $t..end as $t)
+            }
+        })*
+    };
+}
+
+macro_rules! tuple_impl {
+    ($len:expr; $($t:ident $i:tt),+) => {
+        impl<$($t: ToJStar),+> ToJStar for ($($t,)+) {
+            /// Pushes the Rust tuple onto the J* stack as a `Tuple`.
+            fn to_jstar(&self, vm: &VM) {
+                vm.ensure_stack($len);
+                $(self.$i.to_jstar(vm);)+
+                vm.push_tuple($len);
+            }
+        }
+
+        impl<'vm, $($t: for<'v> FromJStar<'v>),+> FromJStar<'vm> for ($($t,)+) {
+            /// Reads a J* `Tuple` or `List` with the same number of elements into a Rust tuple.
+            /// Returns `None` if the value is not a `Tuple` or a `List`, if its length doesn't
+            /// match or if any of its elements has the wrong type.
+            fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+                if get_seq_len(vm, slot)? != $len {
+                    return None;
+                }
+                Some(($(get_seq_elem::<$t>(vm, slot, $i)?,)+))
+            }
+
+            /// Reads a J* `Tuple` or `List` with the same number of elements into a Rust tuple.
+            /// If the value is not a `Tuple` or a `List`, if its length doesn't match or if any of
+            /// its elements has the wrong type, this method returns an error leaving a
+            /// `TypeException` on top of the stack.
+            fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+                let Some(len) = get_seq_len(vm, slot) else {
+                    vm.raise("TypeException", &format!("{name} must be a Tuple or a List"));
+                    return Err(Error::Runtime);
+                };
+                if len != $len {
+                    vm.raise(
+                        "TypeException",
+                        &format!("{name} must have {} elements, got {len}", $len),
+                    );
+                    return Err(Error::Runtime);
+                }
+                Ok(($(
+                    match get_seq_elem::<$t>(vm, slot, $i) {
+                        Some(elem) => elem,
+                        None => {
+                            vm.raise(
+                                "TypeException",
+                                &format!("{name} element {} has the wrong type", $i),
+                            );
+                            return Err(Error::Runtime);
+                        }
+                    },
+                )+))
+            }
+        }
+    };
+}
+
+/// Gets the length of the `Tuple` or `List` at `slot`.
+fn get_seq_len(vm: &VM, slot: Index) -> Option<usize> {
+    vm.get_tuple_len(slot).or_else(|| vm.get_list_len(slot))
+}
+
+/// Gets the `i`th element of the `Tuple` or `List` at `slot`, converted to `T`.
+fn get_seq_elem<T: for<'v> FromJStar<'v>>(vm: &VM, slot: Index, i: usize) -> Option<T> {
+    if vm.is_tuple(slot) {
+        vm.with_tuple_elem(slot, i, |vm| T::from_jstar(vm, -1))
+    } else {
+        vm.with_list_elem(slot, i, |vm| T::from_jstar(vm, -1))
+    }
+}
+
+/// Gets the elements of a `(start, end)` `Tuple` of integers at `slot`.
+fn get_int_pair(vm: &VM, slot: Index) -> Option<(f64, f64)> {
+    if vm.get_tuple_len(slot)? != 2 {
+        return None;
+    }
+    let get_int =
+        |i| vm.with_tuple_elem(slot, i, |vm| vm.get_number(-1).filter(|n| n.fract() == 0.0));
+    Some((get_int(0)?, get_int(1)?))
+}
+
 /// Trait used to push a value onto the J* stack.
 /// Types that implement this trait usually have a corresponding `push_...` method in the [VM].
+///
+/// This trait is object safe, so values of different types can be pushed through a
+/// `dyn ToJStar` (e.g. a `Vec<Box<dyn ToJStar>>` of heterogeneous arguments).
 pub trait ToJStar {
     /// Pushes the value onto the J* stack
     fn to_jstar(&self, vm: &VM);
 }
 
-to_jstar_number_impl!(f64, f32, u64, u32, u16, u8, i64, i32, i16, i8);
+to_jstar_number_impl!(f64, f32, u32, u16, u8, i32, i16, i8);
+
+impl ToJStar for i64 {
+    /// Pushes the integer onto the J* stack as a `Number`.
+    ///
+    /// Integers with a magnitude greater than 2^53 are rounded to the nearest representable
+    /// [f64]. Use [VM::push_i64] to fail instead of losing precision.
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_number(*self as f64);
+    }
+}
+
+impl ToJStar for u64 {
+    /// Pushes the integer onto the J* stack as a `Number`.
+    ///
+    /// Integers greater than 2^53 are rounded to the nearest representable [f64]. Use
+    /// [VM::push_i64] to fail instead of losing precision.
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_number(*self as f64);
+    }
+}
+
+impl ToJStar for bool {
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_boolean(*self);
+    }
+}
+
+impl ToJStar for () {
+    /// Pushes `null` onto the J* stack.
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_null();
+    }
+}
+
+impl<T: ToJStar> ToJStar for Option<T> {
+    /// Pushes the contained value onto the J* stack, or `null` if the option is `None`.
+    fn to_jstar(&self, vm: &VM) {
+        match self {
+            Some(value) => value.to_jstar(vm),
+            None => vm.push_null(),
+        }
+    }
+}
+
+impl<T: ToJStar + ?Sized> ToJStar for &T {
+    fn to_jstar(&self, vm: &VM) {
+        (**self).to_jstar(vm);
+    }
+}
+
+impl<T: ToJStar + ?Sized> ToJStar for Box<T> {
+    fn to_jstar(&self, vm: &VM) {
+        (**self).to_jstar(vm);
+    }
+}
+
+impl ToJStar for Infallible {
+    /// [Infallible] values cannot exist, so this is never called. This allows to satisfy a
+    /// [ToJStar] bound in generic code, e.g. when pushing a `Result<T, Infallible>`.
+    fn to_jstar(&self, _: &VM) {
+        match *self {}
+    }
+}
 
 /// Trait used to get a value from the J* stack.
 /// Types that implement this trait usually have corresponding `get_...`, `is_...` and `check` methods in the [VM]
@@ -55,3 +263,244 @@ pub trait FromJStar<'vm>: Sized {
 }
 
 from_jstar_number_impl!(f64, f32, u64, u32, u16, u8, i64, i32, i16, i8);
+range_impl!(usize, u64, u32, u16, u8, isize, i64, i32, i16, i8);
+tuple_impl!(1; A 0);
+tuple_impl!(2; A 0, B 1);
+tuple_impl!(3; A 0, B 1, C 2);
+tuple_impl!(4; A 0, B 1, C 2, D 3);
+tuple_impl!(5; A 0, B 1, C 2, D 3, E 4);
+tuple_impl!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+tuple_impl!(7; A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+tuple_impl!(8; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+impl<'vm> FromJStar<'vm> for () {
+    /// Gets a `null` from the J* stack, as returned by functions that don't return anything.
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        vm.is_null(slot).then_some(())
+    }
+
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        vm.check_null(slot, name)
+    }
+}
+
+impl<'vm, T: FromJStar<'vm>> FromJStar<'vm> for Option<T> {
+    /// Gets an optional value from the J* stack, mapping `null` to `Some(None)`.
+    ///
+    /// Note that a value that is neither `null` nor a `T` also returns `None` (the outer
+    /// [Option]), which makes it indistinguishable from a missing value if the result is flattened.
+    /// Use [FromJStar::from_jstar_checked] to reject such values.
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        if vm.is_null(slot) {
+            Some(None)
+        } else {
+            T::from_jstar(vm, slot).map(Some)
+        }
+    }
+
+    /// Gets an optional value from the J* stack, mapping `null` to `None`.
+    /// Only `null` is treated as `None`: if the value is not a `T` this method returns an error
+    /// leaving a `TypeException` on top of the stack.
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        if vm.is_null(slot) {
+            Ok(None)
+        } else {
+            T::from_jstar_checked(vm, slot, name).map(Some)
+        }
+    }
+}
+
+impl<'vm> FromJStar<'vm> for bool {
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        vm.get_boolean(slot)
+    }
+
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        vm.check_boolean(slot, name)
+    }
+}
+
+/// A value computed lazily by a Rust closure, for injecting expensive values into J* code that
+/// may never use them.
+///
+/// When pushed, a [Lazy] becomes a J* function that runs the closure the first time it is called,
+/// and returns the cached value on subsequent calls. The closure must push exactly one value,
+/// or return `Err(`[`Error::Runtime`]`)` leaving an exception on top of the stack, in which case
+/// it is run again on the next call.
+///
+/// ```rust
+/// # use jstar::{conf::Conf, convert::{Lazy, ToJStar}, vm::VM, MAIN_MODULE};
+/// let vm = VM::new(Conf::new()).init_runtime();
+/// Lazy::new(|vm: &mut VM| {
+///     vm.push_number(42.0);
+///     Ok(())
+/// })
+/// .to_jstar(&vm);
+/// vm.set_global(MAIN_MODULE, "answer").unwrap();
+/// vm.eval("<string>", "std.assert(answer() == 42)").unwrap();
+/// ```
+///
+/// The closure is kept alive by the J* function, and dropped once the function is collected.
+pub struct Lazy<F> {
+    thunk: Rc<F>,
+}
+
+impl<F> Lazy<F> {
+    /// Constructs a new [Lazy] value computed by `thunk`.
+    pub fn new(thunk: F) -> Self {
+        Lazy {
+            thunk: Rc::new(thunk),
+        }
+    }
+}
+
+impl<F: Fn(&mut VM) -> Result<()> + 'static> ToJStar for Lazy<F> {
+    /// Pushes a J* function that computes the value on its first call.
+    ///
+    /// If the function can't be created, the raised exception is pushed in its place.
+    fn to_jstar(&self, vm: &VM) {
+        // On failure the exception is left on top of the stack, in place of the function
+        let _ = vm.push_lazy(self.thunk.clone());
+    }
+}
+
+impl<T: ToJStar> ToJStar for Vec<T> {
+    /// Pushes the [Vec] onto the J* stack as a `List`.
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_list_from(self);
+    }
+}
+
+impl<'vm, T: for<'v> FromJStar<'v>> FromJStar<'vm> for Vec<T> {
+    /// Reads a J* `List` into a [Vec].
+    /// Returns `None` if the value is not a `List` or if any of its elements is not of type `T`.
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        let len = vm.get_list_len(slot)?;
+        (0..len)
+            .map(|i| vm.with_list_elem(slot, i, |vm| T::from_jstar(vm, -1)))
+            .collect()
+    }
+
+    /// Reads a J* `List` into a [Vec].
+    /// If the value is not a `List` or if any of its elements is not of type `T`, this method
+    /// returns an error leaving a `TypeException` on top of the stack.
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        let Some(len) = vm.get_list_len(slot) else {
+            vm.raise("TypeException", &format!("{name} must be a List"));
+            return Err(Error::Runtime);
+        };
+        let mut vec = Vec::with_capacity(len);
+        for i in 0..len {
+            match vm.with_list_elem(slot, i, |vm| T::from_jstar(vm, -1)) {
+                Some(elem) => vec.push(elem),
+                None => {
+                    vm.raise(
+                        "TypeException",
+                        &format!("{name} element {i} has the wrong type"),
+                    );
+                    return Err(Error::Runtime);
+                }
+            }
+        }
+        Ok(vec)
+    }
+}
+
+impl<T: ToJStar> ToJStar for VecDeque<T> {
+    /// Pushes the [VecDeque] onto the J* stack as a `List`, from front to back.
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_list_from(self);
+    }
+}
+
+impl<'vm, T: for<'v> FromJStar<'v>> FromJStar<'vm> for VecDeque<T> {
+    /// Reads a J* `List` front to back into a [VecDeque].
+    /// Returns `None` if the value is not a `List` or if any of its elements is not of type `T`.
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        Vec::from_jstar(vm, slot).map(VecDeque::from)
+    }
+
+    /// Reads a J* `List` front to back into a [VecDeque].
+    /// If the value is not a `List` or if any of its elements is not of type `T`, this method
+    /// returns an error leaving a `TypeException` on top of the stack.
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        Vec::from_jstar_checked(vm, slot, name).map(VecDeque::from)
+    }
+}
+
+impl<K: ToJStar, V: ToJStar, S> ToJStar for HashMap<K, V, S> {
+    /// Pushes the [HashMap] onto the J* stack as a `Table`.
+    /// Keys can be of any type that J* can hash, not only strings. If one of them is not
+    /// hashable, the raised exception is pushed in place of the `Table`.
+    fn to_jstar(&self, vm: &VM) {
+        // On failure the exception is left on top of the stack, in place of the table
+        let _ = vm.push_table_from(self);
+    }
+}
+
+impl<'vm, K, V, S> FromJStar<'vm> for HashMap<K, V, S>
+where
+    K: for<'v> FromJStar<'v> + Hash + Eq,
+    V: for<'v> FromJStar<'v>,
+    S: BuildHasher + Default,
+{
+    /// Reads a J* `Table` into a [HashMap].
+    /// Returns `None` if the value is not a `Table` or if any of its keys or values is not of the
+    /// right type.
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        let mut map = HashMap::default();
+        vm.with_table_entries(slot, |vm| {
+            map.insert(K::from_jstar(vm, -2)?, V::from_jstar(vm, -1)?);
+            Some(())
+        })?;
+        Some(map)
+    }
+
+    /// Reads a J* `Table` into a [HashMap].
+    /// If the value is not a `Table` or if any of its keys or values is not of the right type,
+    /// this method returns an error leaving a `TypeException` on top of the stack.
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        Self::from_jstar(vm, slot).ok_or_else(|| {
+            vm.raise(
+                "TypeException",
+                &format!("{name} must be a Table with keys and values of the right type"),
+            );
+            Error::Runtime
+        })
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl ToJStar for uuid::Uuid {
+    /// Pushes the [uuid::Uuid] onto the J* stack as a `String` in its hyphenated form.
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_string(
+            self.hyphenated()
+                .encode_lower(&mut uuid::Uuid::encode_buffer()),
+        );
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl<'vm> FromJStar<'vm> for uuid::Uuid {
+    /// Parses a [uuid::Uuid] from a J* `String`.
+    /// Returns `None` if the value is not a `String` or if it is not a valid UUID.
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        let uuid = vm.get_string(slot)?;
+        uuid::Uuid::try_parse_ascii(uuid.as_bytes()).ok()
+    }
+
+    /// Parses a [uuid::Uuid] from a J* `String`.
+    /// If the value is not a `String`, this method returns an error leaving a `TypeException` on
+    /// top of the stack. If it is not a valid UUID, a `ValueException` is left instead.
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        let uuid = vm.check_string(slot, name)?;
+        uuid::Uuid::try_parse_ascii(uuid.as_bytes()).map_err(|err| {
+            vm.raise(
+                "ValueException",
+                &format!("{name} is not a valid UUID: {err}"),
+            );
+            Error::Runtime
+        })
+    }
+}
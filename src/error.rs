@@ -14,7 +14,14 @@ pub enum Error {
     /// An error was encountered while compiling code
     #[error("Error encountered while compiling code")]
     Compile,
-    /// An exception was thrown while executing code
+    /// An exception was thrown while executing code.
+    ///
+    /// The exception is either left on top of the stack or, for evaluations, reported to the
+    /// error callback. Its message can be recovered with [`VM::last_error`] or
+    /// [`VM::format_error`].
+    ///
+    /// [`VM::last_error`]: crate::vm::VM::last_error
+    /// [`VM::format_error`]: crate::vm::VM::format_error
     #[error("Exception was throw while executing code")]
     Runtime,
     /// An error was encountered while deserializing compiled code
@@ -23,9 +30,66 @@ pub enum Error {
     /// Compiled code version mismatch
     #[error("Compiled code version mismatch")]
     Version,
+    /// An error was encountered while processing the file at `path`
+    #[error("{source} (in `{path}`)")]
+    InFile {
+        /// The path of the file that caused the error
+        path: String,
+        /// The error encountered while processing the file
+        source: Box<Error>,
+    },
+    /// An error was encountered while evaluating the fragment at `index` (see [`VM::eval_each`])
+    ///
+    /// [`VM::eval_each`]: crate::vm::VM::eval_each
+    #[error("{source} (in fragment {index})")]
+    InFragment {
+        /// The index of the fragment that caused the error
+        index: usize,
+        /// The error encountered while evaluating the fragment
+        source: Box<Error>,
+    },
+    /// A string passed to the J* vm (e.g. a module or global name) contains a NUL byte
+    #[error("Invalid string: {0}")]
+    InvalidString(#[from] std::ffi::NulError),
+    /// An integer cannot be represented exactly as a J* `Number` (see [`VM::push_i64`])
+    ///
+    /// [`VM::push_i64`]: crate::vm::VM::push_i64
+    #[error("Integer {0} cannot be represented exactly as a Number")]
+    InexactNumber(i64),
     /// I/O error
-    #[error("I/O error: {0}")]
-    IO(#[from] std::io::Error)
+    #[error("I/O error{}: {source}", fmt_context(.context))]
+    IO {
+        /// A description of the operation that failed, if available
+        context: Option<String>,
+        /// The underlying I/O error
+        source: std::io::Error,
+    },
+}
+
+impl Error {
+    /// Construct a new [Error::IO] carrying a description of the operation that failed.
+    pub fn io_context(source: std::io::Error, context: impl Into<String>) -> Self {
+        Error::IO {
+            context: Some(context.into()),
+            source,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Error::IO {
+            context: None,
+            source,
+        }
+    }
+}
+
+fn fmt_context(context: &Option<String>) -> String {
+    context
+        .as_ref()
+        .map(|ctx| format!(" while {ctx}"))
+        .unwrap_or_default()
 }
 
 impl TryFrom<ffi::JStarResult> for Error {
@@ -41,3 +105,131 @@ impl TryFrom<ffi::JStarResult> for Error {
         }
     }
 }
+
+/// A J* exception that has been caught and handed back to Rust.
+///
+/// See [crate::vm::VM::eval_catching].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exception {
+    class: String,
+    message: String,
+    traceback: String,
+}
+
+impl Exception {
+    /// Parses an exception out of a J* formatted traceback, as the one passed to the error
+    /// callback when a runtime error occurs.
+    ///
+    /// Returns `None` if the traceback doesn't end with an `ExceptionClass: message` entry.
+    pub(crate) fn from_traceback(traceback: &str) -> Option<Self> {
+        // The traceback header and the stack frames come before the exception line. Frames are
+        // always indented, so the exception line is the first non-indented line after the header
+        let mut offset = 0;
+        for (i, line) in traceback.split_inclusive('\n').enumerate() {
+            let is_header = i == 0 && line.starts_with("Traceback");
+            if !is_header && !line.starts_with(char::is_whitespace) {
+                break;
+            }
+            offset += line.len();
+        }
+
+        let exception = traceback[offset..].trim_end();
+        let (class, message) = exception.split_once(": ").unwrap_or((exception, ""));
+        if class.is_empty() || !class.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+
+        Some(Exception {
+            class: class.to_owned(),
+            message: message.to_owned(),
+            traceback: traceback.to_owned(),
+        })
+    }
+
+    /// The name of the class of the exception (e.g. `TypeException`).
+    pub fn class(&self) -> &str {
+        &self.class
+    }
+
+    /// The error message the exception was raised with.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The full J* traceback of the exception.
+    pub fn traceback(&self) -> &str {
+        &self.traceback
+    }
+
+    /// Returns `true` if the exception's class is named `class`.
+    ///
+    /// Only the exact class is checked, superclasses are not taken into account.
+    pub fn is_class(&self, class: &str) -> bool {
+        self.class == class
+    }
+
+    /// Returns the [ExceptionKind] of the exception, or `None` if it is not one of the core J*
+    /// exception classes.
+    pub fn kind(&self) -> Option<ExceptionKind> {
+        ExceptionKind::from_class(&self.class)
+    }
+}
+
+macro_rules! exception_kinds {
+    ($($(#[$doc:meta])* $kind:ident),* $(,)?) => {
+        /// The exception classes defined in the J* core module.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum ExceptionKind {
+            $($(#[$doc])* $kind),*
+        }
+
+        impl ExceptionKind {
+            /// Returns the [ExceptionKind] corresponding to the core class named `class`, or
+            /// `None` if no core exception class has that name.
+            pub fn from_class(class: &str) -> Option<Self> {
+                match class {
+                    $(stringify!($kind) => Some(ExceptionKind::$kind),)*
+                    _ => None,
+                }
+            }
+
+            /// Returns the name of the J* class corresponding to this kind.
+            pub fn class(&self) -> &'static str {
+                match self {
+                    $(ExceptionKind::$kind => stringify!($kind),)*
+                }
+            }
+        }
+    };
+}
+
+exception_kinds!(
+    /// The base class of all exceptions
+    Exception,
+    /// A value has an unexpected type
+    TypeException,
+    /// A name (e.g. a global variable) couldn't be resolved
+    NameException,
+    /// A field couldn't be found
+    FieldException,
+    /// A method couldn't be found
+    MethodException,
+    /// A module couldn't be imported
+    ImportException,
+    /// The maximum recursion depth has been exceeded
+    StackOverflowException,
+    /// A syntax error has been encountered while evaluating code at runtime
+    SyntaxException,
+    /// A function has been passed an invalid argument
+    InvalidArgException,
+    /// An error occured while iterating
+    GenericIterException,
+    /// An index is out of bounds
+    IndexOutOfBoundException,
+    /// An assertion failed
+    AssertException,
+    /// A method or function is not implemented
+    NotImplementedException,
+    /// The program has been interrupted
+    ProgramInterrupt,
+);
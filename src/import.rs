@@ -1,6 +1,14 @@
-use crate::ffi;
+use crate::{conf::ImportCallback, error::Result, ffi, vm::VM};
 
-use std::ffi::CString;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::CString,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
 
 /// Represents an imported J* module.
 pub enum Module {
@@ -20,27 +28,208 @@ pub enum Module {
 
 impl Module {
     /// Construct a new [Module] with J* source code.
-    pub fn source(src: String, path: String) -> Self {
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Module)` in case of success.  
+    /// `Err(`[`Error::InvalidString`](crate::error::Error::InvalidString)`)` if `src` or `path`
+    /// contain a nul byte.
+    pub fn source(src: String, path: String) -> Result<Self> {
         Self::source_with_reg(src, path, std::ptr::null_mut())
     }
 
     /// Same as [source](#method.source) but with a native registry.
-    pub fn source_with_reg(src: String, path: String, reg: *mut ffi::JStarNativeReg) -> Self {
-        Module::Source {
-            src: CString::new(src).expect("Couldn't create a c compatible string from `src`"),
-            path: CString::new(path).expect("Couldn't create a c compatible string from `path`"),
+    pub fn source_with_reg(
+        src: String,
+        path: String,
+        reg: *mut ffi::JStarNativeReg,
+    ) -> Result<Self> {
+        Ok(Module::Source {
+            src: CString::new(src)?,
+            path: CString::new(path)?,
             reg,
-        }
+        })
     }
 
     /// Construct a new module with J* bytecode.
-    pub fn binary(code: Vec<u8>, path: String) -> Self {
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Module)` in case of success.  
+    /// `Err(`[`Error::InvalidString`](crate::error::Error::InvalidString)`)` if `path` contains a
+    /// nul byte.
+    pub fn binary(code: Vec<u8>, path: String) -> Result<Self> {
         Self::binary_with_reg(code, path, std::ptr::null_mut())
     }
 
     /// Same as [source](#method.binary) but with a native registry.
-    pub fn binary_with_reg(code: Vec<u8>, path: String, reg: *mut ffi::JStarNativeReg) -> Self {
-        let path = CString::new(path).expect("Couldn't create a c compatible string from `path`");
-        Module::Binary { code, path, reg }
+    pub fn binary_with_reg(
+        code: Vec<u8>,
+        path: String,
+        reg: *mut ffi::JStarNativeReg,
+    ) -> Result<Self> {
+        let path = CString::new(path)?;
+        Ok(Module::Binary { code, path, reg })
+    }
+}
+
+/// An import resolver that looks up J* modules on the filesystem.
+///
+/// A module named `a.b.c` is searched in each of the search paths, in order, as either:
+/// * `a/b/c.jsc` or `a/b/c.jsr` - a compiled or source module respectively
+/// * `a/b/c/__package__.jsc` or `a/b/c/__package__.jsr` - a compiled or source package
+///
+/// The search paths are shared between all the clones of a resolver, so they can be modified
+/// even after the resolver has been installed in a vm (see [FsResolver::add_path]):
+/// ```rust
+/// # use jstar::{conf::Conf, import::FsResolver, vm::VM};
+/// let resolver = FsResolver::new(["./modules"]);
+/// let conf = Conf::new().import_callback(resolver.clone().into_callback());
+/// let vm = VM::new(conf).init_runtime();
+///
+/// // Modules in `./plugins` can now be imported by `vm`
+/// resolver.add_path("./plugins");
+/// ```
+///
+/// Optionally, the resolver can cache the bytecode of the source modules it compiles, so that
+/// vms created later on with a clone of the same resolver don't need to recompile unchanged
+/// modules (see [FsResolver::with_cache]).
+#[derive(Debug, Clone, Default)]
+pub struct FsResolver {
+    paths: Rc<RefCell<Vec<PathBuf>>>,
+    cache: Option<Rc<RefCell<HashMap<PathBuf, CachedModule>>>>,
+}
+
+/// The bytecode of a source module, along with the modification time of the file it was
+/// compiled from.
+#[derive(Debug)]
+struct CachedModule {
+    mtime: SystemTime,
+    code: Vec<u8>,
+}
+
+impl FsResolver {
+    /// Construct a new [FsResolver] searching modules in `paths`.
+    pub fn new(paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        FsResolver {
+            paths: Rc::new(RefCell::new(paths.into_iter().map(Into::into).collect())),
+            cache: None,
+        }
+    }
+
+    /// Enables caching of the bytecode of source modules imported through this resolver.
+    ///
+    /// Source modules are compiled when first imported, and their bytecode is stored keyed by file
+    /// path and modification time. Subsequent imports of the same file, even from other vms, reuse
+    /// the cached bytecode as long as the file's modification time is unchanged. This is useful
+    /// when vms are re-created often, for example when reloading scripts in a development server.
+    ///
+    /// The cache is shared between all the clones of the returned resolver.
+    pub fn with_cache(mut self) -> Self {
+        self.cache = Some(Rc::default());
+        self
+    }
+
+    /// Appends `path` to the search paths of this resolver and of all of its clones.
+    pub fn add_path(&self, path: impl Into<PathBuf>) {
+        self.paths.borrow_mut().push(path.into());
+    }
+
+    /// Returns the current search paths.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.paths.borrow().clone()
+    }
+
+    /// Searches the module `module_name` in the search paths.
+    /// This method never consults the bytecode cache, as it has no vm to compile the module with.
+    ///
+    /// # Returns
+    ///
+    /// `Some(Module)` if the module was found, `None` otherwise.
+    pub fn resolve(&self, module_name: &str) -> Option<Module> {
+        self.find(module_name, Self::load)
+    }
+
+    /// Returns an [ImportCallback] that resolves modules using this resolver.
+    pub fn into_callback<'a>(self) -> ImportCallback<'a> {
+        Box::new(move |vm, module_name| match &self.cache {
+            Some(cache) => self.find(module_name, |candidate| {
+                Self::load_cached(vm, &mut cache.borrow_mut(), candidate)
+            }),
+            None => self.resolve(module_name),
+        })
+    }
+
+    fn find(
+        &self,
+        module_name: &str,
+        mut load: impl FnMut(&Path) -> Option<Module>,
+    ) -> Option<Module> {
+        let module_path: PathBuf = module_name.split('.').collect();
+        self.paths.borrow().iter().find_map(|path| {
+            let base = path.join(&module_path);
+            let package = base.join(PACKAGE_FILE);
+            [base, package].iter().find_map(|candidate| load(candidate))
+        })
+    }
+
+    fn load_cached(
+        vm: &mut VM,
+        cache: &mut HashMap<PathBuf, CachedModule>,
+        base: &Path,
+    ) -> Option<Module> {
+        // Compiled modules don't need caching, and files without an mtime can't be cached
+        if base.with_extension(COMPILED_EXT).is_file() {
+            return Self::load(base);
+        }
+        let source = base.with_extension(SOURCE_EXT);
+        let Ok(mtime) = fs::metadata(&source).and_then(|meta| meta.modified()) else {
+            return Self::load(base);
+        };
+
+        let path = source.to_string_lossy().into_owned();
+        if let Some(cached) = cache.get(&source).filter(|cached| cached.mtime == mtime) {
+            return Module::binary(cached.code.clone(), path).ok();
+        }
+
+        let src = fs::read_to_string(&source).ok()?;
+        // Errors are captured and discarded, as they are reported when the vm compiles the source
+        // again below
+        let (res, _) = vm.capture_errors(|vm| vm.compile_in_memory(&path, &src));
+        match res {
+            Ok(code) => {
+                cache.insert(
+                    source,
+                    CachedModule {
+                        mtime,
+                        code: code.clone(),
+                    },
+                );
+                Module::binary(code, path).ok()
+            }
+            // Let the vm compile the source again, so that the error is reported by the import
+            Err(_) => Module::source(src, path).ok(),
+        }
+    }
+
+    fn load(base: &Path) -> Option<Module> {
+        let compiled = base.with_extension(COMPILED_EXT);
+        if let Ok(code) = fs::read(&compiled) {
+            return Module::binary(code, compiled.to_string_lossy().into_owned()).ok();
+        }
+
+        let source = base.with_extension(SOURCE_EXT);
+        if let Ok(src) = fs::read_to_string(&source) {
+            return Module::source(src, source.to_string_lossy().into_owned()).ok();
+        }
+
+        None
     }
 }
+
+/// Name of the file (sans extension) that contains the code of a package.
+const PACKAGE_FILE: &str = "__package__";
+/// Extension of J* source files.
+const SOURCE_EXT: &str = "jsr";
+/// Extension of J* compiled files.
+const COMPILED_EXT: &str = "jsc";
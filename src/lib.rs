@@ -3,6 +3,9 @@
 /// FFI bindings to the J* C API.
 pub use jstar_sys as ffi;
 
+/// A byte buffer managed by the J* allocator.
+pub mod buffer;
+
 /// Configuration options for the J* VM.
 pub mod conf;
 
@@ -18,9 +21,16 @@ pub mod import;
 /// Macros for defining native functions.
 pub mod native;
 
+/// Re-exports of the most commonly used items, so that `use jstar::prelude::*;` is enough to
+/// embed the VM and write natives.
+pub mod prelude;
+
 /// The J* String type.
 pub mod string;
 
+/// Owned snapshots of J* values.
+pub mod value;
+
 /// Methods and types for interacting with the J* VM. This is the main entry point for the library.
 pub mod vm;
 
@@ -40,3 +50,17 @@ pub const CORE_MODULE: &str = JSR_CORE_MODULE;
 ///
 /// It is notably used by [vm::VM::eval] when evaluating a script.
 pub const MAIN_MODULE: &str = JSR_MAIN_MODULE;
+
+/// Returns whether two compiled J* modules are semantically equivalent.
+///
+/// The J* compiler is deterministic and its output embeds no timestamps or other build-dependent
+/// data, so this boils down to a byte comparison. Build systems should nonetheless go through this
+/// function rather than comparing the bytes themselves, so that they keep working if the bytecode
+/// format ever grows such data.
+///
+/// # Arguments
+///
+/// * `a`, `b` - The compiled modules to compare, as returned by [vm::VM::compile_in_memory].
+pub fn bytecode_equal(a: &[u8], b: &[u8]) -> bool {
+    a == b
+}
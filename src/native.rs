@@ -8,6 +8,8 @@ pub const MIN_NATIVE_STACK_SZ: usize = crate::ffi::JSTAR_MIN_NATIVE_STACK_SZ;
 ///
 /// The function takes in a `&mut `[`crate::vm::VM`] as its only argument and must return a
 /// [`Result`] where the [Ok] variant is `()` and the [Err] variant is [`crate::error::Error`].
+/// Returning [`crate::error::Error::Runtime`] signals the exception left on top of the stack,
+/// while any other error is raised as a J* `Exception` carrying the error's message.
 ///
 /// # Example
 ///
@@ -43,21 +45,91 @@ macro_rules! native {
             let func = |$arg: &mut $crate::vm::VM| -> $crate::error::Result<()> { $b };
             let res = func($arg);
             match res {
-                Err(_) => false,
+                Err($crate::error::Error::Runtime) => false,
+                // Other errors don't leave an exception on the stack, so one is raised here
+                Err(err) => {
+                    vm.raise("Exception", &err.to_string());
+                    false
+                }
                 Ok(()) => true,
             }
         }
     };
 }
 
+/// Macro to define a native function that reads its arguments and returns its result directly.
+///
+/// Each argument is converted from the J* stack using [`crate::convert::FromJStar`], and is named
+/// after the argument in error messages. The block must evaluate to a [`Result`] whose [Ok] value
+/// implements [`crate::convert::ToJStar`], and that is automatically pushed as the function's
+/// return value. Just like with [native], an [Err] must be returned with an exception on top of
+/// the stack (for example by using [raise]).
+///
+/// The `vm` argument is still available in the block, as a `&`[`crate::vm::VM`] so that arguments
+/// borrowing from the VM (such as [`crate::string::String`]) can be used alongside it. Use
+/// [native] if the function needs mutable access to the VM.
+///
+/// # Example
+///
+/// ```
+/// # use jstar::{native_ret, raise, vm::VM, conf::Conf, MAIN_MODULE};
+/// # let mut vm = VM::new(Conf::new()).init_runtime();
+/// native_ret!(fn rustDiv(vm, a: i32, b: i32) -> i32 {
+///     if b == 0 {
+///         raise!(vm, "InvalidArgException", "Division by zero");
+///     }
+///     Ok(a / b)
+/// });
+/// # vm.register_native(MAIN_MODULE, "rustDiv", rustDiv, 2).unwrap();
+/// # vm.eval("<string>", "std.assert(rustDiv(7, 2) == 3)").unwrap();
+/// ```
+#[macro_export]
+macro_rules! native_ret {
+    ($v:vis fn $name:ident($vm:ident $(, $arg:ident: $t:ty)* $(,)?) -> $ret:ty $b:block) => {
+        $crate::native!($v fn $name($vm) {
+            #[allow(unused_mut, unused_variables)]
+            let mut slot: $crate::vm::Index = 0;
+            $(
+                slot += 1;
+                let $arg = <$t as $crate::convert::FromJStar>::from_jstar_checked(
+                    $vm,
+                    slot,
+                    stringify!($arg),
+                )?;
+            )*
+            let func = |#[allow(unused_variables)] $vm: &$crate::vm::VM|
+                -> $crate::error::Result<$ret> { $b };
+            let res = func(&*$vm)?;
+            $crate::convert::ToJStar::to_jstar(&res, $vm);
+            Ok(())
+        });
+    };
+}
+
 /// Macro to raise an exception in a native function and return an error.
 ///
 /// This macro is useful when you want to raise an exception in a native function and return an
-/// error. It takes in a `&mut `[`crate::vm::VM`], a class name and a message.
+/// error. It takes in a `&mut `[`crate::vm::VM`], a class name and a message. A literal message
+/// is a format string, optionally followed by its arguments, as in [format]:
+///
+/// ```
+/// # use jstar::{native, raise};
+/// native!(fn checkPositive(vm) {
+///     let n = vm.check_number(1, "n")?;
+///     if n < 0.0 {
+///         raise!(vm, "InvalidArgException", "expected a positive number, got {n}");
+///     }
+///     Ok(())
+/// });
+/// ```
 #[macro_export]
 macro_rules! raise {
+    ($vm:expr, $cls:expr, $fmt:literal $(, $($arg:tt)+)?) => {
+        $vm.raise($cls, &format!($fmt $(, $($arg)+)?));
+        return Err($crate::error::Error::Runtime)
+    };
     ($vm:expr, $cls:expr, $msg:expr) => {
-        $vm.raise($cls, $msg);
+        $vm.raise($cls, &format!("{}", $msg));
         return Err($crate::error::Error::Runtime)
     };
 }
@@ -0,0 +1,30 @@
+//! # Example
+//!
+//! ```
+//! use jstar::prelude::*;
+//!
+//! native!(fn rustAdd(vm) {
+//!     let a = i32::from_jstar_checked(vm, 1, "a")?;
+//!     let b = i32::from_jstar_checked(vm, 2, "b")?;
+//!     (a + b).to_jstar(vm);
+//!     Ok(())
+//! });
+//!
+//! fn main() -> Result<()> {
+//!     let vm = VM::new(Conf::new()).init_runtime();
+//!     vm.register_native(MAIN_MODULE, "rustAdd", rustAdd, 2)?;
+//!     vm.eval("<string>", "var res = rustAdd(1, 2)")?;
+//!     vm.get_global(MAIN_MODULE, "res")?;
+//!     assert_eq!(vm.get_top().get::<i32>(), Some(3));
+//!     Ok(())
+//! }
+//! ```
+
+pub use crate::conf::Conf;
+pub use crate::convert::{FromJStar, ToJStar};
+pub use crate::error::{Error, Result};
+pub use crate::string::String as JStarString;
+pub use crate::value::Value;
+pub use crate::vm::{Index, VM};
+pub use crate::{native, native_ret, raise};
+pub use crate::{CORE_MODULE, MAIN_MODULE};
@@ -1,6 +1,9 @@
-use crate::{convert::{FromJStar, ToJStar}, vm::{Index, VM}};
+use crate::{
+    convert::{FromJStar, ToJStar},
+    vm::{Index, VM},
+};
 
-use std::{ffi::c_char, hash::Hash, marker::PhantomData};
+use std::{borrow::Cow, ffi::c_char, hash::Hash, marker::PhantomData};
 
 /// [String] represents a J* `String`.
 ///
@@ -52,15 +55,18 @@ use std::{ffi::c_char, hash::Hash, marker::PhantomData};
 pub struct String<'vm> {
     data: *const c_char,
     len: usize,
+    index: Index,
     phantom: PhantomData<&'vm VM<'vm>>,
 }
 
 impl<'vm> String<'vm> {
-    /// Construct a new [String] starting from a pointer and a length to a J* `String`.
-    pub(crate) fn new(data: *const c_char, len: usize) -> Self {
+    /// Construct a new [String] starting from a pointer and a length to a J* `String`, and the
+    /// absolute stack index it was read from.
+    pub(crate) fn new(data: *const c_char, len: usize, index: Index) -> Self {
         String {
             data,
             len,
+            index,
             phantom: PhantomData,
         }
     }
@@ -73,6 +79,15 @@ impl<'vm> String<'vm> {
         std::str::from_utf8(self.as_bytes())
     }
 
+    /// Converts this J* string into a Rust string, replacing any invalid utf8 sequence with
+    /// [U+FFFD REPLACEMENT CHARACTER](std::char::REPLACEMENT_CHARACTER).
+    ///
+    /// This mirrors [std::ffi::OsStr::to_string_lossy], and is useful for logging J* strings that
+    /// may contain binary data. No allocation is performed if the string is valid utf8.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        std::string::String::from_utf8_lossy(self.as_bytes())
+    }
+
     /// Convers this [String] to a byte slice.
     pub fn as_bytes(&self) -> &[u8] {
         // SAFETY: We know the vm is still valid (`self.phantom` lifetime). Also, as we have an
@@ -82,35 +97,63 @@ impl<'vm> String<'vm> {
     }
 }
 
-impl ToJStar for &str {
-    /// Pushes a Rust `&str` onto the J* stack. See also [VM::push_string].
+impl ToJStar for str {
+    /// Pushes a Rust `str` onto the J* stack. See also [VM::push_string].
     fn to_jstar(&self, vm: &VM) {
         vm.push_string(self);
     }
 }
 
-impl ToJStar for &[u8] {
-    /// Pushes a Rust `&[u8]` onto the J* stack. See also [VM::push_string].
+impl ToJStar for [u8] {
+    /// Pushes a Rust `[u8]` onto the J* stack. See also [VM::push_string].
     fn to_jstar(&self, vm: &VM) {
         vm.push_string(self);
     }
 }
 
+impl ToJStar for std::string::String {
+    /// Pushes a Rust [std::string::String] onto the J* stack. See also [VM::push_string].
+    fn to_jstar(&self, vm: &VM) {
+        vm.push_string(self);
+    }
+}
+
+impl<'vm> FromJStar<'vm> for std::string::String {
+    /// Copies a J* `String` into an owned Rust [std::string::String].
+    /// Returns `None` if the value is not a `String` or if it is not valid utf8.
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        vm.get_string(slot)?.try_into().ok()
+    }
+
+    /// Copies a J* `String` into an owned Rust [std::string::String].
+    /// If the value is not a `String` this method leaves a `TypeException` on top of the stack,
+    /// if it is not valid utf8 it leaves an `InvalidArgException`.
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> crate::error::Result<Self> {
+        vm.check_string(slot, name)?.try_into().map_err(|err| {
+            vm.raise(
+                "InvalidArgException",
+                &format!("{name} is not valid utf8: {err}"),
+            );
+            crate::error::Error::Runtime
+        })
+    }
+}
+
 impl<'vm> ToJStar for String<'vm> {
     /// Pushes this J* [String] onto the stack.  
     /// As the `String` is already owned by the VM, this method can skip a roundtrip through the
     /// J* stack and Rust, and directly push onto the J* stack, without copying the data.
     /// Also see [VM::push_value].
+    ///
+    /// If `vm` is not the [VM] the string was read from, the data is copied instead.
     fn to_jstar(&self, vm: &VM) {
-        // TODO: welp, need to implement dup as documented above. Copying here is pretty stupid.
-        vm.push_string(self.as_ref());
-    }
-}
-
-impl<'vm> ToJStar for &String<'vm> {
-    /// See [impl<'vm> ToJStar for String<'vm>](./struct.String.html#impl-ToJStar-for-String<'vm>)
-    fn to_jstar(&self, vm: &VM) {
-        (*self).to_jstar(vm);
+        let same_vm = vm.validate_slot(self.index)
+            && vm.get_bytes(self.index).map(<[u8]>::as_ptr) == Some(self.data as *const u8);
+        if same_vm {
+            vm.push_value(self.index);
+        } else {
+            vm.push_string(self.as_ref());
+        }
     }
 }
 
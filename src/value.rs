@@ -0,0 +1,120 @@
+use crate::{
+    convert::{FromJStar, ToJStar},
+    error::{Error, Result},
+    vm::{Index, VM},
+};
+
+/// [Value] is an owned snapshot of a J* value.
+///
+/// Differently from the other J* types exposed by this library (such as
+/// [String](crate::string::String)), a [Value] doesn't refer to the [VM] stack, so it can be
+/// freely stored, moved around and inspected after the original value has been popped. This comes
+/// at the cost of copying the whole value, including all nested elements of collections.
+///
+/// Only plain data can be represented as a [Value]: `null`, `Boolean`s, `Number`s, `String`s and
+/// `List`s, `Tuple`s and `Table`s of those. Reading any other kind of value (functions, class
+/// instances, ...) fails.
+///
+/// As a [Value] owns all of its elements, it is always a tree and can't contain cycles or shared
+/// references. This means that pushing a [Value] with [VM::push_value_owned] always terminates,
+/// creating a fresh J* object for every nested collection. Cyclic J* structures, on the other
+/// hand, can't be represented and reading them fails (see [VM::get_value_owned]).
+///
+/// # Example
+///
+/// ```
+/// # use jstar::{conf::Conf, value::Value, vm::VM, convert::FromJStar, MAIN_MODULE};
+/// # let mut vm = VM::new(Conf::new()).init_runtime();
+/// vm.eval("<string>", "var list = [1, 'two', (3,)]").unwrap();
+/// vm.get_global(MAIN_MODULE, "list").unwrap();
+///
+/// let list = Value::from_jstar(&vm, -1).unwrap();
+/// vm.pop();
+///
+/// for elem in list {
+///     println!("{elem:?}");
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// The J* `null` value.
+    Null,
+    /// A J* `Boolean`.
+    Bool(bool),
+    /// A J* `Number`.
+    Number(f64),
+    /// A J* `String`. As J* `String`s can store arbitrary data, they are represented as bytes.
+    String(Vec<u8>),
+    /// A J* `List`.
+    List(Vec<Value>),
+    /// A J* `Tuple`.
+    Tuple(Vec<Value>),
+    /// A J* `Table`, represented as a list of `(key, value)` pairs in iteration order.
+    Table(Vec<(Value, Value)>),
+}
+
+impl Value {
+    /// Returns the elements of this value if it is a [Value::List], `None` otherwise.
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(elems) => Some(elems),
+            _ => None,
+        }
+    }
+
+    /// Returns the entries of this value if it is a [Value::Table], `None` otherwise.
+    pub fn as_table(&self) -> Option<&[(Value, Value)]> {
+        match self {
+            Value::Table(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// Returns this value as a `&str` if it is a [Value::String] containing valid utf8, `None`
+    /// otherwise.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl IntoIterator for Value {
+    type Item = Value;
+    type IntoIter = std::vec::IntoIter<Value>;
+
+    /// Iterates over the elements of a [Value::List] or [Value::Tuple]. Any other value yields no
+    /// elements.
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            Value::List(elems) | Value::Tuple(elems) => elems.into_iter(),
+            _ => Vec::new().into_iter(),
+        }
+    }
+}
+
+impl ToJStar for Value {
+    /// Pushes the [Value] onto the J* stack. If a nested `Table` can't be built, the raised
+    /// exception is pushed in its place (see [VM::push_value_owned]).
+    fn to_jstar(&self, vm: &VM) {
+        // On failure the exception is left on top of the stack, in place of the value
+        let _ = vm.push_value_owned(self);
+    }
+}
+
+impl<'vm> FromJStar<'vm> for Value {
+    fn from_jstar(vm: &'vm VM, slot: Index) -> Option<Self> {
+        vm.get_value_owned(slot)
+    }
+
+    fn from_jstar_checked(vm: &'vm VM, slot: Index, name: &str) -> Result<Self> {
+        vm.get_value_owned(slot).ok_or_else(|| {
+            vm.raise(
+                "TypeException",
+                &format!("{name} must be null, a Boolean, Number, String, List, Tuple or Table"),
+            );
+            Error::Runtime
+        })
+    }
+}
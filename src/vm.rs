@@ -1,19 +1,91 @@
+use crate::buffer::Buffer;
 use crate::conf::Conf;
 use crate::conf::ErrorCallback;
 use crate::conf::ImportCallback;
+use crate::conf::ImportObserver;
 use crate::convert::FromJStar;
+use crate::convert::ToJStar;
 use crate::error::Error;
+use crate::error::Exception;
 use crate::error::Result;
 use crate::ffi;
-use crate::import::Module;
+use crate::import::{FsResolver, Module};
 use crate::string::String as JStarString;
+use crate::value::Value;
+use crate::{CORE_MODULE, MAIN_MODULE};
 
+use std::any::TypeId;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
 use std::os::raw::{c_char, c_int, c_void};
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::slice::from_raw_parts;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Hidden module holding the J* helper functions used by the bindings.
+const RUST_MODULE: &str = "__rust__";
+
+/// J* source of the exceptions raised by the bindings that J* doesn't define, evaluated in the
+/// core module by [VM::init_runtime] so that they are visible everywhere.
+const CORE_EXCEPTIONS_SRC: &str = r#"
+class ValueException is Exception end
+"#;
+
+/// J* source of the helper functions defined in [RUST_MODULE].
+const RUST_MODULE_SRC: &str = r#"
+fun deepEquals(a, b, depth)
+    if depth > 256
+        raise InvalidArgException("Maximum depth exceeded, the values may contain a cycle")
+    end
+    if (a is List and b is List) or (a is Tuple and b is Tuple)
+        if #a != #b
+            return false
+        end
+        for var i = 0; i < #a; i += 1
+            if !deepEquals(a[i], b[i], depth + 1)
+                return false
+            end
+        end
+        return true
+    end
+    if a is Table and b is Table
+        if #a != #b
+            return false
+        end
+        for var k in a
+            if !b.contains(k) or !deepEquals(a[k], b[k], depth + 1)
+                return false
+            end
+        end
+        return true
+    end
+    return a == b
+end
+
+fun makeLazy(thunk)
+    var computed, value = false, null
+    fun lazy()
+        if !computed
+            value = runThunk(thunk)
+            computed = true
+        end
+        return value
+    end
+    return lazy
+end
+"#;
+
+/// Maximum nesting depth of collections read by [VM::get_value_owned], used to bail out of cyclic
+/// structures.
+const MAX_VALUE_DEPTH: usize = 256;
 
 /// Type representing an offset into the J* stack.
 /// If positive it represents a position from the start of the stack, if negative from its end.
@@ -29,6 +101,19 @@ pub type Index = c_int;
 /// performance critical sections.
 pub struct Uninit;
 
+/// An uninitialized [VM], as returned by [VM::new].
+///
+/// This alias provides a name for vms that haven't been initialized yet, e.g. to store them in
+/// struct fields or to pass them to functions that only compile code:
+/// ```rust
+/// # use jstar::{conf::Conf, vm::NewVM};
+/// let vm = NewVM::new(Conf::new());
+/// let code = vm.compile_in_memory("<string>", "var x = 1").unwrap();
+/// let vm = vm.init_runtime();
+/// vm.eval("<string>", &code).unwrap();
+/// ```
+pub type NewVM<'a> = VM<'a, Uninit>;
+
 /// Marker struct that represents a fully initialized J* vm.
 ///
 /// Capable of executing J* code, as well as performing any operations an [Uninit] vm can.
@@ -160,10 +245,18 @@ impl<'a> VM<'a, Uninit> {
         let mut trampolines = Box::new(Trampolines {
             error_callback: conf.error_callback,
             import_callback: conf.import_callback,
+            import_observer: conf.import_observer,
+            captured_errors: None,
+            last_error: None,
+            frozen_modules: HashSet::new(),
+            native_regs: HashMap::new(),
+            exprs: Rc::new(RefCell::new(RootedGlobals::new(MAIN_MODULE, "__expr"))),
+            handlers: Rc::new(RefCell::new(RootedGlobals::new(RUST_MODULE, "handler"))),
+            userdata: Rc::default(),
         });
 
         let conf = ffi::JStarConf {
-            heap_grow_rate: conf.heap_grow_rate,
+            heap_grow_rate: conf.heap_grow_rate.as_factor(),
             first_gc_collection_point: conf.first_gc_collection_point,
             starting_stack_sz: conf.starting_stack_sz,
             error_callback: error_trampoline,
@@ -184,20 +277,44 @@ impl<'a> VM<'a, Uninit> {
     /// Initializes the J* runtime.
     ///
     /// After calliing this method the returned [VM] will be capable of executing J* code.
+    ///
+    /// Besides the J* builtins, the core module also defines `ValueException`, raised by the
+    /// bindings when a value has the right type but is malformed (e.g. a `String` that is not a
+    /// valid UUID).
     pub fn init_runtime(mut self) -> VM<'a, Init> {
         // SAFETY: `self.vm` is a valid pointer
         unsafe { ffi::jsrInitRuntime(self.vm) };
-        VM {
+        let vm = VM {
             vm: self.vm,
             ownership: std::mem::replace(&mut self.ownership, VMOwnership::NonOwned),
             state: PhantomData,
-        }
+        };
+        vm.eval_in_module(CORE_MODULE, CORE_MODULE, CORE_EXCEPTIONS_SRC)
+            .expect("core exceptions to be evaluated successfully");
+        vm
     }
 }
 
 /// Methods available only when the [`VM`] is in an [Init]ialized state, i.e. [`VM::init_runtime`]
 /// has been called.
 impl<'a> VM<'a, Init> {
+    /// Constructs a new J* vm that resolves imports on the filesystem, searching modules in
+    /// `paths`, and initializes its runtime.
+    ///
+    /// This is a shorthand for installing a [FsResolver] as the import callback of `conf` (see
+    /// [Conf::import_callback]), replacing any callback already set:
+    /// ```rust
+    /// # use jstar::{conf::Conf, import::FsResolver, vm::VM};
+    /// let vm = VM::with_fs_imports(Conf::new(), &["./modules".into()]);
+    /// // is equivalent to
+    /// let resolver = FsResolver::new(["./modules"]);
+    /// let vm = VM::new(Conf::new().import_callback(resolver.into_callback())).init_runtime();
+    /// ```
+    pub fn with_fs_imports(conf: Conf<'a>, paths: &[PathBuf]) -> Self {
+        let resolver = FsResolver::new(paths.iter().cloned());
+        VM::new(conf.import_callback(resolver.into_callback())).init_runtime()
+    }
+
     /// Construct a new [VM] wrapper starting from a raw [ffi::JStarVM] pointer.
     ///
     /// Its main use is to construct a `VM` wrapper struct across ffi boundaries when only a
@@ -217,22 +334,52 @@ impl<'a> VM<'a, Init> {
         }
     }
 
+    /// Sets the command line arguments exposed to J* scripts through `sys.args`.
+    ///
+    /// The arguments are copied into J* `String`s, so they don't need to outlive this call.
+    /// Calling this method again replaces the previous arguments.
+    ///
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// let vm = VM::new(Conf::new()).init_runtime();
+    /// vm.init_command_line_args(&std::env::args().skip(1).collect::<Vec<_>>())
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the arguments have been set, `Err(`[`Error::InvalidString`]`)` if one of them
+    /// contains a NUL byte.
+    pub fn init_command_line_args(&self, args: &[impl AsRef<str>]) -> Result<()> {
+        let args = args
+            .iter()
+            .map(|arg| CString::new(arg.as_ref()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let mut argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let argc = c_int::try_from(argv.len()).expect("too many command line arguments");
+        // SAFETY: `self.vm` is a valid J* vm pointer, and `argv` holds `argc` valid cstrings that
+        // are alive for the duration of the call. J* copies them, so they can be freed afterwards
+        unsafe { ffi::jsrInitCommandLineArgs(self.vm, argc, argv.as_mut_ptr()) };
+        Ok(())
+    }
+
     /// Evaluate J* source or compiled code in the context of the `__main__` module.
     ///
     /// # Arguments
     ///
     /// * `path` - A string representing the code path. It doesn't have to be a real filesystem
-    ///    path, as it is only used during error callbacks to provide useful context to the client
-    ///    handling the error. Nonetheless, if the source code has been indeed read from a file, it
-    ///    is reccomended to pass its path to this function.
+    ///   path, as it is only used during error callbacks to provide useful context to the client
+    ///   handling the error. Nonetheless, if the source code has been indeed read from a file, it
+    ///   is reccomended to pass its path to this function.
     ///
     /// * `code` - The J* source or compiled code to evaluate.
     ///
     /// # Returns
     ///
-    /// `Ok(())` if the evaluation succeded, `Err(`[`Error::Runtime`]`)` otherwise.
+    /// `Ok(())` if the evaluation succeded, `Err(`[`Error::Runtime`]`)` otherwise.  
+    /// `Err(`[`Error::InvalidString`]`)` if `path` contains a NUL byte.
     pub fn eval(&self, path: &str, code: impl AsRef<[u8]>) -> Result<()> {
-        let path = CString::new(path).expect("Couldn't create CString");
+        let path = CString::new(path)?;
         let code = code.as_ref();
         let res = unsafe {
             ffi::jsrEval(
@@ -249,27 +396,50 @@ impl<'a> VM<'a, Init> {
         }
     }
 
+    /// Evaluates a sequence of J* source fragments in the context of the `__main__` module,
+    /// stopping at the first one that fails.
+    ///
+    /// As all fragments share the same module, globals defined by a fragment are visible to the
+    /// ones that follow, making this useful for notebook-style incremental execution. The `i`th
+    /// fragment is evaluated with `<fragment i>` as its path.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if all fragments were evaluated successfully, `Err(`[`Error::InFragment`]`)`
+    /// wrapping the error and the index of the first fragment that failed otherwise.
+    pub fn eval_each(&self, fragments: &[&str]) -> Result<()> {
+        for (index, fragment) in fragments.iter().enumerate() {
+            self.eval(&format!("<fragment {index}>"), fragment)
+                .map_err(|err| Error::InFragment {
+                    index,
+                    source: Box::new(err),
+                })?;
+        }
+        Ok(())
+    }
+
     /// Similar to [VM::eval] but it evaluates the code in the context of `module` instead of the
     /// main module.
     ///
     /// # Arguments
     ///
     /// * `path` - A string representing the code path. It doesn't have to be a real filesystem
-    ///    path, as it is only used during error callbacks to provide useful context to the client
-    ///    handling the error. Nonetheless, if the source code has been indeed read from a file, it
-    ///    is reccomended to pass its path to this function.
+    ///   path, as it is only used during error callbacks to provide useful context to the client
+    ///   handling the error. Nonetheless, if the source code has been indeed read from a file, it
+    ///   is reccomended to pass its path to this function.
     ///
     /// * `module` - The name of the module in which to evaluate the code. Can be any valid J*
-    ///    module name or [CORE_MODULE](../constant.CORE_MODULE.html)/[MAIN_MODULE](../constant.MAIN_MODULE.html)
+    ///   module name or [CORE_MODULE](../constant.CORE_MODULE.html)/[MAIN_MODULE](../constant.MAIN_MODULE.html)
     ///
     /// * `code` - The J* source or compiled code to evaluate.
     ///
     /// # Returns
     ///
-    /// `Ok(())` if the evaluation succeded, `Err(`[`Error::Runtime`]`)` otherwise.
+    /// `Ok(())` if the evaluation succeded, `Err(`[`Error::Runtime`]`)` otherwise.  
+    /// `Err(`[`Error::InvalidString`]`)` if `path` or `module` contain a NUL byte.
     pub fn eval_in_module(&self, path: &str, module: &str, code: impl AsRef<[u8]>) -> Result<()> {
-        let path = CString::new(path).expect("Couldn't create CString");
-        let module = CString::new(module).expect("Couldn't create CString");
+        let path = CString::new(path)?;
+        let module = CString::new(module)?;
         let res = unsafe {
             ffi::jsrEvalModule(
                 self.vm,
@@ -286,445 +456,4055 @@ impl<'a> VM<'a, Init> {
         }
     }
 
-    /// Call the value at slot `-(argc - 1)` with the arguments from `-argc..$top`.
+    /// Evaluate J* source or compiled code in the context of the `__main__` module, catching
+    /// exceptions of the given classes.
     ///
-    /// # Returns
+    /// This mirrors a J* `try/except` block around the evaluated code: if the evaluation raises an
+    /// exception whose class name is contained in `classes`, the exception is handed back to Rust
+    /// instead of being reported to the error callback. Any other error is reported as usual.
     ///
-    /// `Ok(())` if the call succeded leaving the result on top of the stack, `Err(`[`Error::Runtime`]`)`
-    /// if the the call failed leaving an Exception on top of the stack. In both cases, the args
-    /// and the callee are popped from the stack.
+    /// Keep in mind that classes are matched by name only, so subclasses of the classes in
+    /// `classes` are not caught.
     ///
-    /// # Errors
+    /// # Arguments
     ///
-    /// This function panics if the stack underflows or overflows the stack (for the current stack
-    /// frame).
-    pub fn call(&mut self, argc: u8) -> Result<()> {
-        assert!(self.validate_slot(-(argc as i32 + 1)));
-        // SAFETY: `self.vm` is a valid pointer
-        let res = unsafe { ffi::jsrCall(self.vm, argc) };
-        if let Ok(err) = res.try_into() {
-            Err(err)
-        } else {
-            Ok(())
+    /// * `path` - A string representing the code path. See [VM::eval].
+    ///
+    /// * `code` - The J* source or compiled code to evaluate.
+    ///
+    /// * `classes` - The names of the exception classes to catch.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if the evaluation succeded, `Ok(Some(`[`Exception`]`))` if the evaluation raised
+    /// an exception of one of the classes in `classes`, `Err(`[`Error`]`)` otherwise.
+    pub fn eval_catching(
+        &mut self,
+        path: &str,
+        code: impl AsRef<[u8]>,
+        classes: &[&str],
+    ) -> Result<Option<Exception>> {
+        let (res, mut errors) = self.capture_errors(|vm| vm.eval(path, code));
+        match res {
+            Ok(()) => Ok(None),
+            Err(Error::Runtime) => {
+                let exception = errors
+                    .last()
+                    .filter(|e| matches!(e.error, Error::Runtime))
+                    .and_then(|e| Exception::from_traceback(&e.message))
+                    .filter(|exc| classes.contains(&exc.class()));
+
+                if let Some(exception) = exception {
+                    errors.pop();
+                    self.report_errors(errors);
+                    Ok(Some(exception))
+                } else {
+                    self.report_errors(errors);
+                    Err(Error::Runtime)
+                }
+            }
+            Err(err) => {
+                self.report_errors(errors);
+                Err(err)
+            }
         }
     }
 
-    /// Pops one element from the VM stack.
+    /// Returns the last error reported by J*, along with its location and formatted message.
     ///
-    /// # Errors
+    /// This is the same information passed to the error callback (see [Conf::error_callback]),
+    /// and allows code that doesn't install a callback to inspect why an evaluation or a
+    /// compilation failed. For [Error::Runtime] errors the message is the exception's traceback.
     ///
-    /// This method panics if we try to pop more items than the stack holds (for the current stack
-    /// frame).
-    pub fn pop(&mut self) {
-        assert!(self.validate_slot(-1), "VM stack underflow");
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrPop(self.vm) };
+    /// Note that only errors reported through the error callback are recorded: exceptions raised
+    /// by [VM::call] are left on the stack instead (see [VM::format_error]).
+    pub fn last_error(&self) -> Option<&CapturedError> {
+        self.trampolines().last_error.as_ref()
     }
 
-    /// Pops `n` elements from the VM stack
+    /// Renders `err` as a human readable message, suitable for user-facing output.
     ///
-    /// # Errors
+    /// Errors carry no context on their own, so this method recovers it from the [VM]:
+    /// * For [Error::Runtime], the traceback of the exception on top of the stack if there is one,
+    ///   otherwise the traceback of the last runtime error reported by J*.
+    /// * For the other errors reported by J* (e.g. [Error::Syntax] or [Error::Compile]), the
+    ///   location and message of the last error of the same kind.
+    /// * For [Error::InFile] and [Error::InFragment], the rendering of the wrapped error followed
+    ///   by the file path or fragment index.
     ///
-    /// This method panics if we try to pop more items than the stack holds (for the current stack
-    /// frame).
-    pub fn pop_n(&mut self, n: i32) {
-        assert!(n > 0, "`n` must be greater than 0");
-        assert!(self.validate_slot(-n), "VM stack underflow");
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrPopN(self.vm, n) };
+    /// If no context is available, the [std::fmt::Display] representation of `err` is used.
+    pub fn format_error(&self, err: &Error) -> std::string::String {
+        match err {
+            Error::InFile { path, source } => {
+                format!("{} (in `{path}`)", self.format_error(source))
+            }
+            Error::InFragment { index, source } => {
+                format!("{} (in fragment {index})", self.format_error(source))
+            }
+            Error::IO { .. } => err.to_string(),
+            _ => {
+                if matches!(err, Error::Runtime) {
+                    if let Some(traceback) = self.exception_stacktrace() {
+                        return traceback;
+                    }
+                }
+                let last = self.trampolines().last_error.as_ref().filter(|last| {
+                    std::mem::discriminant(&last.error) == std::mem::discriminant(err)
+                });
+                match last {
+                    Some(last) if matches!(err, Error::Runtime) => last.message.clone(),
+                    Some(CapturedError {
+                        file,
+                        line: Some(line),
+                        message,
+                        ..
+                    }) => format!("{file}:{line}: {message}"),
+                    Some(CapturedError { file, message, .. }) => format!("{file}: {message}"),
+                    None => err.to_string(),
+                }
+            }
+        }
     }
 
-    /// Push a `Number` onto the VM stack.
+    /// Returns the formatted stacktrace of the exception on top of the stack, or `None` if the
+    /// stack is empty or the value on top is not an exception.
+    fn exception_stacktrace(&self) -> Option<std::string::String> {
+        if !self.validate_slot(-1) {
+            return None;
+        }
+        self.ensure_stack(2);
+        self.get_global(CORE_MODULE, "Exception").ok()?;
+        // SAFETY: `self.vm` is a valid J* vm pointer, the value to check is at slot -2 and the
+        // `Exception` class at slot -1. Every value pushed is popped before returning
+        unsafe {
+            let is_exception = ffi::jsrIs(self.vm, -2, -1);
+            ffi::jsrPop(self.vm);
+            if !is_exception {
+                return None;
+            }
+            ffi::jsrGetStacktrace(self.vm, -1);
+        }
+        let stacktrace = self
+            .get_string(-1)
+            .map(|str| str.to_string_lossy().into_owned());
+        // SAFETY: `self.vm` is a valid J* vm pointer and the stacktrace is on top of the stack
+        unsafe { ffi::jsrPop(self.vm) };
+        stacktrace
+    }
+
+    /// Evaluate J* source or compiled code in the context of the `__main__` module, after
+    /// setting the globals in `env`.
     ///
-    /// # Errors
+    /// This allows to inject variables that the evaluated code can reference, as it is common
+    /// when using J* for templating or configuration. The injected globals are not removed after
+    /// the evaluation, so they remain visible to code evaluated later in `__main__`. Use
+    /// [VM::eval_with_scoped_globals] to clear them once the evaluation is done.
     ///
-    /// This method panics if there isn't enough stack space for one element. Use
-    /// [VM::ensure_stack] if you are not sure the stack has enough space.
-    pub fn push_number(&self, number: f64) {
-        assert!(self.validate_stack(), "VM stack overflow");
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrPushNumber(self.vm, number) };
+    /// # Arguments
+    ///
+    /// * `path` - The path of the code. See [VM::eval].
+    ///
+    /// * `code` - The J* source or compiled code to evaluate.
+    ///
+    /// * `env` - The `(name, value)` pairs of globals to set before the evaluation.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the evaluation succeded, `Err(`[`Error::Runtime`]`)` otherwise. If the globals
+    /// couldn't be set (i.e. `__main__` has been frozen with [VM::freeze_module]) the code is not
+    /// evaluated and an exception is left on top of the stack.
+    pub fn eval_with_globals(
+        &mut self,
+        path: &str,
+        code: impl AsRef<[u8]>,
+        env: &[(&str, &dyn ToJStar)],
+    ) -> Result<()> {
+        self.eval_with_env(path, code, env, false)
     }
 
-    /// Returns wether or not the value at `slot` is a `Number`.
-    ///
-    /// # Errors
+    /// Same as [VM::eval_with_globals], but the injected globals are restored to their previous
+    /// values once the evaluation is done, whatever its outcome, so that they don't leak into code
+    /// evaluated later in `__main__`.
     ///
-    /// This method panics if the slot underflows or overflows the stack (for the current stack
-    /// frame).
-    pub fn is_number(&self, slot: Index) -> bool {
-        assert!(self.validate_slot(slot), "VM stack overflow");
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrIsNumber(self.vm, slot) }
+    /// Keep in mind that J* has no way of removing a global, so the globals that didn't exist
+    /// before the call are left set to `null`.
+    pub fn eval_with_scoped_globals(
+        &mut self,
+        path: &str,
+        code: impl AsRef<[u8]>,
+        env: &[(&str, &dyn ToJStar)],
+    ) -> Result<()> {
+        self.eval_with_env(path, code, env, true)
     }
 
-    /// Gets a J* `Number` from the stack.
+    /// Implementation of [VM::eval_with_globals] and [VM::eval_with_scoped_globals].
+    fn eval_with_env(
+        &mut self,
+        path: &str,
+        code: impl AsRef<[u8]>,
+        env: &[(&str, &dyn ToJStar)],
+        cleanup: bool,
+    ) -> Result<()> {
+        if !env.is_empty() && self.trampolines().frozen_modules.contains(MAIN_MODULE) {
+            self.raise(
+                "NameException",
+                &format!("Cannot inject globals, module `{MAIN_MODULE}` is frozen"),
+            );
+            return Err(Error::Runtime);
+        }
+        for (name, _) in env {
+            CString::new(*name)?;
+        }
+        let code = code.as_ref();
+        let mut res = Ok(());
+        // The previous values of the globals, and the value left by a failed injection, live in
+        // the frame so that they are discarded along with it
+        self.in_frame(None, &mut |vm| {
+            vm.ensure_stack(env.len() + 2);
+            if cleanup {
+                // The previous values are kept in slots `3..3 + env.len()`
+                for (name, _) in env {
+                    if vm.get_global(MAIN_MODULE, name).is_err() {
+                        vm.pop();
+                        vm.push_null();
+                    }
+                }
+            }
+            let mut injected = 0;
+            let mut injected_res = Ok(());
+            for (name, value) in env {
+                value.to_jstar(vm);
+                injected_res = vm.set_global(MAIN_MODULE, name);
+                if injected_res.is_err() {
+                    break;
+                }
+                vm.pop();
+                injected += 1;
+            }
+            if injected_res.is_ok() {
+                res = vm.eval(path, code);
+            }
+            if cleanup {
+                for (i, (name, _)) in env[..injected].iter().enumerate() {
+                    // SAFETY: `vm.vm` is a valid J* vm pointer and the previous value of the
+                    // global is in slot `3 + i`
+                    unsafe { ffi::jsrPushValue(vm.vm, 3 + i as Index) };
+                    // The global has just been set, so restoring it can't fail
+                    let _ = vm.set_global(MAIN_MODULE, name);
+                    vm.pop();
+                }
+            }
+            injected_res
+        })?;
+        self.pop();
+        res
+    }
+
+    /// Evaluate J* source or compiled code in the context of the `__main__` module, discarding
+    /// any error it raises.
     ///
-    /// # Returns
+    /// This is meant for best-effort code whose failure doesn't matter, such as cleanup hooks run
+    /// during shutdown. Errors are not passed to the error callback, but they are still recorded
+    /// by [VM::last_error]. The stack is left as it was found, whatever the outcome.
     ///
-    /// `None` if the value at `slot` is not a `Number`, the `Number` as an [f64] otherwise.
+    /// # Arguments
     ///
-    /// # Errors
+    /// * `path` - A string representing the code path. See [VM::eval].
     ///
-    /// This method panics if the slot underflows or overflows the stack (for the current stack
-    /// frame).
-    pub fn get_number(&self, slot: Index) -> Option<f64> {
-        if !self.is_number(slot) {
-            None
-        } else {
-            // SAFETY: `slot` is a valide slot per check above, and its a `Number`
-            Some(unsafe { ffi::jsrGetNumber(self.vm, slot) })
-        }
+    /// * `code` - The J* source or compiled code to evaluate.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the evaluation succeded, `false` otherwise.
+    pub fn eval_ignoring_errors(&mut self, path: &str, code: impl AsRef<[u8]>) -> bool {
+        let cp = self.checkpoint();
+        let (res, _) = self.capture_errors(|vm| vm.eval(path, code));
+        self.restore(cp);
+        res.is_ok()
     }
 
-    /// Gets a J* `Number` from the stack, checking that it is a `Number` and leaving a
-    /// `TypeException` on the stack if it is not.
+    /// Evaluate J* source or compiled code in the context of the `__main__` module, interrupting
+    /// it if it runs for longer than `timeout`.
     ///
-    /// # Returns
+    /// Once the timeout expires a watchdog thread interrupts the evaluation, making it fail with a
+    /// `ProgramInterrupt` exception. This only bounds the running time: the code has the same
+    /// access to modules and globals as with [VM::eval], so this is not a sandbox for untrusted
+    /// code.
     ///
-    /// `Ok(`[`f64`]`)` if the value at `slot` is a `Number`, `Err(`[`Error::Runtime`]`)` otherwise,
-    /// leaving a `TypeException` on the stack.
+    /// J* provides no way of cancelling a requested interruption. If the timeout expires just as
+    /// the code completes, after the interpreter stopped but before this method returns, the
+    /// interruption stays pending and the next evaluation on this [VM] will be interrupted as soon
+    /// as it starts.
     ///
-    /// # Errors
+    /// # Arguments
     ///
-    /// This method panics if the slot underflows or overflows the stack (for the current stack
-    /// frame).
-    pub fn check_number(&self, slot: Index, name: &str) -> Result<f64> {
-        assert!(self.validate_slot(slot), "VM stack overflow");
-        let name = CString::new(name).expect("Error converting `name` to c-string");
-        if !unsafe { ffi::jsrCheckNumber(self.vm, slot, name.as_ptr()) } {
-            Err(Error::Runtime)
-        } else {
-            Ok(unsafe { ffi::jsrGetNumber(self.vm, slot) })
-        }
+    /// * `path` - The path of the code. See [VM::eval].
+    ///
+    /// * `code` - The J* source or compiled code to evaluate.
+    ///
+    /// * `timeout` - The maximum time the evaluation is allowed to run for.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the evaluation succeded, `Err(`[`Error::Runtime`]`)` if the code raised an
+    /// exception or was interrupted. As with [VM::eval], the error is reported to the error
+    /// callback and can be inspected with [VM::last_error].
+    pub fn eval_with_timeout(
+        &mut self,
+        path: &str,
+        code: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> Result<()> {
+        struct VMPtr(*mut ffi::JStarVM);
+        // SAFETY: the pointer is only used to call `jsrEvalBreak`, that just sets a flag checked
+        // by the interpreter loop and is meant to be called asynchronously (e.g. from a signal
+        // handler). The thread is joined before this function returns, so the vm outlives it.
+        unsafe impl Send for VMPtr {}
+
+        let vm = VMPtr(self.vm);
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        // Set once the evaluation returned, so that a late watchdog doesn't leave a pending break
+        // that would interrupt the next, unrelated, evaluation
+        let finished = Mutex::new(false);
+        std::thread::scope(|scope| {
+            let finished = &finished;
+            scope.spawn(move || {
+                let vm = vm;
+                if let Err(RecvTimeoutError::Timeout) = wait.recv_timeout(timeout) {
+                    let finished = finished.lock().unwrap_or_else(PoisonError::into_inner);
+                    if !*finished {
+                        // SAFETY: see `VMPtr` above
+                        unsafe { ffi::jsrEvalBreak(vm.0) };
+                    }
+                }
+            });
+            let res = self.eval(path, code);
+            *finished.lock().unwrap_or_else(PoisonError::into_inner) = true;
+            drop(done);
+            res
+        })
     }
 
-    /// Push a `String` onto the VM stack.  
+    /// Compiles the J* expression `src` into a function taking `params` as arguments, so that it
+    /// can be evaluated many times with different inputs without recompiling it.
     ///
-    /// Since a J* string can contain arbitrary bytes, this method accepts anything that can be
-    /// treated as a byte slice. The data will be copied into a J* `String` before being pushed onto
-    /// the [VM] stack.
+    /// The expression is compiled in the `__main__` module, so it can refer to its globals. The
+    /// compiled function is released once the returned [CompiledExpr] (and all of its clones) is
+    /// dropped.
     ///
-    /// # Errors
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// let mut vm = VM::new(Conf::new()).init_runtime();
     ///
-    /// This method panics if there isn't enough stack space for one element. Use [VM::ensure_stack]
-    /// if you are not sure the stack has enough space.
-    pub fn push_string(&self, str: impl AsRef<[u8]>) {
-        let str = str.as_ref();
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrPushStringSz(self.vm, str.as_ptr() as *const c_char, str.len()) }
+    /// let expr = vm.compile_expr(&["a", "b"], "a + b").unwrap();
+    /// expr.eval(&mut vm, &[&1, &2]).unwrap();
+    /// assert_eq!(vm.get_number(-1), Some(3.0));
+    /// ```
+    ///
+    /// # Returns
+    ///
+    /// `Ok(`[CompiledExpr]`)` if the compilation succeded, `Err(`[`Error`]`)` otherwise.
+    /// `Err(`[`Error::Syntax`]`)` is also returned, without reporting any error, if one of the
+    /// `params` is not an identifier or if `src` has unbalanced brackets, as it could then escape
+    /// the function it is compiled into.
+    pub fn compile_expr(&self, params: &[&str], src: &str) -> Result<CompiledExpr> {
+        if !params.iter().all(|param| is_identifier(param)) || !is_balanced(src) {
+            return Err(Error::Syntax);
+        }
+        let global = self.acquire_global(&self.trampolines().exprs);
+        let code = format!(
+            "fun {}({})\n    return ({src})\nend",
+            global.name,
+            params.join(", ")
+        );
+        self.eval_in_module("<expr>", MAIN_MODULE, code)?;
+        Ok(CompiledExpr {
+            vm: self.vm,
+            global: Rc::new(global),
+        })
     }
 
-    /// Returns wether or not the value at `slot` is a J* `String`.
+    /// Returns wether or not the value at `slot` can be called, i.e. if it is a function or a
+    /// class.
     ///
     /// # Errors
     ///
     /// This method panics if the slot underflows or overflows the stack (for the current stack
     /// frame).
-    pub fn is_string(&self, slot: Index) -> bool {
+    pub fn is_callable(&self, slot: Index) -> bool {
         assert!(self.validate_slot(slot), "`slot` out of bounds");
         // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrIsString(self.vm, slot) }
+        if unsafe { ffi::jsrIsFunction(self.vm, slot) } {
+            return true;
+        }
+        let slot = self.abs_slot(slot);
+        self.ensure_stack(1);
+        if self.get_global(CORE_MODULE, "Class").is_err() {
+            // SAFETY: `self.vm` is a valid J* vm pointer and the exception is on top of the stack
+            unsafe { ffi::jsrPop(self.vm) };
+            return false;
+        }
+        // SAFETY: `self.vm` is a valid J* vm pointer and the `Class` class is on top of the stack
+        unsafe {
+            let is_class = ffi::jsrIs(self.vm, slot, -1);
+            ffi::jsrPop(self.vm);
+            is_class
+        }
     }
 
-    /// Gets a J* `String` from the stack.
+    /// Roots the callable at `slot` inside the vm, returning an [EventHandler] that can be
+    /// stored and used to invoke it later on.
+    ///
+    /// This is the building block for event systems, where J* code registers callbacks that the
+    /// embedder fires at a later time, long after the callable has been popped from the stack.
+    /// The callable stays alive until the returned handler is dropped.
     ///
     /// # Returns
     ///
-    /// `Some(`[JStarString]`)` if the value at `slot` is a `String`, `None` otherwise.
+    /// `Ok(EventHandler)` if the value at `slot` is callable (see [VM::is_callable]).  
+    /// `Err(`[`Error::Runtime`]`)` otherwise, leaving a `TypeException` on top of the stack.
     ///
     /// # Errors
     ///
     /// This method panics if the slot underflows or overflows the stack (for the current stack
     /// frame).
-    pub fn get_string(&self, slot: Index) -> Option<JStarString> {
-        if !self.is_string(slot) {
-            None
-        } else {
-            // SAFETY: `slot` is a valid slot per check above, and its a `Number`
-            let data = unsafe { ffi::jsrGetString(self.vm, slot) };
-            let len = unsafe { ffi::jsrGetStringSz(self.vm, slot) };
-            Some(JStarString::new(data, len))
+    pub fn get_closure(&self, slot: Index) -> Result<EventHandler> {
+        if !self.is_callable(slot) {
+            self.raise("TypeException", "Value must be callable");
+            return Err(Error::Runtime);
         }
+        self.ensure_rust_module()?;
+        let global = self.acquire_global(&self.trampolines().handlers);
+        self.ensure_stack(1);
+        // SAFETY: `self.vm` is a valid J* vm pointer and `slot` is valid
+        unsafe { ffi::jsrPushValue(self.vm, slot) };
+        let res = self.set_global(RUST_MODULE, &global.name);
+        // SAFETY: `self.vm` is a valid J* vm pointer and the callable is on top of the stack
+        unsafe { ffi::jsrPop(self.vm) };
+        res?;
+        Ok(EventHandler {
+            vm: self.vm,
+            global,
+        })
     }
 
-    /// Gets a J* `String` from the stack, checking that it is a `String` and leaving a
-    /// `TypeException` on top of the stack if it is not.
-    ///
-    /// # Returns
-    ///
-    /// `Ok(`[`JStarString`]`)` if the value at `slot` is a `Number`.  
-    /// `Err(`[`Error::Runtime`]`)` otherwise, leaving a `TypeException` on the stack.
-    ///
-    /// # Errors
-    ///
-    /// This method panics if the slot underflows or overflows the stack (for the current stack
-    /// frame).
-    pub fn check_string(&self, slot: Index, name: &str) -> Result<JStarString> {
-        assert!(self.validate_slot(slot), "VM stack overflow");
-        let name = CString::new(name).expect("Error converting `name` to c-string");
-        if !unsafe { ffi::jsrCheckString(self.vm, slot, name.as_ptr()) } {
-            Err(Error::Runtime)
-        } else {
-            let data = unsafe { ffi::jsrGetString(self.vm, slot) };
-            let len = unsafe { ffi::jsrGetStringSz(self.vm, slot) };
-            Ok(JStarString::new(data, len))
+    /// Returns a global of `globals` free to root a new value, reusing the ones released by the
+    /// dropped handles.
+    fn acquire_global(&self, globals: &Rc<RefCell<RootedGlobals>>) -> RootedGlobal {
+        self.release_rooted(globals);
+        let mut rooted = globals.borrow_mut();
+        let id = rooted.free.pop().unwrap_or_else(|| {
+            rooted.len += 1;
+            rooted.len - 1
+        });
+        RootedGlobal {
+            name: format!("{}{id}", rooted.prefix),
+            id,
+            globals: Rc::clone(globals),
         }
     }
 
-    /// Get a global variable `name` from module `module_name`.
-    ///
-    /// # Returns
-    ///
-    /// `Ok(())` in case of success leaving the value on top of the stack.  
-    /// `Err(`[`Error::Runtime`]`)` in case of failure leaving an exception on top of the stack.
-    pub fn get_global(&self, module_name: &str, name: &str) -> Result<()> {
-        // TODO: check that `module_name` exists. New J* apis should be added for this.
-        assert!(self.validate_stack());
-        let module_name =
-            CString::new(module_name).expect("Error converting `module` name to c-string");
-        let name = CString::new(name).expect("Error converting `name` to c-string");
-        let res = unsafe { ffi::jsrGetGlobal(self.vm, module_name.as_ptr(), name.as_ptr()) };
-        if !res {
-            Err(Error::Runtime)
-        } else {
-            Ok(())
+    /// Nulls the globals of `globals` released since the last call, making their values
+    /// collectable, and marks them as free to be reused.
+    fn release_rooted(&self, globals: &RefCell<RootedGlobals>) {
+        let (module, prefix, released) = {
+            let mut rooted = globals.borrow_mut();
+            let released = std::mem::take(&mut rooted.released);
+            (rooted.module, rooted.prefix, released)
+        };
+        if released.is_empty() {
+            return;
+        }
+        let module = CString::new(module).expect("module names to be valid c-strings");
+        self.ensure_stack(1);
+        for &id in &released {
+            let name =
+                CString::new(format!("{prefix}{id}")).expect("global names to be valid c-strings");
+            // SAFETY: `self.vm` is a valid J* vm pointer. The global is set directly through the
+            // J* api, as the module may have been frozen after the global was created
+            unsafe {
+                ffi::jsrPushNull(self.vm);
+                ffi::jsrSetGlobal(self.vm, module.as_ptr(), name.as_ptr());
+                ffi::jsrPop(self.vm);
+            }
         }
+        globals.borrow_mut().free.extend(released);
     }
 
-    /// Sets a global variable `name` in module `module_name` with the value on top of the stack.
-    /// The value is not popped.
+    /// Pops the exception on top of the stack, reporting its stacktrace to the error callback as
+    /// a runtime error coming from `file`.
+    fn report_exception(&mut self, file: &str) {
+        let stacktrace = self.exception_stacktrace().unwrap_or_default();
+        self.pop();
+        self.trampolines_mut()
+            .report_error(ffi::JStarResult::RuntimeErr, file, None, &stacktrace);
+    }
+
+    /// Evaluate J* source or compiled code in the context of module `module`, then read the
+    /// global `export` from it converting it to `T`.
+    ///
+    /// This is handy for loading plugins that expose a single value, such as a factory function.
     ///
     /// # Arguments
     ///
-    /// * `module_name` - The name of the module in which to set the global. Could be any valid J*
-    ///    module name or [CORE_MODULE](../constant.CORE_MODULE.html)/[MAIN_MODULE](../constant.MAIN_MODULE.html)
-    ///    for the two built-in modules.
-    /// * `name` - The name of the global variable to set.
+    /// * `path` - The path of the module. Used for error reporting.
+    ///
+    /// * `module` - The name of the module to evaluate.
+    ///
+    /// * `code` - The J* source or compiled code to evaluate.
+    ///
+    /// * `export` - The name of the global to read after the evaluation.
     ///
     /// # Returns
     ///
-    /// `Ok(())` on success, leaving the value on top of the stack.
-    /// `Err(`[`Error::Runtime`]`)` in case of failure, leaving an exception on top of the stack.
-    pub fn set_global(&self, module_name: &str, name: &str) -> Result<()> {
-        // TODO: check that `module_name` exists. New J* apis should be added for this.
-        assert!(self.validate_slot(-1));
-        let module_name = CString::new(module_name).expect("`module` to be a valid CString");
-        let name = CString::new(name).expect("`name` to be a valid CString");
-        let res = unsafe { ffi::jsrSetGlobal(self.vm, module_name.as_ptr(), name.as_ptr()) };
-        if !res {
-            Err(Error::Runtime)
+    /// `Ok(T)` if the evaluation succeded and the export could be converted to `T`.  
+    /// `Err(`[`Error::Runtime`]`)` if the evaluation failed, or if the export doesn't exist or it
+    /// is not of type `T`, leaving an exception on top of the stack in the latter two cases.
+    pub fn run_module<T>(
+        &mut self,
+        path: &str,
+        module: &str,
+        code: impl AsRef<[u8]>,
+        export: &str,
+    ) -> Result<T>
+    where
+        T: for<'vm> FromJStar<'vm>,
+    {
+        self.eval_in_module(path, module, code)?;
+        self.get_global(module, export)?;
+        let res = T::from_jstar(self, -1);
+        self.pop();
+        res.ok_or_else(|| {
+            self.raise(
+                "TypeException",
+                &format!("Export `{export}` of module `{module}` has the wrong type"),
+            );
+            Error::Runtime
+        })
+    }
+
+    /// Call the value at slot `-(argc - 1)` with the arguments from `-argc..$top`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the call succeded leaving the result on top of the stack, `Err(`[`Error::Runtime`]`)`
+    /// if the the call failed leaving an Exception on top of the stack. In both cases, the args
+    /// and the callee are popped from the stack.
+    ///
+    /// # Errors
+    ///
+    /// This function panics if the stack underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn call(&mut self, argc: u8) -> Result<()> {
+        assert!(self.validate_slot(-(argc as i32 + 1)));
+        // SAFETY: `self.vm` is a valid pointer
+        let res = unsafe { ffi::jsrCall(self.vm, argc) };
+        if let Ok(err) = res.try_into() {
+            Err(err)
         } else {
             Ok(())
         }
     }
 
-    /// Pushes a naive function onto the stack.
+    /// Call the value at slot `-(argc - 1)` with the arguments from `-argc..$top`, collecting its
+    /// multiple return values as owned [Value]s.
     ///
-    /// See [crate::native!] for utility functions and macros to create natives.
+    /// J* functions return multiple values by packing them in a `Tuple`, whose elements become
+    /// the results. Any other return value is treated as a single result.
     ///
     /// # Arguments
     ///
-    /// * `module` - The name of the module the function belongs to
-    /// * `name` - The name of the function
-    /// * `func` - The native function to push
-    /// * `argc` - The number of arguments the function takes
+    /// * `argc` - The number of arguments on top of the stack.
+    /// * `n_results` - The number of results the function is expected to return.
     ///
     /// # Returns
     ///
-    /// `Ok(())` on success, leaving the native function on top of the stack.
-    /// `Err(`[`Error::Runtime`]`)` in case of failure, leaving an exception on top of the stack.
-    pub fn push_native(
-        &self,
-        module: &str,
-        name: &str,
-        func: ffi::JStarNative,
-        argc: u8,
-    ) -> Result<()> {
-        let module = CString::new(module).expect("`module` to be a valid CString");
-        let name = CString::new(name).expect("`name` to be a valid CString");
-        let res =
-            unsafe { ffi::jsrPushNative(self.vm, module.as_ptr(), name.as_ptr(), func, argc) };
-        if !res {
-            Err(Error::Runtime)
-        } else {
-            Ok(())
+    /// `Ok(Vec<Value>)` with the `n_results` results if the call succeded, popping its return
+    /// value from the stack.
+    /// `Err(`[`Error::Runtime`]`)` if the call failed, if the number of results doesn't match
+    /// `n_results` or if they cannot be represented as [Value]s, leaving an exception on top of
+    /// the stack.
+    ///
+    /// # Errors
+    ///
+    /// This function panics if the stack underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn call_multi(&mut self, argc: u8, n_results: usize) -> Result<Vec<Value>> {
+        self.call(argc)?;
+        let res = self.get_value_owned(-1);
+        self.pop();
+        let results = match res {
+            Some(Value::Tuple(elems)) => elems,
+            Some(value) => vec![value],
+            None => {
+                self.raise("TypeException", "Return value cannot be converted");
+                return Err(Error::Runtime);
+            }
+        };
+        if results.len() != n_results {
+            self.raise(
+                "TypeException",
+                &format!("Expected {n_results} return values, got {}", results.len()),
+            );
+            return Err(Error::Runtime);
         }
+        Ok(results)
     }
 
-    /// Registers a native function in the global scope of module `module`.
+    /// Call the value on top of the stack passing `fixed` arguments followed by the optional
+    /// arguments in `opt`.
     ///
-    /// This is a convenience method that is mostly equivalent to doing:
-    /// ```rust
-    /// # use jstar::{vm::VM, conf::Conf, native};
-    /// # let mut vm = VM::new(Conf::new()).init_runtime();
-    /// # let module = "module";
-    /// # let name = "func_name";
-    /// # let argc = 0;
-    /// # native!(fn func(vm) { Ok(()) });
-    /// vm.push_native(module, name, func, argc);
-    /// vm.set_global(module, name);
-    /// vm.pop();
-    /// ```
+    /// Trailing `None`s in `opt` are omitted from the call, so that the callee's default values
+    /// are used for them. Any other `None` is passed as `null`.
     ///
-    /// # Arguments
+    /// # Returns
     ///
-    /// * `module` - The name of the module the function belongs to
-    /// * `name` - The name the function will be bound to
-    /// * `func` - The native function to register
-    /// * `argc` - The number of arguments the function takes
+    /// `Ok(())` if the call succeded leaving the result on top of the stack,
+    /// `Err(`[`Error::Runtime`]`)` if the call failed leaving an exception on top of the stack.
+    /// In both cases, the callee is popped from the stack.
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// `Ok(())` on success.
-    /// `Err(`[`Error::Runtime`]`)` in case of failure, leaving an exception on top of the stack.
-    pub fn register_native(
-        &self,
-        module: &str,
-        name: &str,
-        func: ffi::JStarNative,
-        argc: u8,
-    ) -> Result<()> {
-        self.push_native(module, name, func, argc)?;
-        self.set_global(module, name)?;
-        // SAFETY: `self.vm` is a valid J* vm pointer and we are guaranteed that the stack will
-        // not underflow (we just pushed ane element)
-        unsafe { ffi::jsrPop(self.vm) };
-        Ok(())
+    /// This function panics if the stack is empty or if more than 255 arguments are passed.
+    pub fn call_opt(&mut self, fixed: &[&dyn ToJStar], opt: &[Option<&dyn ToJStar>]) -> Result<()> {
+        assert!(self.validate_slot(-1), "No callee on the stack");
+        let opt_len = opt.iter().rposition(Option::is_some).map_or(0, |i| i + 1);
+        let argc = u8::try_from(fixed.len() + opt_len).expect("Too many arguments");
+        self.ensure_stack(argc as usize);
+        for arg in fixed {
+            arg.to_jstar(self);
+        }
+        for arg in &opt[..opt_len] {
+            match arg {
+                Some(arg) => arg.to_jstar(self),
+                None => self.push_null(),
+            }
+        }
+        self.call(argc)
     }
 
-    /// Raises an exception in the VM, leaving it on top of the stack.
+    /// Call the value on top of the stack passing `args`, converting the result to `R`.
     ///
-    /// The exception class 'cls' is searched in the current executing module or __core__.
-    pub fn raise(&self, cls: &str, msg: &str) {
-        let cls = CString::new(cls).expect("Error converting `cls` to c-string");
-        let msg = CString::new(msg).expect("Error converting `msg` to c-string");
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrRaise(self.vm, cls.as_ptr(), c"%s".as_ptr(), msg.as_ptr()) };
+    /// Use `R = ()` to call functions that don't return anything (i.e. that return `null`).
+    ///
+    /// # Returns
+    ///
+    /// `Ok(R)` if the call succeded and its result could be converted, popping the result from
+    /// the stack.
+    /// `Err(`[`Error::Runtime`]`)` if the call failed or the result has the wrong type, leaving
+    /// an exception on top of the stack.
+    /// In both cases, the callee is popped from the stack.
+    ///
+    /// # Errors
+    ///
+    /// This function panics if the stack is empty or if more than 255 arguments are passed.
+    pub fn call_with<R>(&mut self, args: &[&dyn ToJStar]) -> Result<R>
+    where
+        R: for<'v> FromJStar<'v>,
+    {
+        assert!(self.validate_slot(-1), "No callee on the stack");
+        let argc = u8::try_from(args.len()).expect("Too many arguments");
+        self.ensure_stack(args.len());
+        for arg in args {
+            arg.to_jstar(self);
+        }
+        self.call(argc)?;
+        let res = R::from_jstar(self, -1);
+        self.pop();
+        res.ok_or_else(|| {
+            self.raise("TypeException", "Return value has the wrong type");
+            Error::Runtime
+        })
     }
 
-    /// Returns a [`StackRef`] pointing to the topmost stack slot.
-    pub fn get_top(&self) -> StackRef {
-        StackRef {
-            // SAFETY: `self.vm` is a valid J* vm pointer
-            index: unsafe { ffi::jsrTop(self.vm) },
-            vm: self,
+    /// Instantiates the class at slot `-(argc + 1)` passing the arguments from `-argc..$top` to its
+    /// constructor.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the instantiation succeded leaving the new instance on top of the stack,
+    /// `Err(`[`Error::Runtime`]`)` if the value at slot `-(argc + 1)` is not a `Class` or if the
+    /// constructor failed, leaving an exception on top of the stack. In both cases, the args and
+    /// the class are popped from the stack.
+    ///
+    /// # Errors
+    ///
+    /// This function panics if the stack underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn new_instance(&mut self, argc: u8) -> Result<()> {
+        assert!(self.validate_slot(-(argc as i32 + 1)));
+        if !self.is_class(-(argc as i32 + 1)) {
+            self.pop_n(argc as i32 + 1);
+            self.raise("TypeException", "Can only instantiate a Class");
+            return Err(Error::Runtime);
         }
+        self.call(argc)
     }
 
-    /// Returns a [`StackRef`] pointing to the stack slot at `slot`.
-    /// `slot` is treated as an offset from the top of the stack and must be positive.
+    /// Structurally compares the values at slots `a` and `b`.
+    ///
+    /// Lists, tuples and tables are compared element by element recursively, while all other
+    /// values are compared using the `==` operator. This is useful, for example, to check the
+    /// shape of data structures produced by J* scripts, as two distinct lists with the same
+    /// elements compare equal.
+    ///
+    /// Cyclic structures are handled by bounding the recursion depth: if the bound is exceeded an
+    /// `InvalidArgException` is raised.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(true)` if the values are structurally equal, `Ok(false)` otherwise.  
+    /// `Err(`[`Error::Runtime`]`)` if the comparison raised an exception, leaving it on top of the
+    /// stack.
     ///
     /// # Errors
     ///
-    /// This method panics if the slot underflows the stack (for the current stack frame).
-    pub fn peek_top(&self, slot: Index) -> StackRef {
-        assert!(slot > 0, "`slot` must be positive");
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        let idx = unsafe { ffi::jsrTop(self.vm) } - slot;
-        assert!(self.validate_slot(idx), "`slot` out of bounds");
-        StackRef {
-            index: idx,
-            vm: self,
+    /// This method panics if `a` or `b` underflow or overflow the stack (for the current stack
+    /// frame).
+    pub fn deep_equals(&mut self, a: Index, b: Index) -> Result<bool> {
+        assert!(self.validate_slot(a), "`a` out of bounds");
+        assert!(self.validate_slot(b), "`b` out of bounds");
+        let (a, b) = (self.abs_slot(a), self.abs_slot(b));
+        self.ensure_stack(4);
+        self.get_helper("deepEquals")?;
+        // SAFETY: `self.vm` is a valid J* vm pointer and `a` and `b` are valid slots
+        unsafe {
+            ffi::jsrPushValue(self.vm, a);
+            ffi::jsrPushValue(self.vm, b);
         }
+        self.push_number(0.0);
+        self.call(3)?;
+        // SAFETY: `self.vm` is a valid J* vm pointer and `deepEquals` always returns a boolean
+        let res = unsafe { ffi::jsrGetBoolean(self.vm, -1) };
+        self.pop();
+        Ok(res)
     }
 
-    /// Ensure that the vm's stack can hold at least `needed` items, reallocating the stack
-    /// to add more space if needed.
+    /// Pushes `value` onto the stack and returns `Ok(())`.
     ///
-    /// See [`native::MIN_NATIVE_STACK_SZ`](../native/constant.MIN_NATIVE_STACK_SZ.html) for the
-    /// minimum guaranteed stack size when calling a J* native function.
-    pub fn ensure_stack(&self, needed: usize) {
-        // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrEnsureStack(self.vm, needed) };
+    /// This is a shorthand for the common ending of a native function, that pushes its return
+    /// value and then succeeds:
+    ///
+    /// ```rust
+    /// # use jstar::{native, convert::FromJStar};
+    /// native!(fn double(vm) {
+    ///     let n = f64::from_jstar_checked(vm, 1, "n")?;
+    ///     vm.push_ok(n * 2.0)
+    /// });
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_ok<T: ToJStar>(&self, value: T) -> Result<()> {
+        value.to_jstar(self);
+        Ok(())
     }
 
-    /// Returns `true` if the provided slot is valid, i.e. it doesn't overflow or underflow the
-    /// stack, false otherwise
-    pub fn validate_slot(&self, slot: Index) -> bool {
+    /// Pops one element from the VM stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if we try to pop more items than the stack holds (for the current stack
+    /// frame).
+    pub fn pop(&mut self) {
+        assert!(self.validate_slot(-1), "VM stack underflow");
         // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrValidateSlot(self.vm, slot) }
+        unsafe { ffi::jsrPop(self.vm) };
     }
 
-    /// Returns `true` if the stack has space for one element, i.e. pushing one element will not
-    /// overflow the stack
-    pub fn validate_stack(&self) -> bool {
+    /// Pops `n` elements from the VM stack
+    ///
+    /// # Errors
+    ///
+    /// This method panics if we try to pop more items than the stack holds (for the current stack
+    /// frame).
+    pub fn pop_n(&mut self, n: i32) {
+        assert!(n > 0, "`n` must be greater than 0");
+        assert!(self.validate_slot(-n), "VM stack underflow");
         // SAFETY: `self.vm` is a valid J* vm pointer
-        unsafe { ffi::jsrValidateStack(self.vm) }
+        unsafe { ffi::jsrPopN(self.vm, n) };
     }
-}
 
-/// Methods available to both [Init] and [Uninit] VMs.
-impl<'a, State> VM<'a, State> {
-    /// Compiles J* source code into bytecode.
+    /// Pops the value on top of the VM stack, converting it to an owned `T`.
     ///
-    /// # Arguments
-    ///
-    /// * `src` - The J* source code to compile
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// let mut vm = VM::new(Conf::new()).init_runtime();
+    /// vm.push_number(42.0);
+    /// assert_eq!(vm.pop_to::<i32>().unwrap(), 42);
+    /// ```
     ///
-    /// * `path` - The path of the source code. It doesn't have to be a real filesystem path, as it
-    ///    is only used during error callbacks to provide useful context to the client handling the
-    ///    error. Nonetheless, if the source code has been indeed read from a file, it is reccomended
-    ///    to pass its path to this function.
+    /// # Returns
     ///
-    /// * `out` - A [`Write`] implementor to write the compiled bytecode to
+    /// `Ok(T)` if the value could be converted to `T`, `Err(`[`Error::Runtime`]`)` otherwise,
+    /// leaving a `TypeException` on top of the stack. The value is popped in both cases.
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// `Ok(())` if the compilation succeded, `Err(`[`Error`]`)` otherwise.
-    pub fn compile(&self, path: &str, src: &str, mut out: impl Write) -> Result<()> {
-        let path = CString::new(path).expect("`path` to not contain NUL characters");
-        let src = CString::new(src).expect("`src` to not contain NUL characters");
-        let mut buf = ffi::JStarBuffer::default();
-
-        // SAFETY: `self.vm` is a valid pointer
-        let res = unsafe {
-            ffi::jsrCompileCode(
-                self.vm,
-                path.as_ptr(),
-                src.as_ptr(),
-                &mut buf as *mut ffi::JStarBuffer,
-            )
-        };
-
-        if let Ok(err) = res.try_into() {
-            return Err(err);
-        }
-
-        // SAFETY: we are guaranteed by the J* API that `buf.data` is a valid pointer (check above)
-        // and that its size is at least of `buf.size` bytes
-        let slice = unsafe { from_raw_parts(buf.data as *const u8, buf.size) };
-        let write_res = out.write_all(slice);
-
-        // SAFETY: we are guaranteed that `buf` is a valid and initialized J* buffer (check above)
-        unsafe { ffi::jsrBufferFree(&mut buf as *mut ffi::JStarBuffer) };
+    /// This method panics if the stack is empty (for the current stack frame).
+    pub fn pop_to<T>(&mut self) -> Result<T>
+    where
+        T: for<'vm> FromJStar<'vm>,
+    {
+        assert!(self.validate_slot(-1), "VM stack underflow");
+        let res = T::from_jstar(self, -1);
+        self.pop();
+        res.ok_or_else(|| {
+            self.raise("TypeException", "Value has the wrong type");
+            Error::Runtime
+        })
+    }
 
-        match write_res {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e.into()),
-        }
+    /// Pushes a copy of the value at `slot` onto the top of the VM stack.
+    ///
+    /// Only the reference is copied: objects such as `String`s or `List`s are shared between the
+    /// two slots, so no data is duplicated.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame), or if there isn't enough stack space for one element. Use [VM::ensure_stack] if
+    /// you are not sure the stack has enough space.
+    pub fn push_value(&self, slot: Index) {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer, `slot` is a valid slot and we made sure
+        // there's space for the value
+        unsafe { ffi::jsrPushValue(self.vm, slot) };
     }
 
-    /// Similar to [VM::compile] but returns the compiled bytecode as a [`Vec<u8>`].
-    /// This method is convenient when the compiled bytecode needs to be stored in memory.
+    /// Duplicates the value on top of the VM stack. Equivalent to `vm.push_value(-1)`.
     ///
-    /// # arguments
+    /// # Errors
     ///
-    /// * `path` - The path of the source code. It doesn't have to be a real filesystem path, as it
-    ///    is only used during error callbacks to provide useful context to the client handling the
-    ///    error. Nonetheless, if the source code has been indeed read from a file, it is reccomended
-    ///    to pass its path to this function.
+    /// This method panics if the stack is empty (for the current stack frame), or if there isn't
+    /// enough stack space for one element.
+    pub fn dup(&self) {
+        self.push_value(-1);
+    }
+
+    /// Push a `Number` onto the VM stack.
     ///
-    /// * `src` - The J* source code to compile
+    /// # Errors
     ///
-    /// # Returns
-    /// `Ok(`[`Vec<u8>`]`)` if the compilation succeded, `Err(`[`Error`]`)` otherwise.
-    pub fn compile_in_memory(&self, path: &str, src: &str) -> Result<Vec<u8>> {
-        let mut out = Vec::new();
-        self.compile(path, src, &mut out)?;
-        Ok(out)
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_number(&self, number: f64) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushNumber(self.vm, number) };
     }
-}
+
+    /// Push an [i64] onto the VM stack as a `Number`, failing if it cannot be represented exactly.
+    ///
+    /// J* `Number`s are [f64]s, so integers with a magnitude greater than 2^53 may lose precision
+    /// when converted. Use this method instead of [VM::push_number] when such a loss would be a
+    /// bug rather than an acceptable approximation.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if `n` has been pushed, `Err(`[`Error::InexactNumber`]`)` if `n` isn't exactly
+    /// representable as an [f64]. In the latter case nothing is pushed onto the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_i64(&self, n: i64) -> Result<()> {
+        let number = n as f64;
+        // `as` saturates when going back to `i64`, so widen to compare the exact values
+        if number as i128 != n as i128 {
+            return Err(Error::InexactNumber(n));
+        }
+        self.push_number(number);
+        Ok(())
+    }
+
+    /// Returns wether or not the value at `slot` is a `Number`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_number(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsNumber(self.vm, slot) }
+    }
+
+    /// Gets a J* `Number` from the stack.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the value at `slot` is not a `Number`, the `Number` as an [f64] otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_number(&self, slot: Index) -> Option<f64> {
+        if !self.is_number(slot) {
+            None
+        } else {
+            // SAFETY: `slot` is a valide slot per check above, and its a `Number`
+            Some(unsafe { ffi::jsrGetNumber(self.vm, slot) })
+        }
+    }
+
+    /// Gets a J* `Number` from the stack, checking that it is a `Number` and leaving a
+    /// `TypeException` on the stack if it is not.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(`[`f64`]`)` if the value at `slot` is a `Number`, `Err(`[`Error::Runtime`]`)` otherwise,
+    /// leaving a `TypeException` on the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn check_number(&self, slot: Index, name: &str) -> Result<f64> {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        let name = CString::new(name)?;
+        if !unsafe { ffi::jsrCheckNumber(self.vm, slot, name.as_ptr()) } {
+            Err(Error::Runtime)
+        } else {
+            Ok(unsafe { ffi::jsrGetNumber(self.vm, slot) })
+        }
+    }
+
+    /// Push a `null` onto the VM stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_null(&self) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushNull(self.vm) };
+    }
+
+    /// Returns wether or not the value at `slot` is `null`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_null(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsNull(self.vm, slot) }
+    }
+
+    /// Checks that the value at `slot` is `null`, leaving a `TypeException` on the stack if it
+    /// is not.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the value at `slot` is `null`, `Err(`[`Error::Runtime`]`)` otherwise, leaving a
+    /// `TypeException` on the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn check_null(&self, slot: Index, name: &str) -> Result<()> {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        let name = CString::new(name)?;
+        if !unsafe { ffi::jsrCheckNull(self.vm, slot, name.as_ptr()) } {
+            Err(Error::Runtime)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Push a `Boolean` onto the VM stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_boolean(&self, boolean: bool) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushBoolean(self.vm, boolean) };
+    }
+
+    /// Returns wether or not the value at `slot` is a `Boolean`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_boolean(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsBoolean(self.vm, slot) }
+    }
+
+    /// Gets a J* `Boolean` from the stack.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the value at `slot` is not a `Boolean`, the `Boolean` as a [bool] otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_boolean(&self, slot: Index) -> Option<bool> {
+        if !self.is_boolean(slot) {
+            None
+        } else {
+            // SAFETY: `slot` is a valide slot per check above, and its a `Boolean`
+            Some(unsafe { ffi::jsrGetBoolean(self.vm, slot) })
+        }
+    }
+
+    /// Gets a J* `Boolean` from the stack, checking that it is a `Boolean` and leaving a
+    /// `TypeException` on the stack if it is not.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(`[`bool`]`)` if the value at `slot` is a `Boolean`, `Err(`[`Error::Runtime`]`)`
+    /// otherwise, leaving a `TypeException` on the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn check_boolean(&self, slot: Index, name: &str) -> Result<bool> {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        let name = CString::new(name)?;
+        if !unsafe { ffi::jsrCheckBoolean(self.vm, slot, name.as_ptr()) } {
+            Err(Error::Runtime)
+        } else {
+            Ok(unsafe { ffi::jsrGetBoolean(self.vm, slot) })
+        }
+    }
+
+    /// Push a `Handle` onto the VM stack.
+    ///
+    /// A `Handle` is an opaque pointer that J* code can store and pass around, but not inspect.
+    /// It is handy to hand Rust objects to J* code that only needs to give them back to natives
+    /// later on (e.g. a `*mut Connection`).
+    ///
+    /// Keep in mind that, unlike userdata, the pointed-to memory is not managed by the J* GC and
+    /// the handle carries no finalizer: the pointer is never freed by J*, and it is up to the
+    /// caller to make sure it stays valid for as long as J* code can reach the handle.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    // The handle is stored as an opaque value and never dereferenced
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn push_handle(&self, handle: *mut c_void) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer. The handle is never dereferenced by J*
+        unsafe { ffi::jsrPushHandle(self.vm, handle) };
+    }
+
+    /// Returns wether or not the value at `slot` is a `Handle`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_handle(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsHandle(self.vm, slot) }
+    }
+
+    /// Gets a J* `Handle` from the stack. See [VM::push_handle].
+    ///
+    /// # Returns
+    ///
+    /// `None` if the value at `slot` is not a `Handle`, the pointer it holds otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_handle(&self, slot: Index) -> Option<*mut c_void> {
+        if !self.is_handle(slot) {
+            None
+        } else {
+            // SAFETY: `slot` is a valide slot per check above, and its a `Handle`
+            Some(unsafe { ffi::jsrGetHandle(self.vm, slot) })
+        }
+    }
+
+    /// Gets a J* `Handle` from the stack, checking that it is a `Handle` and leaving a
+    /// `TypeException` on the stack if it is not.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(*mut c_void)` if the value at `slot` is a `Handle`, `Err(`[`Error::Runtime`]`)`
+    /// otherwise, leaving a `TypeException` on the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn check_handle(&self, slot: Index, name: &str) -> Result<*mut c_void> {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        let name = CString::new(name)?;
+        if !unsafe { ffi::jsrCheckHandle(self.vm, slot, name.as_ptr()) } {
+            Err(Error::Runtime)
+        } else {
+            Ok(unsafe { ffi::jsrGetHandle(self.vm, slot) })
+        }
+    }
+
+    /// Moves `value` into a newly allocated J* `Userdata` and pushes it onto the VM stack.
+    ///
+    /// The userdata is managed by the J* GC: when it is collected (or when the [VM] is dropped)
+    /// `value` is dropped in place by a finalizer. This allows to store arbitrary Rust objects,
+    /// like a [std::fs::File] or a `Box<dyn Trait>`, inside J* values. The type of `value` is
+    /// recorded alongside it, so that [VM::get_userdata] can check it when reading it back.
+    ///
+    /// Keep in mind that `T`'s [Drop] implementation runs inside the J* GC: it must not panic, as
+    /// unwinding across the J* C code aborts the process.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element, or if the memory
+    /// allocated by J* is not suitably aligned for `T` (J* guarantees the alignment of the
+    /// platform's largest primitive type, so only over-aligned types are affected).
+    pub fn push_userdata<T: 'static>(&self, value: T) {
+        extern "C" fn finalize<T>(data: *mut c_void) {
+            let userdata = data as *mut Userdata<T>;
+            // SAFETY: `data` points to the `Userdata<T>` initialized by `push_userdata`, and J*
+            // calls the finalizer only once, when the userdata is collected. The registry is
+            // owned by the trampolines, that outlive the J* vm and all its userdata
+            unsafe {
+                let registry = &*(*userdata).registry;
+                registry.borrow_mut().remove(&(data as usize));
+                std::ptr::drop_in_place(userdata);
+            }
+        }
+
+        const {
+            assert!(
+                std::mem::align_of::<Userdata<T>>() <= USERDATA_ALIGN,
+                "the type is over-aligned for J* userdata memory"
+            )
+        };
+        assert!(self.validate_stack(), "VM stack overflow");
+        let registry = &self.trampolines().userdata;
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        let data = unsafe {
+            ffi::jsrPushUserdata(self.vm, std::mem::size_of::<Userdata<T>>(), finalize::<T>)
+        };
+        let userdata = Userdata {
+            registry: Rc::as_ptr(registry),
+            value: RefCell::new(value),
+        };
+        // SAFETY: `data` points to a fresh allocation of the right size, aligned to at least
+        // `USERDATA_ALIGN` (checked at compile time above). It is dropped by `finalize` once the
+        // userdata is collected
+        unsafe { std::ptr::write(data as *mut Userdata<T>, userdata) };
+        registry
+            .borrow_mut()
+            .insert(data as usize, TypeId::of::<T>());
+    }
+
+    /// Returns wether or not the value at `slot` is a J* `Userdata`, of any type.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_userdata(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsUserdata(self.vm, slot) }
+    }
+
+    /// Borrows the `T` stored in the J* `Userdata` at `slot`. See [VM::push_userdata].
+    ///
+    /// The same userdata can be reachable from several slots, and natives run by a re-entrant
+    /// evaluation can access it while the borrow is alive, so borrows are checked at runtime just
+    /// like with a [RefCell]. Use [VM::get_userdata_mut] to mutate the value.
+    ///
+    /// Userdata created by other means, such as native C modules, are never read by this method.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the value at `slot` is not a `Userdata` created by [VM::push_userdata] with a
+    /// value of type `T`, or if the value is currently borrowed mutably. A [Ref] to the value
+    /// otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_userdata<T: 'static>(&self, slot: Index) -> Option<Ref<'_, T>> {
+        // SAFETY: the pointer is valid for as long as the userdata is on the stack, which is
+        // guaranteed by the shared borrow of `self`
+        let cell = unsafe { &*self.userdata_ptr::<T>(slot)? };
+        cell.try_borrow().ok()
+    }
+
+    /// Same as [VM::get_userdata], but mutably borrows the value.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the value at `slot` is not a `Userdata` created by [VM::push_userdata] with a
+    /// value of type `T`, or if the value is currently borrowed. A [RefMut] to the value
+    /// otherwise.
+    pub fn get_userdata_mut<T: 'static>(&mut self, slot: Index) -> Option<RefMut<'_, T>> {
+        // SAFETY: the pointer is valid for as long as the userdata is on the stack, which is
+        // guaranteed by the borrow of `self`
+        let cell = unsafe { &*self.userdata_ptr::<T>(slot)? };
+        cell.try_borrow_mut().ok()
+    }
+
+    /// Borrows the `T` stored in the J* `Userdata` at `slot`, leaving a `TypeException` on the
+    /// stack if the value is not a `Userdata` holding a `T`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Ref<T>)` if the value at `slot` is a `Userdata` holding a `T`,
+    /// `Err(`[`Error::Runtime`]`)` otherwise, leaving a `TypeException` on the stack. If the value
+    /// is currently borrowed mutably, a `ValueException` is left on the stack instead.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn check_userdata<T: 'static>(&self, slot: Index, name: &str) -> Result<Ref<'_, T>> {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        let cname = CString::new(name)?;
+        if !unsafe { ffi::jsrCheckUserdata(self.vm, slot, cname.as_ptr()) } {
+            return Err(Error::Runtime);
+        }
+        let Some(cell) = self.userdata_ptr::<T>(slot) else {
+            self.raise(
+                "TypeException",
+                &format!(
+                    "{name} must be a Userdata of type {}",
+                    std::any::type_name::<T>()
+                ),
+            );
+            return Err(Error::Runtime);
+        };
+        // SAFETY: the pointer is valid for as long as the userdata is on the stack, which is
+        // guaranteed by the shared borrow of `self`
+        unsafe { &*cell }.try_borrow().map_err(|_| {
+            self.raise("ValueException", &format!("{name} is already in use"));
+            Error::Runtime
+        })
+    }
+
+    /// Returns a pointer to the cell holding the `T` stored in the J* `Userdata` at `slot`, if
+    /// there is one.
+    fn userdata_ptr<T: 'static>(&self, slot: Index) -> Option<*const RefCell<T>> {
+        if !self.is_userdata(slot) {
+            return None;
+        }
+        // SAFETY: `self.vm` is a valid J* vm pointer and the value at `slot` is a Userdata
+        let data = unsafe { ffi::jsrGetUserdata(self.vm, slot) };
+        let type_id = self
+            .trampolines()
+            .userdata
+            .borrow()
+            .get(&(data as usize))
+            .copied();
+        let data = data as *mut Userdata<T>;
+        // SAFETY: the userdata has been pushed by `push_userdata` with a value of type `T`, so
+        // `data` points to a valid `Userdata<T>`. Only the pointer is computed here, no
+        // reference to the value is created
+        (type_id == Some(TypeId::of::<T>())).then(|| unsafe { std::ptr::addr_of!((*data).value) })
+    }
+
+    /// Push a `String` onto the VM stack.  
+    ///
+    /// Since a J* string can contain arbitrary bytes, this method accepts anything that can be
+    /// treated as a byte slice. The data will be copied into a J* `String` before being pushed onto
+    /// the [VM] stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use [VM::ensure_stack]
+    /// if you are not sure the stack has enough space.
+    pub fn push_string(&self, str: impl AsRef<[u8]>) {
+        let str = str.as_ref();
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushStringSz(self.vm, str.as_ptr() as *const c_char, str.len()) }
+    }
+
+    /// Push a `String` containing `unit` repeated `count` times onto the VM stack.
+    ///
+    /// The string is built directly in a J* buffer with a single allocation, which makes this
+    /// method cheaper than concatenating `String`s when building padding or separators.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the length of the resulting string overflows a `usize`, or if there
+    /// isn't enough stack space for one element. Use [VM::ensure_stack] if you are not sure the
+    /// stack has enough space.
+    pub fn push_repeated_string(&self, unit: &[u8], count: usize) {
+        let len = unit
+            .len()
+            .checked_mul(count)
+            .expect("Repeated string length overflows usize");
+        assert!(self.validate_stack(), "VM stack overflow");
+        if unit.is_empty() {
+            // Don't spin `count` times appending nothing
+            self.push_string(unit);
+            return;
+        }
+        let mut buf = ffi::JStarBuffer::default();
+        // SAFETY: `self.vm` is a valid J* vm pointer and `buf` is initialized before being used.
+        // `jsrBufferPush` takes ownership of the buffer's memory, so it must not be freed
+        unsafe {
+            ffi::jsrBufferInitCapacity(self.vm, &mut buf, len);
+            for _ in 0..count {
+                ffi::jsrBufferAppend(&mut buf, unit.as_ptr() as *const c_char, unit.len());
+            }
+            ffi::jsrBufferPush(&mut buf);
+        }
+    }
+
+    /// Push a `String` with all the bytes read from `r` onto the VM stack.
+    ///
+    /// The bytes are read directly into a J* buffer, avoiding the intermediate copy that reading
+    /// into a [Vec] and calling [VM::push_string] would require. This is useful for natives that
+    /// load large files.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if `r` was read until EOF, leaving the `String` on top of the stack.
+    /// `Err(`[`io::Error`]`)` if reading failed, in which case nothing is pushed.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_string_from_reader(&self, mut r: impl Read) -> io::Result<()> {
+        assert!(self.validate_stack(), "VM stack overflow");
+        let mut buf = ffi::JStarBuffer::default();
+        let mut chunk = [0u8; 8192];
+        // SAFETY: `self.vm` is a valid J* vm pointer and `buf` is initialized before being used
+        unsafe { ffi::jsrBufferInit(self.vm, &mut buf) };
+        loop {
+            match r.read(&mut chunk) {
+                Ok(0) => break,
+                // SAFETY: `buf` is a valid and initialized J* buffer, and `n` bytes of `chunk`
+                // have been filled by the reader
+                Ok(n) => unsafe {
+                    ffi::jsrBufferAppend(&mut buf, chunk.as_ptr() as *const c_char, n)
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // SAFETY: `buf` is a valid and initialized J* buffer
+                    unsafe { ffi::jsrBufferFree(&mut buf) };
+                    return Err(e);
+                }
+            }
+        }
+        // SAFETY: `buf` is a valid and initialized J* buffer. `jsrBufferPush` takes ownership of
+        // its memory
+        unsafe { ffi::jsrBufferPush(&mut buf) };
+        Ok(())
+    }
+
+    /// Creates a new empty [Buffer] whose memory is managed by the J* allocator.
+    pub fn new_buffer(&self) -> Buffer<'_> {
+        let mut buf = ffi::JStarBuffer::default();
+        // SAFETY: `self.vm` is a valid J* vm pointer and `buf` is initialized before being used
+        unsafe { ffi::jsrBufferInit(self.vm, &mut buf) };
+        Buffer::new(buf, self)
+    }
+
+    /// Push a `List` of `Number`s onto the VM stack, one for each byte in `bytes`.
+    ///
+    /// This differs from [VM::push_string], that treats a byte slice as the contents of a J*
+    /// `String`, in that each byte becomes a separate element of the list. Use this method when the
+    /// bytes should be handled as an array of integers by J* code.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_byte_list(&self, bytes: &[u8]) {
+        self.push_number_list(bytes.iter().map(|&byte| byte as f64));
+    }
+
+    /// Push a `List` of `Number`s onto the VM stack, one for each element of `numbers`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_f64_slice(&self, numbers: &[f64]) {
+        self.push_number_list(numbers.iter().copied());
+    }
+
+    /// Pushes a `List` containing the `Number`s yielded by `numbers`.
+    fn push_number_list(&self, numbers: impl IntoIterator<Item = f64>) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushList(self.vm) };
+        // Make room for the elements before appending them to the list
+        self.ensure_stack(1);
+        for number in numbers {
+            // SAFETY: `self.vm` is a valid J* vm pointer, the list is at slot -2 after pushing the
+            // element, and the element is popped after being appended
+            unsafe {
+                ffi::jsrPushNumber(self.vm, number);
+                ffi::jsrListAppend(self.vm, -2);
+                ffi::jsrPop(self.vm);
+            }
+        }
+    }
+
+    /// Reads a whole `List` of `Number`s from the stack in one go.
+    ///
+    /// This is useful when exchanging numeric arrays with J* code.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Vec<f64>)` with the list elements if the value at `slot` is a `List` of `Number`s,
+    /// `Err(`[`Error::Runtime`]`)` otherwise, leaving a `TypeException` on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_f64_slice(&self, slot: Index) -> Result<Vec<f64>> {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        if !unsafe { ffi::jsrIsList(self.vm, slot) } {
+            self.raise("TypeException", "Value must be a List");
+            return Err(Error::Runtime);
+        }
+        let slot = self.abs_slot(slot);
+        self.ensure_stack(1);
+        // SAFETY: `self.vm` is a valid J* vm pointer and the value at `slot` is a List
+        let len = unsafe { ffi::jsrListGetLength(self.vm, slot) };
+        let mut numbers = Vec::with_capacity(len);
+        for i in 0..len {
+            // SAFETY: `self.vm` is a valid J* vm pointer, `i` is in bounds and the element is
+            // popped right after being read
+            let number = unsafe {
+                ffi::jsrListGet(self.vm, i, slot);
+                let number = ffi::jsrIsNumber(self.vm, -1).then(|| ffi::jsrGetNumber(self.vm, -1));
+                ffi::jsrPop(self.vm);
+                number
+            };
+            match number {
+                Some(number) => numbers.push(number),
+                None => {
+                    self.raise(
+                        "TypeException",
+                        &format!("List element {i} must be a Number"),
+                    );
+                    return Err(Error::Runtime);
+                }
+            }
+        }
+        Ok(numbers)
+    }
+
+    /// Performs `container[key] = value`, with `key` and `value` taken from the top of the stack
+    /// (`value` being the topmost one). The key and the value are not popped.
+    ///
+    /// The assignment goes through the container's `__set__` method, so it works for `List`s,
+    /// `Table`s and any class overloading the subscript operator. This is different from setting
+    /// a field, i.e. `container.key = value`.
+    ///
+    /// # Arguments
+    ///
+    /// * `container_slot` - The slot of the value to subscript.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the key and the value on top of the stack.
+    /// `Err(`[`Error::Runtime`]`)` in case of failure (e.g. an out of bounds `List` index),
+    /// leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `container_slot` underflows or overflows the stack, or if the stack
+    /// holds less than two elements (for the current stack frame).
+    pub fn index_set(&mut self, container_slot: Index) -> Result<()> {
+        assert!(
+            self.validate_slot(container_slot),
+            "`container_slot` out of bounds"
+        );
+        assert!(self.validate_slot(-2), "VM stack underflow");
+        let container_slot = self.abs_slot(container_slot);
+        self.ensure_stack(3);
+        // SAFETY: `self.vm` is a valid J* vm pointer and all pushed slots are valid. After pushing
+        // the container, the key and the value are at slots -3 and -2 respectively, and after
+        // pushing the key the value is at -3
+        unsafe {
+            ffi::jsrPushValue(self.vm, container_slot);
+            ffi::jsrPushValue(self.vm, -3);
+            ffi::jsrPushValue(self.vm, -3);
+        }
+        self.call_method("__set__", 2)?;
+        self.pop();
+        Ok(())
+    }
+
+    /// Pushes a new empty `Table` onto the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element.
+    pub fn push_table(&self) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushTable(self.vm) };
+    }
+
+    /// Returns wether or not the value at `slot` is a J* `Table`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_table(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsTable(self.vm, slot) }
+    }
+
+    /// Performs `table[key] = value` on the `Table` at `table_slot`, with the key at slot `-2` and
+    /// the value at slot `-1`. Just like [VM::index_set] and [VM::table_get], the key and the
+    /// value are not popped:
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// # let mut vm = VM::new(Conf::new()).init_runtime();
+    /// vm.push_table();
+    /// vm.push_string("width");
+    /// vm.push_number(80.0);
+    /// vm.table_put(-3).unwrap();
+    /// vm.pop_n(2);
+    /// ```
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the key and the value on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `table_slot` is not a `Table` or if the key is
+    /// not hashable, leaving an exception on top of the stack (above the key and the value).
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `table_slot` underflows or overflows the stack, or if the stack
+    /// holds less than two elements (for the current stack frame).
+    pub fn table_put(&mut self, table_slot: Index) -> Result<()> {
+        assert!(self.validate_slot(-2), "VM stack underflow");
+        if !self.is_table(table_slot) {
+            self.raise("TypeException", "Value must be a Table");
+            return Err(Error::Runtime);
+        }
+        self.index_set(table_slot)
+    }
+
+    /// Looks up the key at slot `-1` in the `Table` at `table_slot`, pushing the associated value,
+    /// or `null` if the key is absent. The key is not popped.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the value on top of the stack, right above the key.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `table_slot` is not a `Table` or if the key is
+    /// not hashable, leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `table_slot` underflows or overflows the stack, or if the stack is
+    /// empty (for the current stack frame).
+    pub fn table_get(&mut self, table_slot: Index) -> Result<()> {
+        assert!(self.validate_slot(-1), "VM stack underflow");
+        if !self.is_table(table_slot) {
+            self.raise("TypeException", "Value must be a Table");
+            return Err(Error::Runtime);
+        }
+        let table_slot = self.abs_slot(table_slot);
+        self.ensure_stack(2);
+        // SAFETY: `self.vm` is a valid J* vm pointer and `table_slot` is a valid slot. After
+        // pushing the table, the key is at slot -2
+        unsafe {
+            ffi::jsrPushValue(self.vm, table_slot);
+            ffi::jsrPushValue(self.vm, -2);
+        }
+        self.call_method("__get__", 1)
+    }
+
+    /// Calls `f` once for each entry of the `Table` at `table_slot`, with the key at slot `-2` and
+    /// the value at slot `-1`. `f` must leave the stack as it found it.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if all the entries were visited.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `table_slot` is not a `Table`, if the iteration
+    /// raised, if `f` failed or if it didn't leave the stack as it found it, leaving an exception
+    /// on top of the stack. In the latter cases the iteration stops at the failing entry.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn table_for_each(
+        &mut self,
+        table_slot: Index,
+        mut f: impl FnMut(&mut VM) -> Result<()>,
+    ) -> Result<()> {
+        if !self.is_table(table_slot) {
+            self.raise("TypeException", "Value must be a Table");
+            return Err(Error::Runtime);
+        }
+        self.in_frame(Some(table_slot), &mut |vm| {
+            vm.ensure_stack(4);
+            // SAFETY: `vm.vm` is a valid J* vm pointer and slot 2 holds the table. The iterator
+            // is kept in slot 3, and each entry's key and value in slots 4 and 5
+            unsafe {
+                ffi::jsrPushNull(vm.vm);
+                let mut err = false;
+                while ffi::jsrIter(vm.vm, 2, 3, &mut err) {
+                    if err || !ffi::jsrNext(vm.vm, 2, 3) {
+                        return Err(Error::Runtime);
+                    }
+                    ffi::jsrPushValue(vm.vm, 2);
+                    ffi::jsrPushValue(vm.vm, 4);
+                    vm.call_method("__get__", 1)?;
+                    let top = ffi::jsrTop(vm.vm);
+                    f(vm)?;
+                    if ffi::jsrTop(vm.vm) != top {
+                        crate::raise!(vm, "Exception", "`f` must leave the stack unchanged");
+                    }
+                    ffi::jsrPopN(vm.vm, 2);
+                }
+                if err {
+                    return Err(Error::Runtime);
+                }
+            }
+            Ok(())
+        })?;
+        self.pop();
+        Ok(())
+    }
+
+    /// Looks up `key` in the `Table` at `table_slot`, converting the associated value to `T`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(T)` with the converted value if the key is present, or `Ok(T::default())` if it is
+    /// absent (or associated with `null`).  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `table_slot` is not a `Table`, if the lookup
+    /// raised or if the value is not of type `T`, leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn table_get_or<T>(&mut self, table_slot: Index, key: impl ToJStar) -> Result<T>
+    where
+        T: for<'vm> FromJStar<'vm> + Default,
+    {
+        assert!(self.validate_slot(table_slot), "`table_slot` out of bounds");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        if !unsafe { ffi::jsrIsTable(self.vm, table_slot) } {
+            self.raise("TypeException", "Value must be a Table");
+            return Err(Error::Runtime);
+        }
+        let table_slot = self.abs_slot(table_slot);
+        self.ensure_stack(2);
+        // SAFETY: `self.vm` is a valid J* vm pointer and `table_slot` is a valid slot
+        unsafe { ffi::jsrPushValue(self.vm, table_slot) };
+        key.to_jstar(self);
+        self.call_method("__get__", 1)?;
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        if unsafe { ffi::jsrIsNull(self.vm, -1) } {
+            self.pop();
+            return Ok(T::default());
+        }
+        let res = T::from_jstar(self, -1);
+        self.pop();
+        res.ok_or_else(|| {
+            self.raise("TypeException", "Table value has the wrong type");
+            Error::Runtime
+        })
+    }
+
+    /// Pushes an owned [Value] onto the VM stack, recursively creating all nested collections.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` in case of success leaving the value on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if building a nested `Table` raised (e.g. one of its keys is
+    /// not hashable), leaving the exception on top of the stack in place of the value.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_value_owned(&self, value: &Value) -> Result<()> {
+        assert!(self.validate_stack(), "VM stack overflow");
+        match value {
+            // Partially built collections are discarded along with the frame on failure
+            Value::List(_) | Value::Tuple(_) | Value::Table(_) => {
+                self.in_frame(None, &mut |vm| vm.push_value_unchecked(value))
+            }
+            _ => self.push_value_unchecked(value),
+        }
+    }
+
+    /// Pushes `value` onto the stack. On failure the stack is left unbalanced, with the exception
+    /// on top, so collections must be pushed from a frame (see [VM::in_frame]).
+    fn push_value_unchecked(&self, value: &Value) -> Result<()> {
+        // SAFETY: `self.vm` is a valid J* vm pointer and we made sure there's space for the value.
+        // Collection elements are pushed after ensuring enough stack space, and popped as soon as
+        // they are inserted in the collection.
+        unsafe {
+            match value {
+                Value::Null => ffi::jsrPushNull(self.vm),
+                Value::Bool(b) => ffi::jsrPushBoolean(self.vm, *b),
+                Value::Number(n) => ffi::jsrPushNumber(self.vm, *n),
+                Value::String(bytes) => self.push_string(bytes),
+                Value::List(elems) => {
+                    ffi::jsrPushList(self.vm);
+                    self.ensure_stack(1);
+                    for elem in elems {
+                        self.push_value_unchecked(elem)?;
+                        ffi::jsrListAppend(self.vm, -2);
+                        ffi::jsrPop(self.vm);
+                    }
+                }
+                Value::Tuple(elems) => {
+                    self.ensure_stack(elems.len().max(1));
+                    for elem in elems {
+                        self.push_value_unchecked(elem)?;
+                    }
+                    ffi::jsrPushTuple(self.vm, elems.len());
+                }
+                Value::Table(entries) => {
+                    ffi::jsrPushTable(self.vm);
+                    self.ensure_stack(3);
+                    for (key, val) in entries {
+                        ffi::jsrDup(self.vm);
+                        self.push_value_unchecked(key)?;
+                        self.push_value_unchecked(val)?;
+                        let res = ffi::jsrCallMethod(self.vm, c"__set__".as_ptr(), 2);
+                        if let Ok(err) = res.try_into() {
+                            return Err(err);
+                        }
+                        ffi::jsrPop(self.vm);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses `json` and pushes the equivalent J* value onto the VM stack.
+    ///
+    /// JSON objects become `Table`s, arrays become `List`s, and all other values become the
+    /// corresponding J* primitive.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` in case of success leaving the value on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if `json` is malformed, leaving an `InvalidArgException` on
+    /// top of the stack, or if building the value raised, leaving the exception on top of the
+    /// stack in place of the partially built value.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    #[cfg(feature = "serde_json")]
+    pub fn push_json(&self, json: &str) -> Result<()> {
+        assert!(self.validate_stack(), "VM stack overflow");
+        match serde_json::from_str(json) {
+            // Partially built collections are discarded along with the frame on failure
+            Ok(value) => self.in_frame(None, &mut |vm| vm.push_json_value(&value)),
+            Err(err) => {
+                self.raise("InvalidArgException", &format!("Invalid JSON: {err}"));
+                Err(Error::Runtime)
+            }
+        }
+    }
+
+    /// Pushes `value` onto the stack. On failure the stack is left unbalanced, with the exception
+    /// on top, so this must be called from a frame (see [VM::in_frame]).
+    #[cfg(feature = "serde_json")]
+    fn push_json_value(&self, value: &serde_json::Value) -> Result<()> {
+        use serde_json::Value as Json;
+        // SAFETY: `self.vm` is a valid J* vm pointer and there's space for the value. Nested
+        // values are pushed after ensuring enough stack space, and popped as soon as they are
+        // inserted in their collection.
+        unsafe {
+            match value {
+                Json::Null => ffi::jsrPushNull(self.vm),
+                Json::Bool(b) => ffi::jsrPushBoolean(self.vm, *b),
+                Json::Number(n) => ffi::jsrPushNumber(self.vm, n.as_f64().unwrap_or(f64::NAN)),
+                Json::String(str) => self.push_string(str),
+                Json::Array(elems) => {
+                    ffi::jsrPushList(self.vm);
+                    self.ensure_stack(1);
+                    for elem in elems {
+                        self.push_json_value(elem)?;
+                        ffi::jsrListAppend(self.vm, -2);
+                        ffi::jsrPop(self.vm);
+                    }
+                }
+                Json::Object(entries) => {
+                    ffi::jsrPushTable(self.vm);
+                    self.ensure_stack(3);
+                    for (key, val) in entries {
+                        ffi::jsrDup(self.vm);
+                        self.push_string(key);
+                        self.push_json_value(val)?;
+                        let res = ffi::jsrCallMethod(self.vm, c"__set__".as_ptr(), 2);
+                        if let Ok(err) = res.try_into() {
+                            return Err(err);
+                        }
+                        ffi::jsrPop(self.vm);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Serializes the value at `slot` to JSON text.
+    ///
+    /// `Table`s become JSON objects, `List`s and `Tuple`s become arrays, and all other primitive
+    /// values become the corresponding JSON value. Integral `Number`s are emitted without a
+    /// fractional part.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(String)` with the serialized value in case of success.  
+    /// `Err(`[`Error::Runtime`]`)` if the value cannot be represented as JSON (e.g. it is a
+    /// function, an instance, a `Table` with non-`String` keys or a non finite `Number`), leaving
+    /// a `TypeException` on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    #[cfg(feature = "serde_json")]
+    pub fn to_json(&self, slot: Index) -> Result<std::string::String> {
+        let json = self
+            .get_value_owned(slot)
+            .ok_or_else(|| "value cannot be converted".to_owned())
+            .and_then(value_to_json);
+        match json {
+            Ok(json) => Ok(json.to_string()),
+            Err(msg) => {
+                self.raise("TypeException", &format!("Cannot serialize to JSON: {msg}"));
+                Err(Error::Runtime)
+            }
+        }
+    }
+
+    /// Gets an owned snapshot of the value at `slot`. See [Value].
+    ///
+    /// # Returns
+    ///
+    /// `Some(`[Value]`)` if the value at `slot` (and all of its elements, if it is a collection)
+    /// can be represented as a [Value], `None` otherwise. `None` is also returned for collections
+    /// nested too deeply, as it is the case with cyclic ones.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_value_owned(&self, slot: Index) -> Option<Value> {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        self.read_value(self.abs_slot(slot), 0)
+    }
+
+    fn read_value(&self, slot: Index, depth: usize) -> Option<Value> {
+        // SAFETY: `self.vm` is a valid J* vm pointer and `slot` is a valid absolute slot. Every
+        // element pushed while reading collections is popped before returning.
+        unsafe {
+            if ffi::jsrIsNull(self.vm, slot) {
+                return Some(Value::Null);
+            } else if ffi::jsrIsBoolean(self.vm, slot) {
+                return Some(Value::Bool(ffi::jsrGetBoolean(self.vm, slot)));
+            } else if ffi::jsrIsNumber(self.vm, slot) {
+                return Some(Value::Number(ffi::jsrGetNumber(self.vm, slot)));
+            } else if let Some(str) = self.get_string(slot) {
+                return Some(Value::String(str.as_bytes().to_vec()));
+            }
+
+            if depth >= MAX_VALUE_DEPTH {
+                return None;
+            }
+
+            if ffi::jsrIsList(self.vm, slot) || ffi::jsrIsTuple(self.vm, slot) {
+                let is_list = ffi::jsrIsList(self.vm, slot);
+                let len = if is_list {
+                    ffi::jsrListGetLength(self.vm, slot)
+                } else {
+                    ffi::jsrTupleGetLength(self.vm, slot)
+                };
+                self.ensure_stack(1);
+                let mut elems = Vec::with_capacity(len);
+                for i in 0..len {
+                    if is_list {
+                        ffi::jsrListGet(self.vm, i, slot);
+                    } else {
+                        ffi::jsrTupleGet(self.vm, i, slot);
+                    }
+                    let elem = self.read_value(ffi::jsrTop(self.vm), depth + 1);
+                    ffi::jsrPop(self.vm);
+                    elems.push(elem?);
+                }
+                Some(if is_list {
+                    Value::List(elems)
+                } else {
+                    Value::Tuple(elems)
+                })
+            } else if ffi::jsrIsTable(self.vm, slot) {
+                let mut entries = Vec::new();
+                self.with_table_entries(slot, |vm| {
+                    let top = ffi::jsrTop(vm.vm);
+                    let key = vm.read_value(top - 1, depth + 1)?;
+                    let val = vm.read_value(top, depth + 1)?;
+                    entries.push((key, val));
+                    Some(())
+                })?;
+                Some(Value::Table(entries))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Pushes a `List` containing the values yielded by `elems`, converted with [ToJStar].
+    ///
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// # let vm = VM::new(Conf::new()).init_runtime();
+    /// vm.push_list_from(&["a", "b", "c"]);
+    /// assert_eq!(vm.get_list_len(-1), Some(3));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push_list_from<'e, T: ToJStar + 'e>(&self, elems: impl IntoIterator<Item = &'e T>) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushList(self.vm) };
+        self.ensure_stack(1);
+        for elem in elems {
+            elem.to_jstar(self);
+            // SAFETY: `self.vm` is a valid J* vm pointer, the list is at slot -2 after pushing the
+            // element, and the element is popped after being appended
+            unsafe {
+                ffi::jsrListAppend(self.vm, -2);
+                ffi::jsrPop(self.vm);
+            }
+        }
+    }
+
+    /// Pushes a `Table` containing the entries yielded by `entries`, converted with [ToJStar].
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` in case of success leaving the table on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if one of the entries couldn't be set (e.g. its key is not
+    /// hashable), leaving the exception on top of the stack in place of the table.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element.
+    pub(crate) fn push_table_from<'e, K: ToJStar + 'e, V: ToJStar + 'e>(
+        &self,
+        entries: impl IntoIterator<Item = (&'e K, &'e V)>,
+    ) -> Result<()> {
+        assert!(self.validate_stack(), "VM stack overflow");
+        let mut entries = entries.into_iter();
+        // The partially built table is discarded along with the frame on failure
+        self.in_frame(None, &mut |vm| {
+            // SAFETY: `vm.vm` is a valid J* vm pointer
+            unsafe { ffi::jsrPushTable(vm.vm) };
+            vm.ensure_stack(3);
+            for (key, val) in &mut entries {
+                // SAFETY: `vm.vm` is a valid J* vm pointer and the table is on top of the stack
+                unsafe { ffi::jsrDup(vm.vm) };
+                key.to_jstar(vm);
+                val.to_jstar(vm);
+                vm.call_method("__set__", 2)?;
+                vm.pop();
+            }
+            Ok(())
+        })
+    }
+
+    /// Calls `f` once for each entry of the `Table` at `slot`, with the key at slot `-2` and the
+    /// value at slot `-1`. `f` must leave the stack as it found it.
+    ///
+    /// # Returns
+    ///
+    /// `Some(())` if all entries were visited, `None` if the value at `slot` is not a `Table`, if
+    /// the iteration raised or if `f` returned `None`. In all cases the stack is left as it was
+    /// before the call, exceptions included.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub(crate) fn with_table_entries(
+        &self,
+        slot: Index,
+        mut f: impl FnMut(&Self) -> Option<()>,
+    ) -> Option<()> {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        if !unsafe { ffi::jsrIsTable(self.vm, slot) } {
+            return None;
+        }
+        let slot = self.abs_slot(slot);
+        self.ensure_stack(4);
+        // SAFETY: `self.vm` is a valid J* vm pointer and `slot` is a valid absolute slot holding
+        // a `Table`. Whatever is left on the stack, including exceptions raised by the iteration
+        // protocol, is popped before returning
+        unsafe {
+            let base = ffi::jsrTop(self.vm);
+            ffi::jsrPushNull(self.vm);
+            let mut err = false;
+            let res = loop {
+                if !ffi::jsrIter(self.vm, slot, -1, &mut err) {
+                    break (!err).then_some(());
+                }
+                if err || !ffi::jsrNext(self.vm, slot, -1) {
+                    break None;
+                }
+                ffi::jsrPushValue(self.vm, slot);
+                ffi::jsrPushValue(self.vm, -2);
+                let res = ffi::jsrCallMethod(self.vm, c"__get__".as_ptr(), 1);
+                if !matches!(res, ffi::JStarResult::Success) || f(self).is_none() {
+                    break None;
+                }
+                ffi::jsrPopN(self.vm, 2);
+            };
+            ffi::jsrPopN(self.vm, ffi::jsrTop(self.vm) - base);
+            res
+        }
+    }
+
+    /// Sorts the `List` at `list_slot` in place using the Rust comparator `cmp`.
+    ///
+    /// The comparator is called with the two elements to compare on top of the stack, the first
+    /// at slot `-2` and the second at slot `-1`, and must leave the stack as it found it. The sort
+    /// is stable.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the sort succeded.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `list_slot` is not a `List`, if the comparator
+    /// failed or if it didn't leave the stack as it found it, leaving an exception on top of the
+    /// stack. In the latter cases the sort is aborted and the list is left unchanged.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn list_sort_by(
+        &mut self,
+        list_slot: Index,
+        mut cmp: impl FnMut(&mut VM) -> Result<std::cmp::Ordering>,
+    ) -> Result<()> {
+        if !self.is_list(list_slot) {
+            self.raise("TypeException", "Value must be a List");
+            return Err(Error::Runtime);
+        }
+        self.in_frame(Some(list_slot), &mut |vm| {
+            // SAFETY: `vm.vm` is a valid J* vm pointer and slot 2 holds the list. The elements are
+            // pushed in slots `3..3 + len`, and every other temporary is popped before returning
+            unsafe {
+                let len = ffi::jsrListGetLength(vm.vm, 2);
+                vm.ensure_stack(len + 2);
+                for i in 0..len {
+                    ffi::jsrListGet(vm.vm, i, 2);
+                }
+
+                let elem_slot = |i: usize| i as Index + 3;
+                let mut indices: Vec<usize> = (0..len).collect();
+                merge_sort(&mut indices, |i, j| {
+                    ffi::jsrPushValue(vm.vm, elem_slot(i));
+                    ffi::jsrPushValue(vm.vm, elem_slot(j));
+                    let top = ffi::jsrTop(vm.vm);
+                    let ord = cmp(vm)?;
+                    let new_top = ffi::jsrTop(vm.vm);
+                    if new_top != top {
+                        // Discard what the comparator left behind, the frame is dropped anyway
+                        if new_top > top {
+                            ffi::jsrPopN(vm.vm, new_top - top);
+                        }
+                        vm.ensure_stack(1);
+                        crate::raise!(vm, "Exception", "`cmp` must leave the stack unchanged");
+                    }
+                    ffi::jsrPopN(vm.vm, 2);
+                    Ok(ord)
+                })?;
+
+                for (i, &elem) in indices.iter().enumerate() {
+                    ffi::jsrPushValue(vm.vm, elem_slot(elem));
+                    ffi::jsrListSet(vm.vm, i, 2);
+                    ffi::jsrPop(vm.vm);
+                }
+            }
+            Ok(())
+        })?;
+        self.pop();
+        Ok(())
+    }
+
+    /// Builds a new `List` by applying the Rust transform `f` to each element of the `List` at
+    /// `list_slot`.
+    ///
+    /// `f` is called with the element on top of the stack, and must push exactly one value: the
+    /// transformed element.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the new `List` on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `list_slot` is not a `List`, if `f` failed or
+    /// if it didn't push exactly one value, leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn list_map(
+        &mut self,
+        list_slot: Index,
+        mut f: impl FnMut(&mut VM) -> Result<()>,
+    ) -> Result<()> {
+        if !self.is_list(list_slot) {
+            self.raise("TypeException", "Value must be a List");
+            return Err(Error::Runtime);
+        }
+        self.in_frame(Some(list_slot), &mut |vm| {
+            // SAFETY: `vm.vm` is a valid J* vm pointer, slot 2 holds the source list and the new
+            // list is pushed in slot 3
+            unsafe {
+                ffi::jsrPushList(vm.vm);
+                let mut i = 0;
+                // The length is read on every iteration, as `f` may shrink the source list
+                while i < ffi::jsrListGetLength(vm.vm, 2) {
+                    vm.ensure_stack(2);
+                    ffi::jsrListGet(vm.vm, i, 2);
+                    let top = ffi::jsrTop(vm.vm);
+                    f(vm)?;
+                    if ffi::jsrTop(vm.vm) != top + 1 {
+                        crate::raise!(vm, "Exception", "`f` must push exactly one value");
+                    }
+                    ffi::jsrListAppend(vm.vm, 3);
+                    ffi::jsrPopN(vm.vm, 2);
+                    i += 1;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Builds a new `List` containing only the elements of the `List` at `list_slot` for which
+    /// the Rust predicate `f` returns `true`.
+    ///
+    /// `f` is called with the element on top of the stack, and must leave the stack as it found
+    /// it.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the new `List` on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `list_slot` is not a `List`, if `f` failed or
+    /// if it didn't leave the stack as it found it, leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn list_filter(
+        &mut self,
+        list_slot: Index,
+        mut f: impl FnMut(&mut VM) -> Result<bool>,
+    ) -> Result<()> {
+        if !self.is_list(list_slot) {
+            self.raise("TypeException", "Value must be a List");
+            return Err(Error::Runtime);
+        }
+        self.in_frame(Some(list_slot), &mut |vm| {
+            // SAFETY: `vm.vm` is a valid J* vm pointer, slot 2 holds the source list and the new
+            // list is pushed in slot 3
+            unsafe {
+                ffi::jsrPushList(vm.vm);
+                let mut i = 0;
+                // The length is read on every iteration, as `f` may shrink the source list
+                while i < ffi::jsrListGetLength(vm.vm, 2) {
+                    vm.ensure_stack(1);
+                    ffi::jsrListGet(vm.vm, i, 2);
+                    let top = ffi::jsrTop(vm.vm);
+                    let keep = f(vm)?;
+                    if ffi::jsrTop(vm.vm) != top {
+                        crate::raise!(vm, "Exception", "`f` must leave the stack unchanged");
+                    }
+                    if keep {
+                        ffi::jsrListAppend(vm.vm, 3);
+                    }
+                    ffi::jsrPop(vm.vm);
+                    i += 1;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns wether or not the value at `slot` is a J* `List`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_list(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsList(self.vm, slot) }
+    }
+
+    /// Gets the length of the J* `List` at `slot`.
+    ///
+    /// # Returns
+    ///
+    /// `Some(len)` if the value at `slot` is a `List`, `None` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_list_len(&self, slot: Index) -> Option<usize> {
+        if !self.is_list(slot) {
+            None
+        } else {
+            // SAFETY: `self.vm` is a valid J* vm pointer and the value at `slot` is a List
+            Some(unsafe { ffi::jsrListGetLength(self.vm, slot) })
+        }
+    }
+
+    /// Temporarily pushes the `i`th element of the `List` at `slot` onto the stack and calls `f`
+    /// with it on top of the stack. The element is popped once `f` returns.
+    ///
+    /// The element is popped through a shared borrow, so `f` must not return values borrowed from
+    /// the vm: this is only used by conversions to owned types.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the value at `slot` is not a `List`, if `i` is out of bounds or if
+    /// `f` doesn't leave the stack as it found it.
+    pub(crate) fn with_list_elem<R>(&self, slot: Index, i: usize, f: impl FnOnce(&Self) -> R) -> R {
+        let len = self
+            .get_list_len(slot)
+            .expect("value at `slot` is not a List");
+        assert!(i < len, "list index out of bounds");
+        self.ensure_stack(1);
+        // SAFETY: `self.vm` is a valid J* vm pointer, `slot` is a list and `i` is in bounds
+        let top = unsafe {
+            ffi::jsrListGet(self.vm, i, slot);
+            ffi::jsrTop(self.vm)
+        };
+        let res = f(self);
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        assert_eq!(top, unsafe { ffi::jsrTop(self.vm) }, "unbalanced stack");
+        // SAFETY: `self.vm` is a valid J* vm pointer and the element is on top of the stack
+        unsafe { ffi::jsrPop(self.vm) };
+        res
+    }
+
+    /// Returns an iterator over the elements of the `List` at `slot`, converted to owned `T`s.
+    ///
+    /// Each element is pushed on top of the stack, converted and popped again before being
+    /// yielded, so the stack is left as it was found at every step. Elements that can't be
+    /// converted to a `T` are yielded as `None`. Use [Value] as `T` to iterate over lists of
+    /// mixed types.
+    ///
+    /// ```
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// # let mut vm = VM::new(Conf::new()).init_runtime();
+    /// vm.eval("<string>", "var list = [1, 2, 3]").unwrap();
+    /// vm.get_global(jstar::MAIN_MODULE, "list").unwrap();
+    /// let sum: f64 = vm.list_iter::<f64>(-1).unwrap().flatten().sum();
+    /// assert_eq!(sum, 6.0);
+    /// ```
+    ///
+    /// # Returns
+    ///
+    /// `Some(`[ListIter]`)` if the value at `slot` is a `List`, `None` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn list_iter<T>(&self, slot: Index) -> Option<ListIter<'_, T>>
+    where
+        T: for<'v> FromJStar<'v>,
+    {
+        if !self.is_list(slot) {
+            return None;
+        }
+        Some(ListIter {
+            list: self.abs_slot(slot),
+            index: 0,
+            vm: self,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Pushes a new empty `List` onto the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element.
+    pub fn push_list(&self) {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushList(self.vm) };
+    }
+
+    /// Pops the value on top of the stack and appends it to the `List` at `list_slot`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the value was appended.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `list_slot` is not a `List`. In this case the
+    /// value is still popped, and a `TypeException` is left on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the stack is empty or if `list_slot` underflows or overflows the
+    /// stack (for the current stack frame).
+    pub fn list_append(&mut self, list_slot: Index) -> Result<()> {
+        assert!(self.validate_slot(-1), "VM stack underflow");
+        if !self.is_list(list_slot) {
+            self.pop();
+            self.raise("TypeException", "Value must be a List");
+            return Err(Error::Runtime);
+        }
+        // SAFETY: `self.vm` is a valid J* vm pointer, the value at `list_slot` is a List and the
+        // appended value is on top of the stack
+        unsafe {
+            ffi::jsrListAppend(self.vm, list_slot);
+            ffi::jsrPop(self.vm);
+        }
+        Ok(())
+    }
+
+    /// Pushes the element at `index` of the `List` at `list_slot` onto the stack.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the element was pushed.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `list_slot` is not a `List` or if `index` is out
+    /// of bounds, leaving a `TypeException` or an `IndexOutOfBoundException` on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `list_slot` underflows or overflows the stack (for the current stack
+    /// frame) or if there isn't enough stack space for one element.
+    pub fn list_get(&self, list_slot: Index, index: usize) -> Result<()> {
+        assert!(self.validate_stack(), "VM stack overflow");
+        if let Some((cls, msg)) = self.list_index_error(list_slot, index) {
+            self.raise(cls, &msg);
+            return Err(Error::Runtime);
+        }
+        // SAFETY: `self.vm` is a valid J* vm pointer, the value at `list_slot` is a List and
+        // `index` is in bounds
+        unsafe { ffi::jsrListGet(self.vm, index, list_slot) };
+        Ok(())
+    }
+
+    /// Pops the value on top of the stack and stores it at `index` in the `List` at `list_slot`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the element was set.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `list_slot` is not a `List` or if `index` is out
+    /// of bounds. In this case the value is still popped, and a `TypeException` or an
+    /// `IndexOutOfBoundException` is left on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the stack is empty or if `list_slot` underflows or overflows the
+    /// stack (for the current stack frame).
+    pub fn list_set(&mut self, list_slot: Index, index: usize) -> Result<()> {
+        assert!(self.validate_slot(-1), "VM stack underflow");
+        if let Some((cls, msg)) = self.list_index_error(list_slot, index) {
+            self.pop();
+            self.raise(cls, &msg);
+            return Err(Error::Runtime);
+        }
+        // SAFETY: `self.vm` is a valid J* vm pointer, the value at `list_slot` is a List, `index`
+        // is in bounds and the value to set is on top of the stack
+        unsafe {
+            ffi::jsrListSet(self.vm, index, list_slot);
+            ffi::jsrPop(self.vm);
+        }
+        Ok(())
+    }
+
+    /// Returns the exception class and message to raise if the value at `list_slot` is not a
+    /// `List` or if `index` is out of its bounds.
+    fn list_index_error(&self, list_slot: Index, index: usize) -> Option<(&'static str, String)> {
+        match self.get_list_len(list_slot) {
+            None => Some(("TypeException", "Value must be a List".to_owned())),
+            Some(len) if index >= len => Some((
+                "IndexOutOfBoundException",
+                format!("List index out of bounds: {index} (length {len})"),
+            )),
+            Some(_) => None,
+        }
+    }
+
+    /// Pops `size` elements from the VM stack and pushes a `Tuple` containing them, in stack
+    /// order.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the stack holds less than `size` elements (for the current stack
+    /// frame).
+    pub fn push_tuple(&self, size: usize) {
+        assert!(
+            size == 0 || self.validate_slot(-(size as i32)),
+            "VM stack underflow"
+        );
+        assert!(size > 0 || self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer and the stack holds `size` elements
+        unsafe { ffi::jsrPushTuple(self.vm, size) };
+    }
+
+    /// Returns wether or not the value at `slot` is a J* `Tuple`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_tuple(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsTuple(self.vm, slot) }
+    }
+
+    /// Gets the length of the J* `Tuple` at `slot`.
+    ///
+    /// # Returns
+    ///
+    /// `Some(len)` if the value at `slot` is a `Tuple`, `None` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_tuple_len(&self, slot: Index) -> Option<usize> {
+        if !self.is_tuple(slot) {
+            None
+        } else {
+            // SAFETY: `self.vm` is a valid J* vm pointer and the value at `slot` is a Tuple
+            Some(unsafe { ffi::jsrTupleGetLength(self.vm, slot) })
+        }
+    }
+
+    /// Temporarily pushes the `i`th element of the `Tuple` at `slot` onto the stack and calls `f`
+    /// with it on top of the stack. The element is popped once `f` returns.
+    ///
+    /// The element is popped through a shared borrow, so `f` must not return values borrowed from
+    /// the vm: this is only used by conversions to owned types.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the value at `slot` is not a `Tuple`, if `i` is out of bounds or if
+    /// `f` doesn't leave the stack as it found it.
+    pub(crate) fn with_tuple_elem<R>(
+        &self,
+        slot: Index,
+        i: usize,
+        f: impl FnOnce(&Self) -> R,
+    ) -> R {
+        let len = self
+            .get_tuple_len(slot)
+            .expect("value at `slot` is not a Tuple");
+        assert!(i < len, "tuple index out of bounds");
+        self.ensure_stack(1);
+        // SAFETY: `self.vm` is a valid J* vm pointer, `slot` is a tuple and `i` is in bounds
+        let top = unsafe {
+            ffi::jsrTupleGet(self.vm, i, slot);
+            ffi::jsrTop(self.vm)
+        };
+        let res = f(self);
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        assert_eq!(top, unsafe { ffi::jsrTop(self.vm) }, "unbalanced stack");
+        // SAFETY: `self.vm` is a valid J* vm pointer and the element is on top of the stack
+        unsafe { ffi::jsrPop(self.vm) };
+        res
+    }
+
+    /// Returns wether or not the value at `slot` is a J* `String`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_string(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrIsString(self.vm, slot) }
+    }
+
+    /// Gets a J* `String` from the stack.
+    ///
+    /// # Returns
+    ///
+    /// `Some(`[JStarString]`)` if the value at `slot` is a `String`, `None` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_string(&self, slot: Index) -> Option<JStarString> {
+        if !self.is_string(slot) {
+            None
+        } else {
+            // SAFETY: `slot` is a valid slot per check above, and its a `Number`
+            let data = unsafe { ffi::jsrGetString(self.vm, slot) };
+            let len = unsafe { ffi::jsrGetStringSz(self.vm, slot) };
+            Some(JStarString::new(data, len, self.abs_slot(slot)))
+        }
+    }
+
+    /// Gets the raw bytes of a J* `String` from the stack.
+    ///
+    /// This is a lighter-weight alternative to [VM::get_string] for when only a byte slice is
+    /// needed. As with [JStarString], the returned slice borrows the [VM], so the stack cannot be
+    /// mutated while it is held.
+    ///
+    /// # Returns
+    ///
+    /// `Some(&[u8])` if the value at `slot` is a `String`, `None` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_bytes(&self, slot: Index) -> Option<&[u8]> {
+        if !self.is_string(slot) {
+            None
+        } else {
+            // SAFETY: `slot` is a valid slot per check above, and its a `String`. The returned
+            // slice borrows `self`, so the string cannot be popped while the slice is alive
+            unsafe {
+                let data = ffi::jsrGetString(self.vm, slot);
+                let len = ffi::jsrGetStringSz(self.vm, slot);
+                Some(std::slice::from_raw_parts(data as *const u8, len))
+            }
+        }
+    }
+
+    /// Gets a J* `String` from the stack, checking that it is a `String` and leaving a
+    /// `TypeException` on top of the stack if it is not.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(`[`JStarString`]`)` if the value at `slot` is a `Number`.  
+    /// `Err(`[`Error::Runtime`]`)` otherwise, leaving a `TypeException` on the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn check_string(&self, slot: Index, name: &str) -> Result<JStarString> {
+        assert!(self.validate_slot(slot), "VM stack overflow");
+        let name = CString::new(name)?;
+        if !unsafe { ffi::jsrCheckString(self.vm, slot, name.as_ptr()) } {
+            Err(Error::Runtime)
+        } else {
+            let data = unsafe { ffi::jsrGetString(self.vm, slot) };
+            let len = unsafe { ffi::jsrGetStringSz(self.vm, slot) };
+            Ok(JStarString::new(data, len, self.abs_slot(slot)))
+        }
+    }
+
+    /// Returns wether or not the value at `slot` is a J* `Class`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_class(&self, slot: Index) -> bool {
+        assert!(self.validate_slot(slot), "`slot` out of bounds");
+        let slot = self.abs_slot(slot);
+        self.ensure_stack(1);
+        // `Class` is the class of all classes, so we check with `is` against it
+        let res = self.get_global(CORE_MODULE, "Class").is_ok()
+            // SAFETY: `self.vm` is a valid J* vm pointer and both slots are valid
+            && unsafe { ffi::jsrIs(self.vm, slot, -1) };
+        // SAFETY: `get_global` always leaves a value on the stack (the class or an exception)
+        unsafe { ffi::jsrPop(self.vm) };
+        res
+    }
+
+    /// Checks that the value at `slot` is a J* `Class`, leaving a `TypeException` on top of the
+    /// stack if it is not.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the value at `slot` is a `Class`, `Err(`[`Error::Runtime`]`)` otherwise, leaving
+    /// a `TypeException` on the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn check_class(&self, slot: Index, name: &str) -> Result<()> {
+        if !self.is_class(slot) {
+            self.raise("TypeException", &format!("{name} must be a Class"));
+            Err(Error::Runtime)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns wether or not the `Class` at `cls_slot` is a subclass of the class
+    /// `parent_name` defined in module `parent_module`. A class is considered a subclass of
+    /// itself.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(bool)` with the result of the check.  
+    /// `Err(`[`Error::Runtime`]`)` if the value at `cls_slot` is not a `Class` or if the parent
+    /// class cannot be found, leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn is_subclass(
+        &mut self,
+        cls_slot: Index,
+        parent_module: &str,
+        parent_name: &str,
+    ) -> Result<bool> {
+        self.check_class(cls_slot, "cls")?;
+        let cls_slot = self.abs_slot(cls_slot);
+        self.ensure_stack(4);
+        self.get_helper("isSubclass")?;
+        // SAFETY: `self.vm` is a valid J* vm pointer and `cls_slot` is valid
+        unsafe { ffi::jsrPushValue(self.vm, cls_slot) };
+        self.push_string(parent_module);
+        self.push_string(parent_name);
+        self.call(3)?;
+        // SAFETY: `self.vm` is a valid J* vm pointer and `isSubclass` always returns a boolean
+        let res = unsafe { ffi::jsrGetBoolean(self.vm, -1) };
+        self.pop();
+        Ok(res)
+    }
+
+    /// Get a global variable `name` from module `module_name`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` in case of success leaving the value on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` in case of failure leaving an exception on top of the stack.  
+    /// `Err(`[`Error::InvalidString`]`)` if `module_name` or `name` contain a NUL byte.
+    pub fn get_global(&self, module_name: &str, name: &str) -> Result<()> {
+        // TODO: check that `module_name` exists. New J* apis should be added for this.
+        assert!(self.validate_stack());
+        let module_name = CString::new(module_name)?;
+        let name = CString::new(name)?;
+        let res = unsafe { ffi::jsrGetGlobal(self.vm, module_name.as_ptr(), name.as_ptr()) };
+        if !res {
+            Err(Error::Runtime)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sets a global variable `name` in module `module_name` with the value on top of the stack.
+    /// The value is not popped.
+    ///
+    /// # Arguments
+    ///
+    /// * `module_name` - The name of the module in which to set the global. Could be any valid J*
+    ///   module name or [CORE_MODULE](../constant.CORE_MODULE.html)/[MAIN_MODULE](../constant.MAIN_MODULE.html)
+    ///   for the two built-in modules.
+    /// * `name` - The name of the global variable to set.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the value on top of the stack.
+    /// `Err(`[`Error::Runtime`]`)` in case of failure, leaving an exception on top of the stack.  
+    /// `Err(`[`Error::InvalidString`]`)` if `module_name` or `name` contain a NUL byte.
+    pub fn set_global(&self, module_name: &str, name: &str) -> Result<()> {
+        // TODO: check that `module_name` exists. New J* apis should be added for this.
+        assert!(self.validate_slot(-1));
+        if self.trampolines().frozen_modules.contains(module_name) {
+            self.raise(
+                "NameException",
+                &format!("Cannot set global `{name}`, module `{module_name}` is frozen"),
+            );
+            return Err(Error::Runtime);
+        }
+        let module_name = CString::new(module_name)?;
+        let name = CString::new(name)?;
+        let res = unsafe { ffi::jsrSetGlobal(self.vm, module_name.as_ptr(), name.as_ptr()) };
+        if !res {
+            Err(Error::Runtime)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gets the global variable `name` from module `module_name`, initializing it with the value
+    /// returned by `init` if it doesn't exist yet.
+    ///
+    /// This is useful to cache the result of an expensive setup in the J* namespace, as `init` is
+    /// only called the first time the global is requested.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` in case of success leaving the value of the global on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if the global couldn't be set (e.g. the module doesn't exist
+    /// or is frozen), leaving an exception on top of the stack.  
+    /// `Err(`[`Error::InvalidString`]`)` if `module_name` or `name` contain a nul byte, leaving
+    /// the stack untouched.
+    pub fn get_or_init_global<T: ToJStar>(
+        &mut self,
+        module_name: &str,
+        name: &str,
+        init: impl FnOnce() -> T,
+    ) -> Result<()> {
+        match self.get_global(module_name, name) {
+            Ok(()) => return Ok(()),
+            // Pop the exception raised by the failed lookup
+            Err(Error::Runtime) => self.pop(),
+            // Other errors (such as a name containing a nul byte) don't leave an exception
+            Err(err) => return Err(err),
+        }
+        init().to_jstar(self);
+        self.set_global(module_name, name)
+    }
+
+    /// Get the class `name` from module `module_name`.
+    ///
+    /// The class can then be used, for example, to create new instances with [VM::new_instance].
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` in case of success leaving the class on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if the global doesn't exist or it is not a `Class`, leaving an
+    /// exception on top of the stack.
+    pub fn get_class(&self, module_name: &str, name: &str) -> Result<()> {
+        self.get_global(module_name, name)?;
+        if !self.is_class(-1) {
+            // SAFETY: `self.vm` is a valid J* vm pointer and `get_global` pushed a value
+            unsafe { ffi::jsrPop(self.vm) };
+            self.raise(
+                "TypeException",
+                &format!("`{name}` in module `{module_name}` is not a Class"),
+            );
+            return Err(Error::Runtime);
+        }
+        Ok(())
+    }
+
+    /// Freezes the module `module_name`, preventing further modifications of its globals.
+    ///
+    /// After this call, [VM::set_global] (and all the methods built on top of it, like
+    /// [VM::register_native]) will fail when targeting `module_name`, leaving a `NameException` on
+    /// top of the stack. This is useful to lock down an environment prepared from Rust before
+    /// running untrusted code in it.
+    ///
+    /// Keep in mind that the J* runtime doesn't provide a way to intercept global assignments, so
+    /// this only guards writes performed through the `VM` API: J* code evaluated in the module is
+    /// still able to reassign its globals.
+    pub fn freeze_module(&mut self, module_name: &str) {
+        self.trampolines_mut()
+            .frozen_modules
+            .insert(module_name.to_owned());
+    }
+
+    /// Reloads the module `module_name`, resolving it again through the import callback (see
+    /// [Conf::import_callback]) and re-running its code, so that changes to its source take effect
+    /// without restarting the vm.
+    ///
+    /// J* provides no way to discard a module object, so the new code is run inside the existing
+    /// module. This means that:
+    /// * Globals defined by the new code replace the old ones, and are seen by everyone reading
+    ///   them from the module (e.g. `module.name` or functions defined in the module).
+    /// * Values previously read from the module keep referring to the old objects. For example, a
+    ///   function obtained before the reload still runs its old code.
+    /// * Globals that are no longer defined by the new code keep their old value.
+    ///
+    /// If the module wasn't imported yet, it is created.
+    ///
+    /// As for imports, the import observer (see [Conf::import_observer]) is notified of the
+    /// outcome of the resolution. The `native` functions of the new code are resolved through the
+    /// native registry the module was imported with, as J* can't replace the registry of an
+    /// existing module. For this reason the resolved [Module] must carry either no registry or the
+    /// same one it was first imported with.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the module was reloaded.  
+    /// `Err(`[`Error::Runtime`]`)` if the import callback couldn't resolve the module or if it
+    /// resolved it with a different native registry, leaving an `ImportException` on top of the
+    /// stack.  
+    /// Any other error returned by [VM::eval_in_module] while running the new code.
+    pub fn reload_module(&mut self, module_name: &str) -> Result<()> {
+        let Some(mut import_callback) = self.trampolines_mut().import_callback.take() else {
+            self.raise(
+                "ImportException",
+                &format!("Cannot reload module `{module_name}`: no import callback"),
+            );
+            return Err(Error::Runtime);
+        };
+        let module = import_callback(self, module_name);
+        let trampolines = self.trampolines_mut();
+        trampolines.import_callback = Some(import_callback);
+        if let Some(ref mut import_observer) = trampolines.import_observer {
+            import_observer(module_name, module.is_some());
+        }
+
+        let Some(module) = module else {
+            self.raise(
+                "ImportException",
+                &format!("Cannot reload module `{module_name}`: not found"),
+            );
+            return Err(Error::Runtime);
+        };
+        let (code, path, reg) = match module {
+            Module::Source { src, path, reg } => (src.into_bytes(), path, reg),
+            Module::Binary { code, path, reg } => (code, path, reg),
+        };
+        let imported_reg = self.trampolines().native_regs.get(module_name).copied();
+        if !reg.is_null() && imported_reg != Some(reg) {
+            self.raise(
+                "ImportException",
+                &format!("Cannot reload module `{module_name}`: its native registry changed"),
+            );
+            return Err(Error::Runtime);
+        }
+        self.eval_in_module(&path.to_string_lossy(), module_name, code)
+    }
+
+    /// Pushes a naive function onto the stack.
+    ///
+    /// See [crate::native!] for utility functions and macros to create natives.
+    ///
+    /// # Arguments
+    ///
+    /// * `module` - The name of the module the function belongs to
+    /// * `name` - The name of the function
+    /// * `func` - The native function to push
+    /// * `argc` - The number of arguments the function takes
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the native function on top of the stack.
+    /// `Err(`[`Error::Runtime`]`)` in case of failure, leaving an exception on top of the stack.  
+    /// `Err(`[`Error::InvalidString`]`)` if `module` or `name` contain a NUL byte.
+    pub fn push_native(
+        &self,
+        module: &str,
+        name: &str,
+        func: ffi::JStarNative,
+        argc: u8,
+    ) -> Result<()> {
+        let module = CString::new(module)?;
+        let name = CString::new(name)?;
+        let res =
+            unsafe { ffi::jsrPushNative(self.vm, module.as_ptr(), name.as_ptr(), func, argc) };
+        if !res {
+            Err(Error::Runtime)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Registers a native function in the global scope of module `module`.
+    ///
+    /// This is a convenience method that is mostly equivalent to doing:
+    /// ```rust
+    /// # use jstar::{vm::VM, conf::Conf, native};
+    /// # let mut vm = VM::new(Conf::new()).init_runtime();
+    /// # let module = "module";
+    /// # let name = "func_name";
+    /// # let argc = 0;
+    /// # native!(fn func(vm) { Ok(()) });
+    /// vm.push_native(module, name, func, argc);
+    /// vm.set_global(module, name);
+    /// vm.pop();
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `module` - The name of the module the function belongs to
+    /// * `name` - The name the function will be bound to
+    /// * `func` - The native function to register
+    /// * `argc` - The number of arguments the function takes
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success.
+    /// `Err(`[`Error::Runtime`]`)` in case of failure, leaving an exception on top of the stack.  
+    /// `Err(`[`Error::InvalidString`]`)` if `module` or `name` contain a NUL byte.
+    pub fn register_native(
+        &self,
+        module: &str,
+        name: &str,
+        func: ffi::JStarNative,
+        argc: u8,
+    ) -> Result<()> {
+        self.push_native(module, name, func, argc)?;
+        self.set_global(module, name)?;
+        // SAFETY: `self.vm` is a valid J* vm pointer and we are guaranteed that the stack will
+        // not underflow (we just pushed ane element)
+        unsafe { ffi::jsrPop(self.vm) };
+        Ok(())
+    }
+
+    /// Registers a whole table of native functions in `module`, as if by calling
+    /// [VM::register_native] for each `(name, func, argc)` entry in `natives`.
+    ///
+    /// This is handy when exposing a library's worth of functions at once:
+    /// ```rust
+    /// # use jstar::{vm::VM, conf::Conf, native};
+    /// # let vm = VM::new(Conf::new()).init_runtime();
+    /// native!(fn open(vm) { Ok(()) });
+    /// native!(fn close(vm) { Ok(()) });
+    /// vm.register_natives("file", &[("open", open, 1), ("close", close, 1)]).unwrap();
+    /// ```
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if all the functions were registered.  
+    /// `Err(`[`Error::Runtime`]`)` as soon as one registration fails, leaving an exception on top
+    /// of the stack. Functions registered before the failing one stay registered.
+    pub fn register_natives(
+        &self,
+        module: &str,
+        natives: &[(&str, ffi::JStarNative, u8)],
+    ) -> Result<()> {
+        natives
+            .iter()
+            .try_for_each(|&(name, func, argc)| self.register_native(module, name, func, argc))
+    }
+
+    /// Executes `f` with a [ModuleScope] targeting `module`.
+    ///
+    /// The scope provides `eval`, `get_global` and `set_global` methods that operate on `module`
+    /// without having to repeat its name, and can be used as a normal [VM] for everything else.
+    ///
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM, convert::{FromJStar, ToJStar}};
+    /// let mut vm = VM::new(Conf::new()).init_runtime();
+    /// let sum = vm.with_module("config", |m| {
+    ///     m.eval("<config>", "var width, height = 80, 24")?;
+    ///     m.get_global("width")?;
+    ///     m.get_global("height")?;
+    ///     let sum = i32::from_jstar(m, -2).unwrap() + i32::from_jstar(m, -1).unwrap();
+    ///     m.pop_n(2);
+    ///     Ok::<_, jstar::error::Error>(sum)
+    /// });
+    /// assert_eq!(sum.unwrap(), 104);
+    /// ```
+    pub fn with_module<R>(
+        &mut self,
+        module: &str,
+        f: impl FnOnce(&mut ModuleScope<'_, 'a>) -> R,
+    ) -> R {
+        f(&mut ModuleScope { vm: self, module })
+    }
+
+    /// Returns a [ModuleBuilder] that can be used to define a module implemented in Rust, which
+    /// can then be imported by J* code.
+    ///
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM, native, convert::{FromJStar, ToJStar}};
+    /// let mut vm = VM::new(Conf::new()).init_runtime();
+    /// native!(fn double(vm) {
+    ///     let n = f64::from_jstar_checked(vm, 1, "n")?;
+    ///     (n * 2.0).to_jstar(vm);
+    ///     Ok(())
+    /// });
+    ///
+    /// let mut builder = vm.create_module("util");
+    /// builder.global("answer", 42).native("double", double, 1);
+    /// builder.install().unwrap();
+    ///
+    /// vm.eval("<string>", "import util
+    ///     std.assert(util.double(util.answer) == 84)").unwrap();
+    /// ```
+    pub fn create_module<'b>(&'b mut self, name: &str) -> ModuleBuilder<'b, 'a> {
+        ModuleBuilder {
+            vm: self,
+            name: name.to_owned(),
+            globals: Vec::new(),
+            natives: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Returns a [StringBuilder] that can be used to assemble a J* `String` out of multiple pieces.
+    ///
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM, string::String, convert::FromJStar};
+    /// let mut vm = VM::new(Conf::new()).init_runtime();
+    ///
+    /// let mut builder = vm.string_builder();
+    /// builder.str("x = ");
+    /// builder.number(42.0).unwrap();
+    /// builder.finish();
+    ///
+    /// assert_eq!(String::from_jstar(&vm, -1).unwrap(), "x = 42");
+    /// ```
+    pub fn string_builder(&mut self) -> StringBuilder<'_, 'a> {
+        StringBuilder {
+            vm: self,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sets the value on top of the stack at the dotted `path` (e.g. `"server.port"`), starting
+    /// from the value at `root_slot`. The value is not popped.
+    ///
+    /// Each segment of the path is looked up as a key if the current value is a `Table`, or as a
+    /// field otherwise. Missing intermediate segments are created as new `Table`s when their
+    /// parent is a `Table`, while a missing field is an error. This makes it easy to build nested
+    /// configuration tables from flat key-value inputs.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the value on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if `path` is malformed, if an intermediate field is missing or
+    /// if setting the value failed, leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `root_slot` underflows or overflows the stack (for the current stack
+    /// frame) or if the stack is empty.
+    pub fn set_path(&mut self, root_slot: Index, path: &str) -> Result<()> {
+        assert!(self.validate_slot(root_slot), "`root_slot` out of bounds");
+        assert!(self.validate_slot(-1), "VM stack underflow");
+        let root_slot = self.abs_slot(root_slot);
+        self.ensure_stack(4);
+        self.get_helper("setPath")?;
+        // SAFETY: `self.vm` is a valid J* vm pointer, `root_slot` is valid and the value to set is
+        // at slot -4 after pushing the helper, the root and the path
+        unsafe { ffi::jsrPushValue(self.vm, root_slot) };
+        self.push_string(path);
+        unsafe { ffi::jsrPushValue(self.vm, -4) };
+        self.call(3)?;
+        self.pop();
+        Ok(())
+    }
+
+    /// Pushes the value found at the dotted `path` (e.g. `"server.port"`), starting from the
+    /// value at `root_slot`.
+    ///
+    /// Each segment of the path is looked up as a key if the current value is a `Table`, or as a
+    /// field otherwise. See also [VM::set_path].
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the value on top of the stack.  
+    /// `Err(`[`Error::Runtime`]`)` if `path` is malformed or if one of its segments is missing,
+    /// leaving an exception naming the missing segment on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `root_slot` underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn get_path(&mut self, root_slot: Index, path: &str) -> Result<()> {
+        assert!(self.validate_slot(root_slot), "`root_slot` out of bounds");
+        let root_slot = self.abs_slot(root_slot);
+        self.ensure_stack(3);
+        self.get_helper("getPath")?;
+        // SAFETY: `self.vm` is a valid J* vm pointer and `root_slot` is valid
+        unsafe { ffi::jsrPushValue(self.vm, root_slot) };
+        self.push_string(path);
+        self.call(2)
+    }
+
+    /// Returns a [StringInterner] that caches the J* `String`s it pushes, so that pushing the
+    /// same contents again reuses the already created `String` instead of copying the bytes into
+    /// a new one.
+    ///
+    /// This is useful when pushing many repeated strings, for example the column names of every
+    /// row in a data set.
+    ///
+    /// The cached `String`s are kept in a `List` pushed onto the stack by this method, that must
+    /// not be popped while the interner is alive. Values pushed through the interner sit above it.
+    ///
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM, string::String, convert::FromJStar};
+    /// let mut vm = VM::new(Conf::new()).init_runtime();
+    ///
+    /// let mut interner = vm.string_interner();
+    /// for _ in 0..10 {
+    ///     interner.push("name");
+    ///     interner.pop();
+    /// }
+    /// assert_eq!(interner.len(), 1);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn string_interner(&mut self) -> StringInterner<'_, 'a> {
+        assert!(self.validate_stack(), "VM stack overflow");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrPushList(self.vm) };
+        let slot = self.abs_slot(-1);
+        StringInterner {
+            vm: self,
+            slot,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Raises an exception in the VM, leaving it on top of the stack.
+    ///
+    /// The exception class 'cls' is searched in the current executing module or __core__.
+    /// `msg` is used verbatim as the exception message, and it is never interpreted as a
+    /// printf-style format string. After raising, a native function should return
+    /// `Err(`[`Error::Runtime`]`)` (see also the [raise](crate::raise) macro).
+    ///
+    /// Since raising can't fail, nul bytes in `cls` and `msg` are replaced with `U+FFFD`.
+    pub fn raise(&self, cls: &str, msg: &str) {
+        let cls = lossy_cstring(cls);
+        let msg = lossy_cstring(msg);
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrRaise(self.vm, cls.as_ptr(), c"%s".as_ptr(), msg.as_ptr()) };
+    }
+
+    /// Raises an exception in the VM, leaving it on top of the stack, and records the exception at
+    /// `cause_slot` as its cause.
+    ///
+    /// The cause is stored in the `_cause` field of the new exception, so that J* prints it after
+    /// the exception's own traceback. This is useful in natives that catch an exception and raise
+    /// a more specific one in its place. See [VM::raise] for the handling of `cls` and `msg`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `cause_slot` underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn raise_with_cause(&self, cls: &str, msg: &str, cause_slot: Index) {
+        assert!(self.validate_slot(cause_slot), "VM stack overflow");
+        let cause_slot = self.abs_slot(cause_slot);
+        self.raise(cls, msg);
+        self.ensure_stack(1);
+        // SAFETY: `self.vm` is a valid J* vm pointer, the new exception is on top of the stack and
+        // we made sure there's space for the cause
+        unsafe {
+            ffi::jsrPushValue(self.vm, cause_slot);
+            let set = ffi::jsrSetField(self.vm, -2, c"_cause".as_ptr());
+            assert!(set, "exception instances to accept the `_cause` field");
+            ffi::jsrPop(self.vm);
+        }
+    }
+
+    /// Returns an [Args] helper to validate and read the arguments of a native function.
+    ///
+    /// ```
+    /// # use jstar::{native, convert::ToJStar};
+    /// native!(fn repeat(vm) {
+    ///     let args = vm.args();
+    ///     let str = args.string(1, "str")?.to_string_lossy().into_owned();
+    ///     let times = args.optional_number(2, "times")?.unwrap_or(1.0);
+    ///     str.repeat(times as usize).to_jstar(vm);
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn args(&self) -> Args<'_> {
+        Args { vm: self }
+    }
+
+    /// Returns a [`StackRef`] pointing to the topmost stack slot.
+    pub fn get_top(&self) -> StackRef {
+        StackRef {
+            // SAFETY: `self.vm` is a valid J* vm pointer
+            index: unsafe { ffi::jsrTop(self.vm) },
+            vm: self,
+        }
+    }
+
+    /// Returns a [`StackRef`] pointing to the stack slot at `slot`.
+    /// `slot` is treated as an offset from the top of the stack and must be positive.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows the stack (for the current stack frame).
+    pub fn peek_top(&self, slot: Index) -> StackRef {
+        assert!(slot > 0, "`slot` must be positive");
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        let idx = unsafe { ffi::jsrTop(self.vm) } - slot;
+        assert!(self.validate_slot(idx), "`slot` out of bounds");
+        StackRef {
+            index: idx,
+            vm: self,
+        }
+    }
+
+    /// Records the current height of the stack, so that it can later be restored with
+    /// [VM::restore].
+    ///
+    /// This is useful to implement transactional stack logic, where an arbitrary number of
+    /// temporaries are pushed and then discarded all at once, regardless of how the operation
+    /// ended:
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// # let mut vm = VM::new(Conf::new()).init_runtime();
+    /// let cp = vm.checkpoint();
+    /// vm.push_number(1.0);
+    /// vm.push_string("temporary");
+    /// vm.restore(cp);
+    /// assert_eq!(vm.checkpoint(), cp);
+    /// ```
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            // SAFETY: `self.vm` is a valid J* vm pointer
+            top: unsafe { ffi::jsrTop(self.vm) },
+        }
+    }
+
+    /// Restores the stack to the height recorded by `cp`, popping every value pushed after the
+    /// checkpoint was taken.
+    ///
+    /// # Arguments
+    ///
+    /// * `cp` - A [Checkpoint] obtained from [VM::checkpoint] in the current stack frame.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the stack is lower than it was when `cp` was taken, as restoring it
+    /// would require inventing new slots.
+    pub fn restore(&mut self, cp: Checkpoint) {
+        assert!(
+            self.stack_depth() as Index > cp.top,
+            "Stack is lower than the checkpoint"
+        );
+        self.set_top((cp.top + 1) as usize);
+    }
+
+    /// Returns the number of values on the stack for the current stack frame.
+    pub fn stack_depth(&self) -> usize {
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        (unsafe { ffi::jsrTop(self.vm) } + 1) as usize
+    }
+
+    /// Makes the stack of the current frame exactly `n` values deep, either by popping the values
+    /// above `n` or by pushing `null`s up to `n`. Mirrors `lua_settop`.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the stack cannot be grown to hold `n` values.
+    pub fn set_top(&mut self, n: usize) {
+        let depth = self.stack_depth();
+        if n < depth {
+            self.pop_n((depth - n) as i32);
+        } else if n > depth {
+            self.ensure_stack(n - depth);
+            for _ in depth..n {
+                self.push_null();
+            }
+        }
+    }
+
+    /// Ensure that the vm's stack can hold at least `needed` items, reallocating the stack
+    /// to add more space if needed.
+    ///
+    /// See [`native::MIN_NATIVE_STACK_SZ`](../native/constant.MIN_NATIVE_STACK_SZ.html) for the
+    /// minimum guaranteed stack size when calling a J* native function.
+    pub fn ensure_stack(&self, needed: usize) {
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrEnsureStack(self.vm, needed) };
+    }
+
+    /// Converts `slot` into a positive offset from the start of the stack, so that it keeps
+    /// pointing to the same value when pushing new elements.
+    fn abs_slot(&self, slot: Index) -> Index {
+        if slot < 0 {
+            // SAFETY: `self.vm` is a valid J* vm pointer
+            unsafe { ffi::jsrTop(self.vm) + slot + 1 }
+        } else {
+            slot
+        }
+    }
+
+    /// Calls the method `name` on the value at slot `-(argc + 1)` with the arguments from
+    /// `-argc..$top`, leaving the result (or an exception) on top of the stack.
+    fn call_method(&mut self, name: &str, argc: u8) -> Result<()> {
+        assert!(self.validate_slot(-(argc as i32 + 1)));
+        let name = CString::new(name)?;
+        // SAFETY: `self.vm` is a valid pointer
+        let res = unsafe { ffi::jsrCallMethod(self.vm, name.as_ptr(), argc) };
+        if let Ok(err) = res.try_into() {
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Calls `f` in a new native stack frame, with a copy of the value at `arg` (or `null`) in
+    /// slot 2 of the frame.
+    ///
+    /// Temporaries pushed by `f` are discarded when the frame returns, leaving only the value on
+    /// top of the frame (or the exception raised by `f`) on top of the stack.
+    ///
+    /// A panic in `f` is caught before it can unwind through the J* interpreter, and is resumed
+    /// once the frame has returned.
+    fn in_frame(&self, arg: Option<Index>, f: &mut dyn FnMut(&mut VM) -> Result<()>) -> Result<()> {
+        let arg = arg.map(|arg| {
+            assert!(self.validate_slot(arg), "`arg` out of bounds");
+            self.abs_slot(arg)
+        });
+        self.ensure_rust_module()?;
+        self.ensure_stack(3);
+        // The native is pushed directly instead of being stored in the hidden module, so that J*
+        // code has no way of calling it with an arbitrary handle
+        self.push_native(RUST_MODULE, "runFrame", runFrame, 2)?;
+        let mut frame = Frame { f, panic: None };
+        // SAFETY: `self.vm` is a valid J* vm pointer and `arg` is a valid slot. The handle points
+        // to `frame`, that outlives the call.
+        let res = unsafe {
+            ffi::jsrPushHandle(self.vm, &mut frame as *mut Frame as *mut c_void);
+            match arg {
+                Some(arg) => ffi::jsrPushValue(self.vm, arg),
+                None => ffi::jsrPushNull(self.vm),
+            }
+            ffi::jsrCall(self.vm, 2)
+        };
+        if let Some(payload) = frame.panic {
+            std::panic::resume_unwind(payload);
+        }
+        if let Ok(err) = res.try_into() {
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pushes a J* function computing its value with `thunk` on its first call. See
+    /// [Lazy](crate::convert::Lazy).
+    ///
+    /// `thunk` is stored in a `Userdata` captured by the function, so it is dropped once the
+    /// function is collected.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success, leaving the function on top of the stack. `Err(`[`Error::Runtime`]`)`
+    /// if creating the function failed, leaving an exception on top of the stack in its place.
+    pub(crate) fn push_lazy(&self, thunk: Rc<Thunk>) -> Result<()> {
+        self.get_helper("makeLazy")?;
+        self.ensure_stack(1);
+        self.push_userdata(thunk);
+        // SAFETY: `self.vm` is a valid J* vm pointer, and `makeLazy` and its argument are on top
+        // of the stack
+        let res = unsafe { ffi::jsrCall(self.vm, 1) };
+        if let Ok(err) = res.try_into() {
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pushes the helper function `name` defined in the hidden [RUST_MODULE], evaluating the
+    /// module on first use.
+    fn get_helper(&self, name: &str) -> Result<()> {
+        self.ensure_rust_module()?;
+        self.get_global(RUST_MODULE, name)
+    }
+
+    /// Evaluates the hidden [RUST_MODULE] if it hasn't been evaluated yet.
+    fn ensure_rust_module(&self) -> Result<()> {
+        // Any helper defined by the module works to check whether it has been evaluated
+        let loaded = self.get_global(RUST_MODULE, "deepEquals").is_ok();
+        // SAFETY: `self.vm` is a valid J* vm pointer and `get_global` left either the helper or
+        // an exception on the stack
+        unsafe { ffi::jsrPop(self.vm) };
+        if loaded {
+            return Ok(());
+        }
+        self.eval_in_module(RUST_MODULE, RUST_MODULE, RUST_MODULE_SRC)?;
+        self.register_native(RUST_MODULE, "setPath", setPath, 3)?;
+        self.register_native(RUST_MODULE, "getPath", getPath, 2)?;
+        self.register_native(RUST_MODULE, "isSubclass", isSubclass, 3)?;
+        self.register_native(RUST_MODULE, "runThunk", runThunk, 1)
+    }
+
+    /// Returns `true` if the provided slot is valid, i.e. it doesn't overflow or underflow the
+    /// stack, false otherwise
+    pub fn validate_slot(&self, slot: Index) -> bool {
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrValidateSlot(self.vm, slot) }
+    }
+
+    /// Returns `true` if the stack has space for one element, i.e. pushing one element will not
+    /// overflow the stack
+    pub fn validate_stack(&self) -> bool {
+        // SAFETY: `self.vm` is a valid J* vm pointer
+        unsafe { ffi::jsrValidateStack(self.vm) }
+    }
+}
+
+/// Methods available to both [Init] and [Uninit] VMs.
+impl<'a, State> VM<'a, State> {
+    /// Compiles J* source code into bytecode.
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - The J* source code to compile
+    ///
+    /// * `path` - The path of the source code. It doesn't have to be a real filesystem path, as it
+    ///   is only used during error callbacks to provide useful context to the client handling the
+    ///   error. Nonetheless, if the source code has been indeed read from a file, it is reccomended
+    ///   to pass its path to this function.
+    ///
+    /// * `out` - A [`Write`] implementor to write the compiled bytecode to
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the compilation succeded, `Err(`[`Error`]`)` otherwise.
+    pub fn compile(&self, path: &str, src: &str, mut out: impl Write) -> Result<()> {
+        let path = CString::new(path)?;
+        let src = CString::new(src)?;
+        let mut buf = ffi::JStarBuffer::default();
+
+        // SAFETY: `self.vm` is a valid pointer
+        let res = unsafe {
+            ffi::jsrCompileCode(
+                self.vm,
+                path.as_ptr(),
+                src.as_ptr(),
+                &mut buf as *mut ffi::JStarBuffer,
+            )
+        };
+
+        if let Ok(err) = res.try_into() {
+            return Err(err);
+        }
+
+        // SAFETY: we are guaranteed by the J* API that `buf.data` is a valid pointer (check above)
+        // and that its size is at least of `buf.size` bytes
+        let slice = unsafe { from_raw_parts(buf.data as *const u8, buf.size) };
+        let write_res = out.write_all(slice);
+
+        // SAFETY: we are guaranteed that `buf` is a valid and initialized J* buffer (check above)
+        unsafe { ffi::jsrBufferFree(&mut buf as *mut ffi::JStarBuffer) };
+
+        match write_res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::io_context(
+                e,
+                format!("writing bytecode for `{}`", path.to_string_lossy()),
+            )),
+        }
+    }
+
+    /// Similar to [VM::compile] but returns the compiled bytecode as a [`Vec<u8>`].
+    /// This method is convenient when the compiled bytecode needs to be stored in memory.
+    ///
+    /// # arguments
+    ///
+    /// * `path` - The path of the source code. It doesn't have to be a real filesystem path, as it
+    ///   is only used during error callbacks to provide useful context to the client handling the
+    ///   error. Nonetheless, if the source code has been indeed read from a file, it is reccomended
+    ///   to pass its path to this function.
+    ///
+    /// * `src` - The J* source code to compile
+    ///
+    /// # Returns
+    /// `Ok(`[`Vec<u8>`]`)` if the compilation succeded, `Err(`[`Error`]`)` otherwise.
+    pub fn compile_in_memory(&self, path: &str, src: &str) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.compile(path, src, &mut out)?;
+        Ok(out)
+    }
+
+    /// Prints a human readable listing of the instructions in compiled J* code.
+    ///
+    /// The listing is printed to the standard output by the J* runtime itself, so it cannot be
+    /// redirected to a Rust [`Write`] implementor.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the compiled code. It is only used to provide context to the error
+    ///   callback in case the code cannot be deserialized.
+    ///
+    /// * `code` - The compiled code to disassemble, as produced by [VM::compile].
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the disassembly succeded, `Err(`[`Error`]`)` otherwise (e.g.
+    /// [`Error::Deserialize`] if `code` is not valid compiled code).
+    pub fn disassemble(&self, path: &str, code: &[u8]) -> Result<()> {
+        let path = CString::new(path)?;
+        // The buffer borrows `code`: J* only reads from it and it is never grown or freed
+        let buf = ffi::JStarBuffer {
+            vm: self.vm,
+            capacity: code.len(),
+            size: code.len(),
+            data: code.as_ptr() as *mut c_char,
+        };
+
+        // SAFETY: `self.vm` is a valid pointer and `buf` points to `code.len()` readable bytes
+        let res = unsafe {
+            ffi::jsrDisassembleCode(self.vm, path.as_ptr(), &buf as *const ffi::JStarBuffer)
+        };
+
+        if let Ok(err) = res.try_into() {
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Compiles a batch of J* source files into bytecode, stopping at the first failure.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - `(path, src)` pairs of the files to compile. See [VM::compile].
+    ///
+    /// # Returns
+    ///
+    /// `Ok(Vec<(path, bytecode)>)` containing the compiled bytecode of each file if the
+    /// compilation succeded, `Err(`[`Error::InFile`]`)` wrapping the error and the path of
+    /// the first file that failed to compile otherwise.
+    pub fn compile_all<'p>(&self, files: &[(&'p str, &str)]) -> Result<Vec<(&'p str, Vec<u8>)>> {
+        files
+            .iter()
+            .map(|&(path, src)| match self.compile_in_memory(path, src) {
+                Ok(code) => Ok((path, code)),
+                Err(err) => Err(Error::InFile {
+                    path: path.to_owned(),
+                    source: Box::new(err),
+                }),
+            })
+            .collect()
+    }
+
+    /// Returns the raw parts of this [VM]: the pointer to the underlying [ffi::JStarVM] and the
+    /// pointer to its custom data (see [ffi::jsrGetCustomData]).
+    ///
+    /// This is an escape hatch for advanced embedders that need to integrate the J* vm with other C
+    /// libraries that require access to both.
+    ///
+    /// # Safety
+    ///
+    /// The returned pointers are valid only as long as the `VM` owning the J* vm is alive.
+    /// The vm pointer can be passed to the J* C API or wrapped using [VM::from_ptr], but it must
+    /// never be freed. The custom data pointer refers to state managed by this crate (error and
+    /// import callbacks, among other things): it must be treated as opaque and must never be
+    /// written through or freed.
+    pub unsafe fn raw_parts(&self) -> (*mut ffi::JStarVM, *mut c_void) {
+        (self.vm, ffi::jsrGetCustomData(self.vm))
+    }
+
+    /// Returns the [Trampolines] associated with the J* vm.
+    fn trampolines(&self) -> &Trampolines<'a> {
+        // SAFETY: jsrGetCustomData() always returns a `*mut Trampolines` by construction (see
+        // `VM::new`), and the trampolines live as long as the vm does.
+        unsafe { &*(ffi::jsrGetCustomData(self.vm) as *const Trampolines<'a>) }
+    }
+
+    /// Returns the [Trampolines] associated with the J* vm.
+    fn trampolines_mut(&mut self) -> &mut Trampolines<'a> {
+        // SAFETY: see `trampolines`
+        unsafe { &mut *(ffi::jsrGetCustomData(self.vm) as *mut Trampolines<'a>) }
+    }
+
+    /// Executes `f`, collecting all the errors reported by the J* vm in the meantime instead of
+    /// passing them to the error callback. The error callback is restored once `f` returns.
+    ///
+    /// This is useful for tools and test harnesses that want to inspect the full list of
+    /// reported errors (for example, all the syntax errors of a script) instead of only the
+    /// final result.
+    ///
+    /// ```rust
+    /// # use jstar::{conf::Conf, vm::VM};
+    /// let mut vm = VM::new(Conf::new()).init_runtime();
+    ///
+    /// let (_, errors) = vm.capture_errors(|vm| {
+    ///     let _ = vm.eval("first.jsr", "var");
+    ///     let _ = vm.eval("second.jsr", "var");
+    /// });
+    ///
+    /// assert_eq!(errors.len(), 2);
+    /// assert_eq!(errors[0].file, "first.jsr");
+    /// ```
+    pub fn capture_errors<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> (R, Vec<CapturedError>) {
+        /// Restores the errors captured by an outer call when dropped, even if `f` panics.
+        struct Restore {
+            vm: *mut ffi::JStarVM,
+            prev: Option<Vec<CapturedError>>,
+        }
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                // SAFETY: `vm` is the pointer of the vm running `capture_errors`, that outlives
+                // the guard. Its custom data always points to the trampolines (see `trampolines`)
+                let trampolines =
+                    unsafe { &mut *(ffi::jsrGetCustomData(self.vm) as *mut Trampolines) };
+                trampolines.captured_errors = self.prev.take();
+            }
+        }
+
+        let prev = self.trampolines_mut().captured_errors.replace(Vec::new());
+        let restore = Restore { vm: self.vm, prev };
+        let res = f(self);
+        let captured = self.trampolines_mut().captured_errors.take();
+        drop(restore);
+        (res, captured.unwrap_or_default())
+    }
+
+    /// Passes previously captured errors to the error callback.
+    fn report_errors(&mut self, errors: Vec<CapturedError>) {
+        let trampolines = self.trampolines_mut();
+        for err in errors {
+            if let Some(ref mut errors) = trampolines.captured_errors {
+                errors.push(err);
+            } else if let Some(ref mut error_callback) = trampolines.error_callback {
+                error_callback(err.error, &err.file, err.line, &err.message);
+            }
+        }
+    }
+}
+
+// Native helpers registered in [RUST_MODULE]. Running in their own stack frame, they can freely
+// push temporaries that are discarded once they return.
+
+crate::native!(fn setPath(vm) {
+    let path = vm.check_string(2, "path")?.to_string_lossy().into_owned();
+    let segments = split_path(vm, &path)?;
+    let Some((leaf, parents)) = segments.split_last() else {
+        unreachable!("split always returns at least one segment")
+    };
+
+    vm.ensure_stack(1);
+    // SAFETY: `vm.vm` is a valid J* vm pointer and slot 1 holds the root
+    unsafe { ffi::jsrPushValue(vm.vm, 1) };
+    for segment in parents {
+        descend_path(vm, segment, &path, true)?;
+    }
+
+    vm.ensure_stack(3);
+    // SAFETY: `vm.vm` is a valid J* vm pointer, the parent is on top of the stack and slot 3
+    // holds the value to set
+    unsafe {
+        if ffi::jsrIsTable(vm.vm, -1) {
+            ffi::jsrDup(vm.vm);
+            vm.push_string(leaf);
+            ffi::jsrPushValue(vm.vm, 3);
+            vm.call_method("__set__", 2)
+        } else {
+            let field = CString::new(*leaf).expect("`path` to be a valid CString");
+            ffi::jsrPushValue(vm.vm, 3);
+            if ffi::jsrSetField(vm.vm, -2, field.as_ptr()) {
+                Ok(())
+            } else {
+                Err(Error::Runtime)
+            }
+        }
+    }
+});
+
+crate::native!(fn getPath(vm) {
+    let path = vm.check_string(2, "path")?.to_string_lossy().into_owned();
+    let segments = split_path(vm, &path)?;
+
+    vm.ensure_stack(1);
+    // SAFETY: `vm.vm` is a valid J* vm pointer and slot 1 holds the root
+    unsafe { ffi::jsrPushValue(vm.vm, 1) };
+    for segment in segments {
+        descend_path(vm, segment, &path, false)?;
+    }
+    Ok(())
+});
+
+crate::native!(fn isSubclass(vm) {
+    let module = vm.check_string(2, "module")?.to_string_lossy().into_owned();
+    let name = vm.check_string(3, "name")?.to_string_lossy().into_owned();
+    vm.ensure_stack(2);
+    // SAFETY: `vm.vm` is a valid J* vm pointer and slot 1 holds the class
+    unsafe { ffi::jsrPushValue(vm.vm, 1) };
+    vm.get_class(&module, &name)?;
+    vm.call_method("implements", 1)
+});
+
+crate::native!(fn runThunk(vm) {
+    let thunk = Rc::clone(&*vm.check_userdata::<Rc<Thunk>>(1, "thunk")?);
+    // SAFETY: `vm.vm` is a valid J* vm pointer
+    let top = unsafe { ffi::jsrTop(vm.vm) };
+    thunk(vm)?;
+    // SAFETY: `vm.vm` is a valid J* vm pointer
+    let pushed = unsafe { ffi::jsrTop(vm.vm) } - top;
+    if pushed != 1 {
+        // The native frame is dropped on return, so whatever the closure left is discarded
+        vm.ensure_stack(1);
+        crate::raise!(
+            vm,
+            "Exception",
+            "`Lazy` closure must push exactly one value, it pushed {}",
+            pushed
+        );
+    }
+    Ok(())
+});
+
+/// The closure called by `runFrame`, along with the payload of its panic, if any.
+struct Frame<'f> {
+    f: &'f mut dyn FnMut(&mut VM) -> Result<()>,
+    panic: Option<Box<dyn std::any::Any + Send>>,
+}
+
+crate::native!(fn runFrame(vm) {
+    // SAFETY: `runFrame` is only ever called by `VM::in_frame`, that passes a handle pointing to
+    // a `Frame` living on its stack for the whole duration of the call
+    let frame = unsafe { &mut *(ffi::jsrGetHandle(vm.vm, 1) as *mut Frame) };
+    // Unwinding through the J* interpreter is undefined behaviour, so the panic is stashed in the
+    // frame and resumed by `in_frame` once the call has returned
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| (frame.f)(vm))) {
+        Ok(res) => res,
+        Err(payload) => {
+            frame.panic = Some(payload);
+            crate::raise!(vm, "Exception", "Rust panic in native frame");
+        }
+    }
+});
+
+/// Converts a [Value] to its JSON representation, returning a description of the offending value
+/// if it cannot be represented as JSON.
+#[cfg(feature = "serde_json")]
+fn value_to_json(value: Value) -> std::result::Result<serde_json::Value, std::string::String> {
+    use serde_json::Value as Json;
+    Ok(match value {
+        Value::Null => Json::Null,
+        Value::Bool(b) => Json::Bool(b),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < (1u64 << 53) as f64 => {
+            Json::from(n as i64)
+        }
+        Value::Number(n) => serde_json::Number::from_f64(n)
+            .map(Json::Number)
+            .ok_or_else(|| format!("non finite number {n}"))?,
+        Value::String(bytes) => Json::String(
+            std::string::String::from_utf8(bytes).map_err(|_| "non utf8 string".to_owned())?,
+        ),
+        Value::List(elems) | Value::Tuple(elems) => Json::Array(
+            elems
+                .into_iter()
+                .map(value_to_json)
+                .collect::<std::result::Result<_, _>>()?,
+        ),
+        Value::Table(entries) => Json::Object(
+            entries
+                .into_iter()
+                .map(|(key, val)| match key {
+                    Value::String(key) => Ok((
+                        std::string::String::from_utf8(key)
+                            .map_err(|_| "non utf8 key".to_owned())?,
+                        value_to_json(val)?,
+                    )),
+                    _ => Err("non String table key".to_owned()),
+                })
+                .collect::<std::result::Result<_, _>>()?,
+        ),
+    })
+}
+
+/// Converts `s` to a [CString], replacing its nul bytes with `U+FFFD`.
+/// Returns whether `name` is a valid J* identifier.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns whether the brackets of the J* code `src` are balanced, ignoring the ones inside of
+/// string literals and comments, so that it can't close a bracket opened by the code around it.
+fn is_balanced(src: &str) -> bool {
+    let mut depth = 0usize;
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            '"' | '\'' => loop {
+                match chars.next() {
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(q) if q == c => break,
+                    Some(_) => {}
+                    None => return false,
+                }
+            },
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                chars.find(|&c| c == '\n');
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => loop {
+                match chars.next() {
+                    // Bail out of nested comments instead of guessing how they are lexed
+                    Some('/') if chars.peek() == Some(&'*') => return false,
+                    Some('*') if chars.next_if_eq(&'/').is_some() => break,
+                    Some(_) => {}
+                    None => return false,
+                }
+            },
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+fn lossy_cstring(s: &str) -> CString {
+    CString::new(s.replace('\0', "\u{FFFD}")).expect("nul bytes to be replaced")
+}
+
+/// Sorts `elems` using a stable merge sort, aborting as soon as `cmp` fails.
+fn merge_sort<T: Copy>(
+    elems: &mut [T],
+    mut cmp: impl FnMut(T, T) -> Result<std::cmp::Ordering>,
+) -> Result<()> {
+    let mut buf = elems.to_vec();
+    let mut width = 1;
+    while width < elems.len() {
+        for start in (0..elems.len()).step_by(2 * width) {
+            let mid = (start + width).min(elems.len());
+            let end = (start + 2 * width).min(elems.len());
+            let (mut i, mut j) = (start, mid);
+            for slot in &mut buf[start..end] {
+                if j >= end || (i < mid && cmp(elems[i], elems[j])?.is_le()) {
+                    *slot = elems[i];
+                    i += 1;
+                } else {
+                    *slot = elems[j];
+                    j += 1;
+                }
+            }
+        }
+        elems.copy_from_slice(&buf);
+        width *= 2;
+    }
+    Ok(())
+}
+
+/// Splits a dotted path into its segments, raising an `InvalidArgException` if any of them is
+/// empty.
+fn split_path<'p>(vm: &VM, path: &'p str) -> Result<Vec<&'p str>> {
+    if path.contains('\0') {
+        vm.raise("InvalidArgException", "Path must not contain NUL bytes");
+        return Err(Error::Runtime);
+    }
+    let segments: Vec<_> = path.split('.').collect();
+    if segments.iter().any(|s| s.is_empty()) {
+        vm.raise("InvalidArgException", &format!("Invalid path `{path}`"));
+        return Err(Error::Runtime);
+    }
+    Ok(segments)
+}
+
+/// Pushes the value of `segment` of the value on top of the stack, creating a new `Table` for
+/// missing keys of `Table`s if `create` is true.
+fn descend_path(vm: &mut VM, segment: &str, path: &str, create: bool) -> Result<()> {
+    vm.ensure_stack(5);
+    // SAFETY: `vm.vm` is a valid J* vm pointer, the current value is on top of the stack and we
+    // ensured enough space for the temporaries
+    unsafe {
+        if ffi::jsrIsTable(vm.vm, -1) {
+            ffi::jsrDup(vm.vm);
+            vm.push_string(segment);
+            vm.call_method("contains", 1)?;
+            let contains = ffi::jsrIsBoolean(vm.vm, -1) && ffi::jsrGetBoolean(vm.vm, -1);
+            vm.pop();
+
+            if contains {
+                ffi::jsrDup(vm.vm);
+                vm.push_string(segment);
+                return vm.call_method("__get__", 1);
+            } else if create {
+                ffi::jsrPushTable(vm.vm);
+                ffi::jsrPushValue(vm.vm, -2);
+                vm.push_string(segment);
+                ffi::jsrPushValue(vm.vm, -3);
+                vm.call_method("__set__", 2)?;
+                vm.pop();
+                return Ok(());
+            }
+        } else {
+            let field = CString::new(segment).expect("`path` to be a valid CString");
+            if ffi::jsrGetField(vm.vm, -1, field.as_ptr()) {
+                return Ok(());
+            }
+            vm.pop();
+        }
+    }
+    vm.raise(
+        "FieldException",
+        &format!("Missing path segment `{segment}` in `{path}`"),
+    );
+    Err(Error::Runtime)
+}
+
+/// A J* expression compiled once and evaluated many times. See [VM::compile_expr].
+///
+/// Dropping the last clone of the expression releases the compiled function.
+#[derive(Debug, Clone)]
+pub struct CompiledExpr {
+    vm: *mut ffi::JStarVM,
+    global: Rc<RootedGlobal>,
+}
+
+impl CompiledExpr {
+    /// Evaluates the expression, passing `args` in place of its parameters.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the evaluation succeded leaving the result on top of the stack,
+    /// `Err(`[`Error::Runtime`]`)` otherwise, leaving an exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `vm` is not the [VM] that compiled the expression, or if more than
+    /// 255 arguments are passed.
+    pub fn eval(&self, vm: &mut VM, args: &[&dyn ToJStar]) -> Result<()> {
+        assert_eq!(self.vm, vm.vm, "expression compiled by a different VM");
+        let argc = u8::try_from(args.len()).expect("Too many arguments");
+        vm.ensure_stack(args.len() + 1);
+        vm.get_global(MAIN_MODULE, &self.global.name)?;
+        for arg in args {
+            arg.to_jstar(vm);
+        }
+        vm.call(argc)
+    }
+}
+
+/// A J* callable rooted in the [VM], that can be stored and invoked later on, as it is common
+/// when registering callbacks for events. See [VM::get_closure].
+///
+/// Dropping the handler unroots the callable, making it collectable by the J* GC.
+#[derive(Debug)]
+pub struct EventHandler {
+    vm: *mut ffi::JStarVM,
+    global: RootedGlobal,
+}
+
+impl EventHandler {
+    /// Invokes the handler with `args`, discarding its result.
+    ///
+    /// If the handler raises an exception, its stacktrace is reported to the error callback (see
+    /// [Conf::error_callback]) as a runtime error, and the exception is popped from the stack.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the handler returned normally, `Err(`[`Error::Runtime`]`)` if it raised an
+    /// exception. In both cases the stack is left as it was found.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if `vm` is not the [VM] the handler was created from, or if more than
+    /// 255 arguments are passed.
+    pub fn invoke(&self, vm: &mut VM, args: &[&dyn ToJStar]) -> Result<()> {
+        assert_eq!(self.vm, vm.vm, "handler created by a different VM");
+        let argc = u8::try_from(args.len()).expect("Too many arguments");
+        vm.ensure_stack(args.len() + 1);
+        if let Err(err) = vm.get_global(RUST_MODULE, &self.global.name) {
+            vm.report_exception("<event handler>");
+            return Err(err);
+        }
+        for arg in args {
+            arg.to_jstar(vm);
+        }
+        match vm.call(argc) {
+            Ok(()) => {
+                vm.pop();
+                Ok(())
+            }
+            Err(err) => {
+                vm.report_exception("<event handler>");
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The globals rooting the values of the [CompiledExpr]s or [EventHandler]s of a [VM]. The ids of the released
+/// globals are reused, so that the module doesn't accumulate dead globals.
+#[derive(Debug)]
+struct RootedGlobals {
+    module: &'static str,
+    prefix: &'static str,
+    /// The number of ids handed out so far
+    len: usize,
+    /// Ids of the dropped globals, that still need to be nulled
+    released: Vec<usize>,
+    /// Ids of the nulled globals, ready to be reused
+    free: Vec<usize>,
+}
+
+impl RootedGlobals {
+    fn new(module: &'static str, prefix: &'static str) -> Self {
+        RootedGlobals {
+            module,
+            prefix,
+            len: 0,
+            released: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+/// A global of [RootedGlobals] rooting a value in the [VM]. See [VM::acquire_global].
+#[derive(Debug)]
+struct RootedGlobal {
+    name: std::string::String,
+    id: usize,
+    globals: Rc<RefCell<RootedGlobals>>,
+}
+
+impl Drop for RootedGlobal {
+    fn drop(&mut self) {
+        // The vm may already be gone, so the global is nulled lazily by the vm itself
+        self.globals.borrow_mut().released.push(self.id);
+    }
+}
+
+/// The layout of the J* `Userdata` created by [VM::push_userdata]. The registry pointer lets the
+/// finalizer unregister the userdata once it is collected.
+#[repr(C)]
+struct Userdata<T> {
+    registry: *const UserdataRegistry,
+    value: RefCell<T>,
+}
+
+/// The alignment of the memory of J* userdata, that follows the pointer-sized header of the object.
+const USERDATA_ALIGN: usize = std::mem::align_of::<*const c_void>();
+
+/// A snapshot of the height of the J* stack. See [VM::checkpoint] and [VM::restore].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    top: Index,
+}
 
 /// A 'reference' to a slot in the J* stack.
 pub struct StackRef<'vm> {
@@ -732,525 +4512,3399 @@ pub struct StackRef<'vm> {
     vm: &'vm VM<'vm>,
 }
 
-impl<'vm> StackRef<'vm> {
-    /// Get the J* value in the stack slot pointed to by this reference.
-    /// If the value at the slot cannot be converted to a `T` (usually because it has the wrong J*
-    /// type) returns `None`.
-    pub fn get<T>(&self) -> Option<T>
-    where
-        T: FromJStar<'vm>,
-    {
-        T::from_jstar(self.vm, self.index)
+impl<'vm> StackRef<'vm> {
+    /// Get the J* value in the stack slot pointed to by this reference.
+    /// If the value at the slot cannot be converted to a `T` (usually because it has the wrong J*
+    /// type) returns `None`.
+    pub fn get<T>(&self) -> Option<T>
+    where
+        T: FromJStar<'vm>,
+    {
+        T::from_jstar(self.vm, self.index)
+    }
+}
+
+/// An iterator over the elements of a J* `List`. See [VM::list_iter].
+pub struct ListIter<'vm, T> {
+    list: Index,
+    index: usize,
+    vm: &'vm VM<'vm>,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Iterator for ListIter<'_, T>
+where
+    T: for<'v> FromJStar<'v>,
+{
+    type Item = Option<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // SAFETY: `self.vm` is a valid J* vm pointer and `self.list` holds a List, as the stack
+        // cannot be popped while the iterator borrows the vm. The list may have been shrunk by
+        // J* code in the meantime, so its length is checked at every step
+        let len = unsafe { ffi::jsrListGetLength(self.vm.vm, self.list) };
+        if self.index >= len {
+            return None;
+        }
+        self.vm.ensure_stack(1);
+        // SAFETY: `self.vm` is a valid J* vm pointer, `self.list` holds a List and `self.index`
+        // is in bounds. The element is popped right after being converted: `T` is an owned type
+        // (it converts from any vm borrow), so nothing referring to the element outlives the pop
+        let elem = unsafe {
+            ffi::jsrListGet(self.vm.vm, self.index, self.list);
+            let elem = T::from_jstar(self.vm, -1);
+            ffi::jsrPop(self.vm.vm);
+            elem
+        };
+        self.index += 1;
+        Some(elem)
+    }
+}
+
+/// Fluent validation of the arguments of a native function. See [VM::args].
+///
+/// Each method checks the type of the argument at slot `i`, returning the converted value or an
+/// `Err(`[`Error::Runtime`]`)` leaving a `TypeException` that mentions `name` on top of the stack.
+/// All methods panic if `i` underflows or overflows the stack (for the current stack frame).
+pub struct Args<'vm> {
+    vm: &'vm VM<'vm>,
+}
+
+impl<'vm> Args<'vm> {
+    /// Gets the argument at slot `i` as a number. See [VM::check_number].
+    pub fn number(&self, i: Index, name: &str) -> Result<f64> {
+        self.vm.check_number(i, name)
+    }
+
+    /// Gets the argument at slot `i` as a `String`. See [VM::check_string].
+    pub fn string(&self, i: Index, name: &str) -> Result<JStarString<'vm>> {
+        self.vm.check_string(i, name)
+    }
+
+    /// Gets the argument at slot `i` as a boolean. See [VM::check_boolean].
+    pub fn boolean(&self, i: Index, name: &str) -> Result<bool> {
+        self.vm.check_boolean(i, name)
+    }
+
+    /// Gets the argument at slot `i` as a number, or `None` if it is `null`.
+    pub fn optional_number(&self, i: Index, name: &str) -> Result<Option<f64>> {
+        self.get(i, name)
+    }
+
+    /// Gets the argument at slot `i` converted to `T`. See [FromJStar::from_jstar_checked].
+    pub fn get<T: FromJStar<'vm>>(&self, i: Index, name: &str) -> Result<T> {
+        T::from_jstar_checked(self.vm, i, name)
+    }
+}
+
+/// A builder for J* `String`s. See [VM::string_builder].
+///
+/// The builder accumulates the pieces of the string on the Rust side, and only creates the J*
+/// `String` when [StringBuilder::finish] is called.
+pub struct StringBuilder<'b, 'a> {
+    vm: &'b mut VM<'a>,
+    buf: Vec<u8>,
+}
+
+impl<'b, 'a> StringBuilder<'b, 'a> {
+    /// Appends a Rust string (or any byte slice) to the builder.
+    pub fn str(&mut self, str: impl AsRef<[u8]>) -> &mut Self {
+        self.buf.extend_from_slice(str.as_ref());
+        self
+    }
+
+    /// Appends the string representation of a `Number`, formatted as J* would.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(&mut Self)` on success, `Err(`[`Error::Runtime`]`)` in case of failure, leaving an
+    /// exception on top of the stack.
+    pub fn number(&mut self, number: f64) -> Result<&mut Self> {
+        self.vm.ensure_stack(1);
+        self.vm.push_number(number);
+        self.value(-1)?;
+        self.vm.pop();
+        Ok(self)
+    }
+
+    /// Appends the string representation of the value at `slot`, as returned by its `__string__`
+    /// method.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(&mut Self)` on success, `Err(`[`Error::Runtime`]`)` in case of failure, leaving an
+    /// exception on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the slot underflows or overflows the stack (for the current stack
+    /// frame).
+    pub fn value(&mut self, slot: Index) -> Result<&mut Self> {
+        assert!(self.vm.validate_slot(slot), "`slot` out of bounds");
+        let slot = self.vm.abs_slot(slot);
+        self.vm.ensure_stack(1);
+        // SAFETY: `self.vm` is a valid J* vm pointer and `slot` a valid slot per check above
+        unsafe { ffi::jsrPushValue(self.vm.vm, slot) };
+        self.vm.call_method("__string__", 0)?;
+
+        match self.vm.get_string(-1) {
+            Some(str) => self.buf.extend_from_slice(str.as_bytes()),
+            None => {
+                self.vm.pop();
+                self.vm
+                    .raise("TypeException", "`__string__` didn't return a String");
+                return Err(Error::Runtime);
+            }
+        }
+
+        self.vm.pop();
+        Ok(self)
+    }
+
+    /// Pushes the assembled J* `String` onto the VM stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn finish(self) {
+        self.vm.push_string(&self.buf);
+    }
+}
+
+/// A builder for modules implemented in Rust. See [VM::create_module].
+///
+/// The builder accumulates the definitions of the module on the Rust side, and only creates the
+/// module when [ModuleBuilder::install] is called.
+pub struct ModuleBuilder<'b, 'a> {
+    vm: &'b mut VM<'a>,
+    name: std::string::String,
+    globals: Vec<(std::string::String, Box<dyn ToJStar + 'b>)>,
+    natives: Vec<(std::string::String, ffi::JStarNative, u8)>,
+    sources: Vec<std::string::String>,
+}
+
+impl<'b, 'a> ModuleBuilder<'b, 'a> {
+    /// Defines the global variable `name` with `value`.
+    pub fn global(&mut self, name: &str, value: impl ToJStar + 'b) -> &mut Self {
+        self.globals.push((name.to_owned(), Box::new(value)));
+        self
+    }
+
+    /// Defines the native function `name` taking `argc` arguments. See [VM::register_native].
+    pub fn native(&mut self, name: &str, func: ffi::JStarNative, argc: u8) -> &mut Self {
+        self.natives.push((name.to_owned(), func, argc));
+        self
+    }
+
+    /// Adds J* source code to be evaluated in the module after all globals and natives have been
+    /// defined.
+    ///
+    /// This can be used to define classes, or to wrap natives in a more idiomatic J* interface.
+    pub fn source(&mut self, src: &str) -> &mut Self {
+        self.sources.push(src.to_owned());
+        self
+    }
+
+    /// Creates the module and installs all of its definitions, in the order: globals, natives,
+    /// sources. Once installed, the module can be imported by J* code using its name.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` on success.
+    /// `Err(`[`Error::Runtime`]`)` if a definition failed, leaving an exception on top of the
+    /// stack, or any other [Error] if one of the sources failed to compile.
+    pub fn install(self) -> Result<()> {
+        let vm = self.vm;
+        let path = format!("<{}>", self.name);
+        // Evaluating in a module that doesn't exist yet creates it
+        vm.eval_in_module(&path, &self.name, "")?;
+        for (name, value) in &self.globals {
+            vm.ensure_stack(1);
+            value.to_jstar(vm);
+            vm.set_global(&self.name, name)?;
+            vm.pop();
+        }
+        for &(ref name, func, argc) in &self.natives {
+            vm.register_native(&self.name, name, func, argc)?;
+        }
+        for src in &self.sources {
+            vm.eval_in_module(&path, &self.name, src)?;
+        }
+        Ok(())
+    }
+}
+
+/// A cache of J* `String`s for repeated pushes. See [VM::string_interner].
+///
+/// The cached `String`s are kept alive by a `List` on the stack, so a cache hit is a single list
+/// lookup. The list is popped when the interner is dropped, or emptied if other values have been
+/// left above it.
+///
+/// Dereferences to the underlying [VM], so all of its shared methods are available. Values can
+/// only be popped with [StringInterner::pop], that never pops the cache, so the interner always
+/// finds the `List` it created in its slot.
+pub struct StringInterner<'i, 'a> {
+    vm: &'i mut VM<'a>,
+    slot: Index,
+    cache: HashMap<Vec<u8>, usize>,
+}
+
+impl<'i, 'a> StringInterner<'i, 'a> {
+    /// Pushes a J* `String` with the contents of `str` onto the stack, reusing a previously
+    /// created one if `str` has already been pushed through this interner.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if there isn't enough stack space for one element. Use
+    /// [VM::ensure_stack] if you are not sure the stack has enough space.
+    pub fn push(&mut self, str: impl AsRef<[u8]>) {
+        assert!(self.vm.validate_stack(), "VM stack overflow");
+        let str = str.as_ref();
+        // SAFETY: `self.vm` is a valid J* vm pointer and the value at `self.slot` is a List
+        let len = unsafe { ffi::jsrListGetLength(self.vm.vm, self.slot) };
+        if let Some(&i) = self.cache.get(str) {
+            // SAFETY: `self.vm` is a valid J* vm pointer, and `i` is in bounds of the List, that
+            // only grows while the interner is alive
+            unsafe { ffi::jsrListGet(self.vm.vm, i, self.slot) };
+            return;
+        }
+
+        self.vm.push_string(str);
+        // SAFETY: `self.vm` is a valid J* vm pointer, the value at `self.slot` is a List and the
+        // new string is on top of the stack
+        unsafe { ffi::jsrListAppend(self.vm.vm, self.slot) };
+        self.cache.insert(str.to_vec(), len);
+    }
+
+    /// Pops the value on top of the stack.
+    ///
+    /// # Errors
+    ///
+    /// This method panics if the value on top of the stack is the `List` holding the cache, i.e.
+    /// if nothing has been pushed above it.
+    pub fn pop(&mut self) {
+        assert!(
+            self.vm.abs_slot(-1) > self.slot,
+            "cannot pop the interner `List`"
+        );
+        self.vm.pop();
+    }
+
+    /// Returns the number of distinct `String`s cached by this interner.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns `true` if no `String` has been cached yet.
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+}
+
+impl<'i, 'a> Drop for StringInterner<'i, 'a> {
+    fn drop(&mut self) {
+        // The interner never pops the list and holds the only access to the vm, so the list is
+        // still in its slot
+        if self.vm.abs_slot(-1) == self.slot {
+            self.vm.pop();
+            return;
+        }
+        // Other values sit above the list, so empty it to release the cached strings
+        // SAFETY: `self.vm` is a valid J* vm pointer and the value at `self.slot` is a List
+        unsafe {
+            for i in (0..ffi::jsrListGetLength(self.vm.vm, self.slot)).rev() {
+                ffi::jsrListRemove(self.vm.vm, i, self.slot);
+            }
+        }
+    }
+}
+
+impl<'i, 'a> Deref for StringInterner<'i, 'a> {
+    type Target = VM<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.vm
+    }
+}
+
+/// A [VM] targeting a default module. See [VM::with_module].
+///
+/// Dereferences to the underlying [VM], so all of its methods are available.
+pub struct ModuleScope<'s, 'a> {
+    vm: &'s mut VM<'a>,
+    module: &'s str,
+}
+
+impl<'s, 'a> ModuleScope<'s, 'a> {
+    /// Returns the name of the module targeted by this scope.
+    pub fn module(&self) -> &str {
+        self.module
+    }
+
+    /// Evaluate J* source or compiled code in the context of the scope's module.
+    /// See [VM::eval_in_module].
+    pub fn eval(&self, path: &str, code: impl AsRef<[u8]>) -> Result<()> {
+        self.vm.eval_in_module(path, self.module, code)
+    }
+
+    /// Get the global variable `name` from the scope's module. See [VM::get_global].
+    pub fn get_global(&self, name: &str) -> Result<()> {
+        self.vm.get_global(self.module, name)
+    }
+
+    /// Sets the global variable `name` in the scope's module with the value on top of the stack.
+    /// See [VM::set_global].
+    pub fn set_global(&self, name: &str) -> Result<()> {
+        self.vm.set_global(self.module, name)
+    }
+}
+
+impl<'s, 'a> Deref for ModuleScope<'s, 'a> {
+    type Target = VM<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.vm
+    }
+}
+
+impl<'s, 'a> DerefMut for ModuleScope<'s, 'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.vm
+    }
+}
+
+unsafe impl<'a, State> Send for VM<'a, State> {}
+
+/// Enum that serves the purpose of tracking the ownership of a pointer to an [ffi::JStarVM].
+/// Since we need the ability to construct a new rust wrapper around a `*mut JStarVM` when it is
+/// needed (for example in callbakcs, where only a pointer to the vm is available), we need to
+/// keep track which of the `VM` wrappers is the owner of the pointer (and thus is responsible for
+/// its deallocation) and which is only a temporary wrapper (a sort of 'borrow').
+/// This enum accomplishes this need, and it also mantains all of the owned state needed for the VM
+/// to work.
+enum VMOwnership<'a> {
+    Owned(Box<Trampolines<'a>>),
+    NonOwned,
+}
+
+/// Struct that owns the import and error callbacks called by J* during error handling or import
+/// resolution.
+/// In conjunction with [error_trampoline] and [import_trampoline] it enables the execution of these
+/// functions across an ffi boundary, lifting the requirement of having to declare them as `extern "C"`
+struct Trampolines<'a> {
+    error_callback: Option<ErrorCallback<'a>>,
+    import_callback: Option<ImportCallback<'a>>,
+    import_observer: Option<ImportObserver<'a>>,
+    /// When set, errors are collected here instead of being passed to `error_callback`
+    captured_errors: Option<Vec<CapturedError>>,
+    /// The last error reported by J*, used by `VM::format_error`
+    last_error: Option<CapturedError>,
+    /// Modules whose globals cannot be set anymore (see `VM::freeze_module`)
+    frozen_modules: HashSet<std::string::String>,
+    /// Native registries of the modules resolved by the import callback (see `VM::reload_module`)
+    native_regs: HashMap<std::string::String, *mut ffi::JStarNativeReg>,
+    /// Globals of `__main__` holding the functions of the [CompiledExpr]s
+    exprs: Rc<RefCell<RootedGlobals>>,
+    /// Globals of the hidden module holding the callables of the [EventHandler]s
+    handlers: Rc<RefCell<RootedGlobals>>,
+    /// Addresses of the live userdata pushed by `VM::push_userdata`, with the type they hold
+    userdata: Rc<UserdataRegistry>,
+}
+
+/// Maps the addresses of live userdata to the type of the value they hold.
+type UserdataRegistry = RefCell<HashMap<usize, TypeId>>;
+
+/// A Rust closure pushing a lazily computed value (see [Lazy](crate::convert::Lazy)).
+type Thunk = dyn Fn(&mut VM) -> Result<()>;
+
+impl<'a> Trampolines<'a> {
+    /// Records an error reported by J*, and either captures it or passes it to the error callback.
+    fn report_error(&mut self, res: ffi::JStarResult, file: &str, line: Option<i32>, msg: &str) {
+        let err = || Error::try_from(res).expect("err shouldn't be JStarResult::Success");
+        let captured = || CapturedError {
+            error: err(),
+            file: file.to_owned(),
+            line,
+            message: msg.to_owned(),
+        };
+
+        self.last_error = Some(captured());
+
+        if let Some(ref mut errors) = self.captured_errors {
+            errors.push(captured());
+        } else if let Some(ref mut error_callback) = self.error_callback {
+            error_callback(err(), file, line, msg);
+        }
+    }
+}
+
+/// An error reported by the J* vm, collected by [VM::capture_errors] or returned by
+/// [VM::last_error].
+///
+/// Its fields mirror the arguments passed to the error callback (see [Conf::error_callback]).
+#[derive(Debug)]
+pub struct CapturedError {
+    /// The kind of error that has been reported.
+    pub error: Error,
+    /// The path of the file that caused the error.
+    pub file: std::string::String,
+    /// The line that caused the error, if available.
+    pub line: Option<i32>,
+    /// The error message.
+    pub message: std::string::String,
+}
+
+extern "C" fn error_trampoline(
+    vm: *mut ffi::JStarVM,
+    res: ffi::JStarResult,
+    file: *const c_char,
+    line: c_int,
+    error: *const c_char,
+) {
+    // SAFETY: jsrGetCustomData() always returns a `*const Trampolines` by construction (see
+    // `NewVM::new`) and so the cast is safe.
+    // Also, the Trampolines struct is guaranteed to live as long as the vm does, as it is stored
+    // as an owned Box inside of it (`Owned` variant of `VMOwnership` enum). Since this function can
+    // only be called during the lifetime of the vm, the dereference is safe.
+    let trampolines = unsafe { &mut *(ffi::jsrGetCustomData(vm) as *mut Trampolines) };
+
+    let line = if line > 0 { Some(line) } else { None };
+
+    // SAFETY: `file` comes from the J* API that guarantess that is a valid cstring. J* strings
+    // are arbitrary bytes, so it is converted lossily instead of panicking across the C boundary
+    let file = unsafe { CStr::from_ptr(file) }.to_string_lossy();
+
+    // SAFETY: `error` comes from the J* API that guarantess that is a valid cstring. Exception
+    // messages can contain arbitrary bytes, so it is converted lossily as well
+    let error = unsafe { CStr::from_ptr(error) }.to_string_lossy();
+
+    trampolines.report_error(res, &file, line, &error);
+}
+
+extern "C" fn import_trampoline(
+    vm: *mut ffi::JStarVM,
+    module_name: *const c_char,
+) -> ffi::JStarImportResult {
+    // SAFETY: ditto
+    let trampolines = unsafe { &mut *(ffi::jsrGetCustomData(vm) as *mut Trampolines) };
+
+    // SAFETY: `module_name` comes from the J* API that guarantess that is a valid cstring and utf8
+    let module_name = unsafe { CStr::from_ptr(module_name) }
+        .to_str()
+        .expect("module_name is not valid utf8");
+
+    let module = if let Some(ref mut import_callback) = trampolines.import_callback {
+        // SAFETY: this function can only be called during the lifetime of the vm, so it is
+        // guaranteed that the returned returned wrapper is safe to use
+        let mut vm = unsafe { VM::from_ptr(vm) };
+        import_callback(&mut vm, module_name)
+    } else {
+        None
+    };
+
+    if let Some(ref mut import_observer) = trampolines.import_observer {
+        import_observer(module_name, module.is_some());
+    }
+
+    match module {
+        None => ffi::JStarImportResult::default(),
+        Some(module) => {
+            let (code, path, reg) = match module {
+                Module::Source { src, path, reg } => (src.into(), path, reg),
+                Module::Binary { code, path, reg } => (code, path, reg),
+            };
+            trampolines.native_regs.insert(module_name.to_owned(), reg);
+
+            struct ImportData(Vec<u8>, CString);
+            let import_data = Box::new(ImportData(code, path));
+
+            // Callback function that drops data allocated during `import_callback`
+            extern "C" fn finalize_import(user_data: *mut c_void) {
+                // SAFETY: user_data is a `*mut ImportData` obtained from a Box, so it is safe
+                // to construct a new `Box` from it
+                let _ = unsafe { Box::from_raw(user_data as *mut ImportData) };
+            }
+
+            ffi::JStarImportResult {
+                code: import_data.0.as_ptr() as *const c_char,
+                code_len: import_data.0.len(),
+                path: import_data.1.as_ptr(),
+                reg,
+                finalize: Some(finalize_import),
+                user_data: Box::into_raw(import_data) as *mut _ as *mut c_void,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::{
+        convert::{FromJStar, Lazy, ToJStar},
+        error::ExceptionKind,
+        import::FsResolver,
+        native, CORE_MODULE, MAIN_MODULE,
+    };
+
+    #[test]
+    fn eval() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.eval("<string>", "print('Hello, World!')").unwrap();
+    }
+
+    #[test]
+    fn eval_each() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.eval_each(&["var a = 1", "var b = a + 1", "std.assert(b == 2)"])
+            .unwrap();
+
+        let err = vm
+            .eval_each(&["var c = 1", "raise Exception()", "c = 2"])
+            .unwrap_err();
+        match err {
+            Error::InFragment { index, source } => {
+                assert_eq!(index, 1);
+                assert!(matches!(*source, Error::Runtime));
+            }
+            _ => panic!("expected an `InFragment` error"),
+        }
+        vm.eval("<string>", "std.assert(c == 1)").unwrap();
+    }
+
+    #[test]
+    fn eval_bin() {
+        let vm = VM::new(Conf::new());
+        let code = vm
+            .compile_in_memory("<string>", "print('Hello, World!')")
+            .unwrap();
+
+        let vm = vm.init_runtime();
+        vm.eval("<string>", code).unwrap();
+    }
+
+    #[test]
+    fn compile_all() {
+        let vm = VM::new(Conf::new());
+
+        let compiled = vm
+            .compile_all(&[("a.jsr", "var a = 1"), ("b.jsr", "var b = 2")])
+            .unwrap();
+        assert_eq!(compiled.len(), 2);
+        assert_eq!(compiled[0].0, "a.jsr");
+        assert_eq!(compiled[1].0, "b.jsr");
+
+        let err = vm
+            .compile_all(&[
+                ("a.jsr", "var a = 1"),
+                ("bad.jsr", "for end"),
+                ("b.jsr", "var b = 2"),
+            ])
+            .unwrap_err();
+
+        match err {
+            Error::InFile { path, source } => {
+                assert_eq!(path, "bad.jsr");
+                assert!(matches!(*source, Error::Syntax));
+            }
+            _ => panic!("expected an `InFile` error"),
+        }
+    }
+
+    #[test]
+    fn bytecode_equal() {
+        let vm = VM::new(Conf::new());
+        let src = "fun add(a, b)\n return a + b\nend\nvar x = add(1, 2)";
+
+        let a = vm.compile_in_memory("<string>", src).unwrap();
+        let b = vm.compile_in_memory("<string>", src).unwrap();
+        assert!(crate::bytecode_equal(&a, &b));
+
+        let c = vm.compile_in_memory("<string>", "var x = 1 + 2").unwrap();
+        assert!(!crate::bytecode_equal(&a, &c));
+    }
+
+    #[test]
+    fn disassemble() {
+        let vm = VM::new(Conf::new().error_callback(Box::new(|_, _, _, _| {})));
+        let code = vm.compile_in_memory("<string>", "var x = 1 + 2").unwrap();
+        vm.disassemble("<string>", &code).unwrap();
+
+        let res = vm.disassemble("<string>", b"var x = 1 + 2");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn compile_write_error() {
+        struct ClosedSink;
+
+        impl Write for ClosedSink {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let vm = VM::new(Conf::new());
+        let err = vm
+            .compile("<string>", "print('Hello, World!')", ClosedSink)
+            .unwrap_err();
+
+        match err {
+            Error::IO { context, source } => {
+                assert_eq!(context.unwrap(), "writing bytecode for `<string>`");
+                assert_eq!(source.kind(), std::io::ErrorKind::BrokenPipe);
+            }
+            _ => panic!("expected an I/O error"),
+        }
+    }
+
+    #[test]
+    fn raw_parts() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let (ptr, custom_data) = unsafe { vm.raw_parts() };
+        assert!(!custom_data.is_null());
+
+        let wrapper = unsafe { VM::from_ptr(ptr) };
+        assert_eq!(unsafe { wrapper.raw_parts() }, (ptr, custom_data));
+        wrapper.eval("<string>", "var x = 42").unwrap();
+        drop(wrapper);
+
+        vm.get_global(MAIN_MODULE, "x").unwrap();
+        assert_eq!(i32::from_jstar(&vm, -1), Some(42));
+        vm.pop();
+    }
+
+    #[test]
+    fn eval_in_module() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval_in_module("<string>", "test", "var x = 42").unwrap();
+
+        vm.get_global("test", "x").unwrap();
+        assert!(i32::from_jstar(&vm, -1).unwrap() == 42);
+        vm.pop();
+
+        let res = vm.get_global(MAIN_MODULE, "x");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+    }
+
+    #[test]
+    fn eval_in_module_bin() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        let code = vm.compile_in_memory("<test>", "var x = 42").unwrap();
+        vm.eval_in_module("<string>", "test", code).unwrap();
+
+        vm.get_global("test", "x").unwrap();
+        assert!(i32::from_jstar(&vm, -1).unwrap() == 42);
+        vm.pop();
+
+        let res = vm.get_global(MAIN_MODULE, "x");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+    }
+
+    #[test]
+    fn eval_with_globals() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval_with_globals(
+            "<string>",
+            "std.assert(x == 10 and name == 'jstar')",
+            &[("x", &10), ("name", &"jstar")],
+        )?;
+        vm.eval("<string>", "std.assert(x == 10)")?;
+
+        vm.freeze_module(MAIN_MODULE);
+        let res = vm.eval_with_globals("<string>", "", &[("x", &11)]);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    fn eval_with_scoped_globals() -> Result<()> {
+        let mut vm = VM::new(Conf::new().error_callback(Box::new(|_, _, _, _| {}))).init_runtime();
+        vm.eval_with_scoped_globals("<string>", "std.assert(y == 20)", &[("y", &20)])?;
+        vm.eval("<string>", "std.assert(y == null)")?;
+
+        // The globals are cleared even if the evaluation fails
+        let res = vm.eval_with_scoped_globals("<string>", "raise Exception()", &[("z", &1)]);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.eval("<string>", "std.assert(z == null)")?;
+
+        // Existing globals get their previous value back
+        vm.eval("<string>", "var w = 'previous'")?;
+        vm.eval_with_scoped_globals("<string>", "std.assert(w == 5)", &[("w", &5)])?;
+        vm.eval("<string>", "std.assert(w == 'previous')")?;
+
+        let res = vm.eval_with_scoped_globals("<string>", "", &[("w", &5), ("bad\0", &1)]);
+        assert!(matches!(res, Err(Error::InvalidString(_))));
+        assert!(!vm.validate_slot(-1));
+        vm.eval("<string>", "std.assert(w == 'previous')")?;
+        Ok(())
+    }
+
+    #[test]
+    fn compile_expr() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let expr = vm.compile_expr(&["a", "b"], "a + b")?;
+        for (a, b) in [(1, 2), (10, 20), (-5, 5)] {
+            expr.eval(&mut vm, &[&a, &b])?;
+            assert_eq!(vm.get_number(-1), Some((a + b) as f64));
+            vm.pop();
+        }
+
+        let constant = vm.compile_expr(&[], "'const'")?;
+        constant.eval(&mut vm, &[])?;
+        assert_eq!(vm.get_string(-1).unwrap(), "const");
+        vm.pop();
+
+        expr.eval(&mut vm, &[&1, &"a"])
+            .expect_err("adding a number and a string should fail");
+        vm.pop();
+
+        assert!(matches!(vm.compile_expr(&["a"], "a +"), Err(Error::Syntax)));
+
+        // Expressions see the globals of `__main__`
+        vm.eval("<string>", "var scale = 10")?;
+        let scaled = vm.compile_expr(&["a"], "a * scale")?;
+        scaled.eval(&mut vm, &[&4])?;
+        assert_eq!(vm.get_number(-1), Some(40.0));
+        vm.pop();
+
+        // The function is released once the last clone is dropped, and its global reused
+        let name = scaled.global.name.clone();
+        let clone = scaled.clone();
+        drop(scaled);
+        let other = vm.compile_expr(&[], "0")?;
+        assert_ne!(other.global.name, name);
+        vm.get_global(MAIN_MODULE, &name)?;
+        assert!(vm.is_callable(-1));
+        vm.pop();
+        drop(clone);
+        let reused = vm.compile_expr(&[], "1")?;
+        assert_eq!(reused.global.name, name);
+        reused.eval(&mut vm, &[])?;
+        assert_eq!(vm.get_number(-1), Some(1.0));
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    fn compile_expr_escape() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        assert!(matches!(
+            vm.compile_expr(&["a) end fun evil(a"], "a"),
+            Err(Error::Syntax)
+        ));
+        assert!(matches!(
+            vm.compile_expr(&["a"], "a)\nend\nvar evil = (1"),
+            Err(Error::Syntax)
+        ));
+        assert!(matches!(
+            vm.compile_expr(&["a"], "a // (\n)\nend\nvar evil = (1"),
+            Err(Error::Syntax)
+        ));
+        assert!(vm.compile_expr(&["a"], "a + ')' + \"(\\\"\"").is_ok());
+        assert!(vm.compile_expr(&["a"], "[a, (a)] // )").is_err());
+    }
+
+    #[test]
+    fn nul_in_strings() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        assert!(matches!(
+            vm.eval("bad\0path", "var a = 1"),
+            Err(Error::InvalidString(_))
+        ));
+        assert!(matches!(
+            vm.eval_in_module("<string>", "bad\0module", ""),
+            Err(Error::InvalidString(_))
+        ));
+        assert!(matches!(
+            vm.get_global(MAIN_MODULE, "bad\0name"),
+            Err(Error::InvalidString(_))
+        ));
+
+        crate::native!(fn noop(_vm) { Ok(()) });
+        assert!(matches!(
+            vm.register_native(MAIN_MODULE, "bad\0name", noop, 0),
+            Err(Error::InvalidString(_))
+        ));
+
+        crate::native!(fn badGlobal(vm) {
+            vm.get_global(MAIN_MODULE, "bad\0name")
+        });
+        vm.register_native(MAIN_MODULE, "badGlobal", badGlobal, 0)
+            .unwrap();
+        vm.eval(
+            "<string>",
+            "try
+                badGlobal()
+                std.assert(false)
+            except Exception e
+            end",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Module::source("var a = 1".to_owned(), "bad\0path".to_owned()),
+            Err(Error::InvalidString(_))
+        ));
+        assert!(matches!(
+            Module::binary(Vec::new(), "bad\0path".to_owned()),
+            Err(Error::InvalidString(_))
+        ));
+
+        vm.raise("Exception", "bad\0message");
+        vm.set_global(MAIN_MODULE, "exc").unwrap();
+        vm.pop();
+        vm.eval("<string>", "std.assert(exc._err == 'bad\u{FFFD}message')")
+            .unwrap();
+    }
+
+    #[test]
+    fn lazy_values() -> Result<()> {
+        use std::cell::Cell;
+
+        let vm = VM::new(Conf::new()).init_runtime();
+        let runs = Rc::new(Cell::new(0));
+        let lazy = Lazy::new({
+            let runs = Rc::clone(&runs);
+            move |vm: &mut VM| {
+                runs.set(runs.get() + 1);
+                vm.push_string("expensive");
+                Ok(())
+            }
+        });
+        lazy.to_jstar(&vm);
+        vm.set_global(MAIN_MODULE, "config")?;
+        vm.eval("<string>", "var unused = 1")?;
+        assert_eq!(runs.get(), 0);
+
+        vm.eval(
+            "<string>",
+            "std.assert(config() == 'expensive')
+            std.assert(config() == 'expensive')",
+        )?;
+        assert_eq!(runs.get(), 1);
+
+        // The closure is dropped along with the J* function
+        drop(lazy);
+        assert_eq!(Rc::strong_count(&runs), 2);
+        vm.eval("<string>", "config = null; garbageCollect()")?;
+        assert_eq!(Rc::strong_count(&runs), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn lazy_values_unbalanced() -> Result<()> {
+        let vm = VM::new(Conf::new()).init_runtime();
+        Lazy::new(|vm: &mut VM| {
+            vm.push_number(1.0);
+            vm.push_number(2.0);
+            Ok(())
+        })
+        .to_jstar(&vm);
+        vm.set_global(MAIN_MODULE, "config")?;
+        vm.eval(
+            "<string>",
+            "var exc
+            try
+                config()
+            except Exception e
+                exc = e
+            end
+            std.assert(exc is Exception)
+            std.assert(exc._err == '`Lazy` closure must push exactly one value, it pushed 2')",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn userdata() -> Result<()> {
+        struct Counter {
+            count: u32,
+            drops: Rc<std::cell::Cell<u32>>,
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                self.drops.set(self.drops.get() + 1);
+            }
+        }
+
+        let drops = Rc::new(std::cell::Cell::new(0));
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_userdata(Counter {
+            count: 1,
+            drops: Rc::clone(&drops),
+        });
+        assert!(vm.is_userdata(-1));
+        vm.get_userdata_mut::<Counter>(-1).unwrap().count += 1;
+        assert_eq!(vm.get_userdata::<Counter>(-1).unwrap().count, 2);
+        assert_eq!(vm.check_userdata::<Counter>(-1, "counter")?.count, 2);
+
+        assert!(vm.get_userdata::<u32>(-1).is_none());
+        assert!(matches!(
+            vm.check_userdata::<u32>(-1, "counter"),
+            Err(Error::Runtime)
+        ));
+        vm.pop_n(2);
+
+        vm.push_boolean(true);
+        assert!(!vm.is_userdata(-1));
+        assert!(vm.get_userdata::<Counter>(-1).is_none());
+        vm.pop();
+
+        // A native run by a re-entrant evaluation can't mutably borrow a value that is already
+        // borrowed
+        crate::native!(fn bumpCounter(vm) {
+            vm.get_global(MAIN_MODULE, "counter")?;
+            let bumped = vm.get_userdata_mut::<Counter>(-1).map(|mut c| c.count += 1);
+            vm.push_boolean(bumped.is_some());
+            Ok(())
+        });
+        vm.register_native(MAIN_MODULE, "bumpCounter", bumpCounter, 0)?;
+        vm.push_userdata(Counter {
+            count: 0,
+            drops: Rc::clone(&drops),
+        });
+        vm.set_global(MAIN_MODULE, "counter")?;
+        {
+            let counter = vm.get_userdata::<Counter>(-1).unwrap();
+            vm.eval("<string>", "std.assert(!bumpCounter())")?;
+            assert_eq!(counter.count, 0);
+        }
+        vm.eval("<string>", "std.assert(bumpCounter())")?;
+        assert_eq!(vm.get_userdata::<Counter>(-1).unwrap().count, 1);
+        vm.pop();
+
+        assert_eq!(drops.get(), 0);
+        drop(vm);
+        assert_eq!(drops.get(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn handles() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let mut connection = 42u32;
+        let ptr = &mut connection as *mut u32 as *mut c_void;
+
+        vm.eval("<string>", "fun passThrough(handle) return handle end")?;
+        vm.get_global(MAIN_MODULE, "passThrough")?;
+        vm.push_handle(ptr);
+        vm.call(1)?;
+        assert!(vm.is_handle(-1));
+        assert_eq!(vm.get_handle(-1), Some(ptr));
+        assert_eq!(vm.check_handle(-1, "handle")?, ptr);
+        vm.pop();
+
+        vm.push_number(1.0);
+        assert!(!vm.is_handle(-1));
+        assert_eq!(vm.get_handle(-1), None);
+        assert!(matches!(vm.check_handle(-1, "handle"), Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
+    }
+
+    #[test]
+    fn table_api() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "fun area(conf)
+                return conf['width'] * conf['height']
+            end",
+        )?;
+
+        let conf = HashMap::from([("width", 80.0), ("height", 24.0)]);
+        vm.get_global(MAIN_MODULE, "area")?;
+        vm.push_table();
+        assert!(vm.is_table(-1));
+        for (key, value) in &conf {
+            vm.push_string(key);
+            vm.push_number(*value);
+            vm.table_put(-3)?;
+            vm.pop_n(2);
+        }
+
+        vm.push_string("width");
+        vm.table_get(-2)?;
+        assert_eq!(vm.get_number(-1), Some(80.0));
+        vm.pop_n(2);
+
+        vm.push_string("missing");
+        vm.table_get(-2)?;
+        assert!(vm.is_null(-1));
+        vm.pop_n(2);
+
+        vm.call(1)?;
+        assert_eq!(vm.get_number(-1), Some(1920.0));
+
+        vm.push_string("key");
+        let res = vm.table_get(-2);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop_n(2);
+        vm.eval("<string>", "std.assert(exc is TypeException)")?;
+
+        // The key and the value are left below the exception on failure
+        vm.push_string("key");
+        vm.push_number(1.0);
+        let res = vm.table_put(-3);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop();
+        assert_eq!(vm.get_number(-1), Some(1.0));
+        vm.pop_n(2);
+        assert_eq!(vm.get_number(-1), Some(1920.0));
+        vm.pop();
+        vm.eval("<string>", "std.assert(exc is TypeException)")?;
+        Ok(())
+    }
+
+    #[test]
+    fn table_for_each() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let json: HashMap<String, Value> = HashMap::from([
+            ("name".to_owned(), Value::String(b"jstar".to_vec())),
+            ("version".to_owned(), Value::Number(2.0)),
+        ]);
+        json.to_jstar(&vm);
+
+        let mut keys = Vec::new();
+        vm.table_for_each(-1, |vm| {
+            keys.push(vm.check_string(-2, "key")?.to_string_lossy().into_owned());
+            assert!(vm.get_value_owned(-1).is_some());
+            Ok(())
+        })?;
+        keys.sort();
+        assert_eq!(keys, ["name", "version"]);
+
+        let res = vm.table_for_each(-1, |vm| vm.check_number(-1, "value").map(drop));
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop();
+        vm.eval("<string>", "std.assert(exc is TypeException)")?;
+
+        let res = vm.table_for_each(-1, |vm| {
+            vm.push_null();
+            Ok(())
+        });
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop_n(2);
+        vm.eval(
+            "<string>",
+            "std.assert(exc._err == '`f` must leave the stack unchanged')",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn register_natives() -> Result<()> {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        crate::native_ret!(fn add(vm, a: f64, b: f64) -> f64 { Ok(a + b) });
+        crate::native_ret!(fn sub(vm, a: f64, b: f64) -> f64 { Ok(a - b) });
+        crate::native_ret!(fn mul(vm, a: f64, b: f64) -> f64 { Ok(a * b) });
+        crate::native_ret!(fn neg(vm, a: f64) -> f64 { Ok(-a) });
+        crate::native_ret!(fn zero(vm) -> f64 { Ok(0.0) });
+
+        vm.eval_in_module("<string>", "math_natives", "")?;
+        vm.register_natives(
+            "math_natives",
+            &[
+                ("add", add, 2),
+                ("sub", sub, 2),
+                ("mul", mul, 2),
+                ("neg", neg, 1),
+                ("zero", zero, 0),
+            ],
+        )?;
+        vm.eval_in_module(
+            "<string>",
+            "math_natives",
+            "std.assert(add(1, 2) == 3)
+            std.assert(sub(1, 2) == -1)
+            std.assert(mul(3, 2) == 6)
+            std.assert(neg(4) == -4)
+            std.assert(zero() == 0)",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn eval_ignoring_errors() {
+        let mut reported = 0;
+        let conf = Conf::new().error_callback(Box::new(|_, _, _, _| reported += 1));
+        let mut vm = VM::new(conf).init_runtime();
+        vm.push_number(1.0);
+
+        assert!(!vm.eval_ignoring_errors("<cleanup>", "raise Exception('cleanup failed')"));
+        assert!(!vm.eval_ignoring_errors("<cleanup>", "var = "));
+        assert!(vm.eval_ignoring_errors("<cleanup>", "var cleaned = true"));
+        assert_eq!(vm.stack_depth(), 1);
+        assert_eq!(vm.get_number(-1), Some(1.0));
+        assert!(matches!(vm.last_error().unwrap().error, Error::Syntax));
+
+        vm.eval("<string>", "std.assert(cleaned)").unwrap();
+        drop(vm);
+        assert_eq!(reported, 0);
+    }
+
+    #[test]
+    fn eval_with_timeout() {
+        let mut vm = VM::new(Conf::new().error_callback(Box::new(|_, _, _, _| {}))).init_runtime();
+        let timeout = Duration::from_millis(100);
+
+        vm.eval_with_timeout("<string>", "var x = 1 + 2", timeout)
+            .unwrap();
+        vm.eval("<string>", "std.assert(x == 3)").unwrap();
+
+        let res = vm.eval_with_timeout("<string>", "while true end", timeout);
+        assert!(matches!(res, Err(Error::Runtime)));
+        let last = vm.last_error().unwrap();
+        assert!(last.message.contains("ProgramInterrupt"));
+    }
+
+    #[test]
+    fn last_error() {
+        let vm = VM::new(Conf::new().error_callback(Box::new(|_, _, _, _| {}))).init_runtime();
+        assert!(vm.last_error().is_none());
+
+        let err = vm.eval("syntax.jsr", "var = 1").unwrap_err();
+        assert!(matches!(err, Error::Syntax));
+        let last = vm.last_error().unwrap();
+        assert!(matches!(last.error, Error::Syntax));
+        assert_eq!(last.file, "syntax.jsr");
+        assert_eq!(last.line, Some(1));
+
+        vm.eval("runtime.jsr", "raise Exception('boom')")
+            .unwrap_err();
+        let last = vm.last_error().unwrap();
+        assert!(matches!(last.error, Error::Runtime));
+        assert!(last.message.contains("boom"));
+    }
+
+    #[test]
+    fn native_args() -> Result<()> {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        crate::native!(fn describe(vm) {
+            let args = vm.args();
+            let name = args.string(1, "name")?.to_string_lossy().into_owned();
+            let count = args.number(2, "count")?;
+            let scale = args.optional_number(3, "scale")?.unwrap_or(1.0);
+            format!("{name}: {}", count * scale).to_jstar(vm);
+            Ok(())
+        });
+
+        vm.register_native(MAIN_MODULE, "describe", describe, 3)?;
+        vm.eval(
+            "<string>",
+            "std.assert(describe('a', 2, 3) == 'a: 6')
+            std.assert(describe('b', 2, null) == 'b: 2')
+            try
+                describe('c', 'two', null)
+                std.assert(false)
+            except TypeException e
+            end",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn event_handler() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "var events = []
+            fun onEvent(a, b)
+                events.add(a + b)
+            end
+            fun onError()
+                raise Exception('handler failed')
+            end",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "onEvent")?;
+        let handler = vm.get_closure(-1)?;
+        vm.pop();
+        handler.invoke(&mut vm, &[&1, &2])?;
+        handler.invoke(&mut vm, &[&"a", &"b"])?;
+        vm.eval(
+            "<string>",
+            "std.assert(events[0] == 3 and events[1] == 'ab')",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "onError")?;
+        let failing = vm.get_closure(-1)?;
+        vm.pop();
+        let (res, errors) = vm.capture_errors(|vm| failing.invoke(vm, &[]));
+        assert!(matches!(res, Err(Error::Runtime)));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("handler failed"));
+        assert!(!vm.validate_slot(-1));
+
+        // The global of a dropped handler is reused by the next one
+        let name = handler.global.name.clone();
+        drop(handler);
+        vm.get_global(MAIN_MODULE, "onEvent")?;
+        let reused = vm.get_closure(-1)?;
+        vm.pop();
+        assert_eq!(reused.global.name, name);
+        reused.invoke(&mut vm, &[&3, &4])?;
+        vm.eval("<string>", "std.assert(events[2] == 7)")?;
+
+        vm.push_number(1.0);
+        assert!(matches!(vm.get_closure(-1), Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
+    }
+
+    #[test]
+    fn run_module() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let res: i32 = vm.run_module("plugin.jsr", "plugin", "var export = 40 + 2", "export")?;
+        assert_eq!(res, 42);
+
+        let res = vm.run_module::<i32>("plugin.jsr", "plugin", "export = 'str'", "export");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+
+        let res = vm.run_module::<i32>("plugin.jsr", "plugin", "var a = 1", "missing");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    fn call() -> Result<()> {
+        let vm = VM::new(Conf::new());
+        let mut vm = vm.init_runtime();
+
+        vm.eval("<string>", "var add = |a, b| => a + b")?;
+        vm.get_global(MAIN_MODULE, "add")?;
+
+        3.to_jstar(&vm);
+        2.to_jstar(&vm);
+        vm.call(2)?;
+
+        let n = i32::from_jstar(&vm, -1).ok_or(Error::Runtime)?;
+        assert_eq!(n, 5);
+
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    fn call_dyn_args() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        vm.eval("<string>", "var concat = |a, b| => String(a) + b")?;
+        vm.get_global(MAIN_MODULE, "concat")?;
+
+        let args: Vec<Box<dyn ToJStar>> = vec![Box::new(1i32), Box::new("a")];
+        for arg in &args {
+            arg.to_jstar(&vm);
+        }
+        vm.call(args.len() as u8)?;
+
+        let s = JStarString::from_jstar(&vm, -1).ok_or(Error::Runtime)?;
+        assert_eq!(s, "1a");
+
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    fn call_multi() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "fun sumDiff(a, b)
+                return (a + b, a - b)
+            end",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "sumDiff")?;
+        vm.push_number(7.0);
+        vm.push_number(2.0);
+        let res = vm.call_multi(2, 2)?;
+        assert_eq!(res, vec![Value::Number(9.0), Value::Number(5.0)]);
+
+        vm.get_global(MAIN_MODULE, "sumDiff")?;
+        vm.push_number(7.0);
+        vm.push_number(2.0);
+        let res = vm.call_multi(2, 3);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    fn call_with() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "var calls = 0
+            fun add(a, b)
+                return a + b
+            end
+            fun touch()
+                calls += 1
+            end",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "add")?;
+        let sum: i32 = vm.call_with(&[&1, &2])?;
+        assert_eq!(sum, 3);
+
+        vm.get_global(MAIN_MODULE, "touch")?;
+        vm.call_with::<()>(&[])?;
+
+        vm.get_global(MAIN_MODULE, "add")?;
+        let res = vm.call_with::<()>(&[&1, &2]);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+
+        vm.eval("<string>", "std.assert(calls == 1)")?;
+        Ok(())
+    }
+
+    #[test]
+    fn call_opt() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "fun greet(name, greeting='Hello', punct='!')
+                return String(greeting) + ' ' + name + punct
+            end",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "greet")?;
+        vm.call_opt(&[&"jstar"], &[Some(&"Hi"), None])?;
+        assert_eq!(JStarString::from_jstar(&vm, -1).unwrap(), "Hi jstar!");
+        vm.pop();
+
+        vm.get_global(MAIN_MODULE, "greet")?;
+        vm.call_opt(&[&"jstar"], &[None, None])?;
+        assert_eq!(JStarString::from_jstar(&vm, -1).unwrap(), "Hello jstar!");
+        vm.pop();
+
+        vm.get_global(MAIN_MODULE, "greet")?;
+        vm.call_opt(&[&"jstar"], &[None, Some(&"?")])?;
+        assert_eq!(JStarString::from_jstar(&vm, -1).unwrap(), "null jstar?");
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn call_panic() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.get_global(CORE_MODULE, "print").unwrap();
+        vm.call(2).unwrap();
+    }
+
+    #[test]
+    fn get_global() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        vm.eval("<string>", "var test = 'test'").unwrap();
+        vm.get_global(MAIN_MODULE, "test").unwrap();
+        let s = JStarString::from_jstar(&vm, -1).unwrap();
+        assert_eq!(s, "test");
+
+        vm.pop();
+    }
+
+    #[test]
+    fn get_global_fail() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.eval("<string>", "var test = 'test'").unwrap();
+        let res = vm.get_global(MAIN_MODULE, "doesnotexist").unwrap_err();
+        assert!(matches!(res, Error::Runtime));
+    }
+
+    #[test]
+    fn get_global_fail_module() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        let res = vm.get_global("does_not_exist", "doesnotexist").unwrap_err();
+        assert!(matches!(res, Error::Runtime));
+    }
+
+    #[test]
+    fn with_module() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        vm.with_module("scoped", |m| {
+            m.eval("<scoped>", "var a, b = 1, 2")?;
+
+            3.to_jstar(m);
+            m.set_global("c")?;
+            m.pop();
+
+            m.get_global("a")?;
+            m.get_global("b")?;
+            m.get_global("c")?;
+            assert_eq!(i32::from_jstar(m, -3), Some(1));
+            assert_eq!(i32::from_jstar(m, -2), Some(2));
+            assert_eq!(i32::from_jstar(m, -1), Some(3));
+            m.pop_n(3);
+
+            Ok::<_, Error>(())
+        })?;
+
+        vm.get_global("scoped", "c")?;
+        assert_eq!(i32::from_jstar(&vm, -1), Some(3));
+        vm.pop();
+
+        let res = vm.get_global(MAIN_MODULE, "c");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_global() {
+        let vm = VM::new(Conf::new());
+        let mut vm = vm.init_runtime();
+
+        vm.eval("<setglb>", "var test = 'test'").unwrap();
+
+        42.to_jstar(&vm);
+        vm.set_global(MAIN_MODULE, "test").unwrap();
+        vm.pop();
+
+        vm.eval("<setglb>", "std.assert(test == 42)").unwrap();
+    }
+
+    #[test]
+    fn set_global_fail() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        42.to_jstar(&vm);
+        let res = vm.set_global("does_not_exist", "test");
+        vm.pop();
+
+        assert!(matches!(res, Err(Error::Runtime)));
+    }
+
+    #[test]
+    fn get_or_init_global() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let mut calls = 0;
+
+        vm.get_or_init_global(MAIN_MODULE, "cached", || {
+            calls += 1;
+            42
+        })?;
+        assert_eq!(vm.get_number(-1), Some(42.0));
+        vm.pop();
+
+        vm.get_or_init_global(MAIN_MODULE, "cached", || {
+            calls += 1;
+            0
+        })?;
+        assert_eq!(vm.get_number(-1), Some(42.0));
+        vm.pop();
+
+        assert_eq!(calls, 1);
+        vm.eval("<string>", "std.assert(cached == 42)")?;
+
+        vm.push_null();
+        let res = vm.get_or_init_global(MAIN_MODULE, "nul\0name", || 0);
+        assert!(matches!(res, Err(Error::InvalidString(_))));
+        assert!(vm.is_null(-1));
+        vm.pop();
+        Ok(())
+    }
+
+    #[test]
+    fn freeze_module() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval("<string>", "var x = 1").unwrap();
+        vm.eval_in_module("<string>", "other", "var y = 1").unwrap();
+
+        vm.freeze_module(MAIN_MODULE);
+
+        42.to_jstar(&vm);
+        let res = vm.set_global(MAIN_MODULE, "x");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
+
+        vm.eval("<string>", "std.assert(x == 1)").unwrap();
+
+        42.to_jstar(&vm);
+        vm.set_global("other", "y").unwrap();
+        vm.pop();
+    }
+
+    #[test]
+    fn get_class_new_instance() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "class Point
+                construct(x, y)
+                    this.x = x
+                    this.y = y
+                end
+            end
+            var notClass = 1",
+        )
+        .unwrap();
+
+        vm.get_class(MAIN_MODULE, "Point").unwrap();
+        assert!(vm.is_class(-1));
+        vm.check_class(-1, "cls").unwrap();
+
+        1.to_jstar(&vm);
+        2.to_jstar(&vm);
+        vm.new_instance(2).unwrap();
+        assert!(!vm.is_class(-1));
+
+        vm.set_global(MAIN_MODULE, "p").unwrap();
+        vm.pop();
+        vm.eval(
+            "<string>",
+            "std.assert(p is Point and p.x == 1 and p.y == 2)",
+        )
+        .unwrap();
+
+        let res = vm.get_class(MAIN_MODULE, "notClass");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+
+        vm.push_number(1.0);
+        let res = vm.new_instance(0);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+    }
+
+    #[test]
+    fn deep_equals() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            r#"var a = [1, (2, "x"), {"k": [3, 4], 5: null}]
+            var b = [1, (2, "x"), {"k": [3, 4], 5: null}]
+            var c = [1, (2, "x"), {"k": [3, 5], 5: null}]
+            var cyclic = []
+            cyclic.add(cyclic)"#,
+        )?;
+
+        vm.get_global(MAIN_MODULE, "a")?;
+        vm.get_global(MAIN_MODULE, "b")?;
+        vm.get_global(MAIN_MODULE, "c")?;
+        assert!(vm.deep_equals(-3, -2)?);
+        assert!(!vm.deep_equals(-3, -1)?);
+        vm.pop_n(3);
+
+        vm.get_global(MAIN_MODULE, "cyclic")?;
+        assert!(matches!(vm.deep_equals(-1, -1), Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
+    }
+
+    #[test]
+    fn is_subclass() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "class Animal end
+            class Dog is Animal end
+            class Car end",
+        )?;
+
+        vm.get_class(MAIN_MODULE, "Dog")?;
+        assert!(vm.is_subclass(-1, MAIN_MODULE, "Animal")?);
+        assert!(vm.is_subclass(-1, MAIN_MODULE, "Dog")?);
+        assert!(!vm.is_subclass(-1, MAIN_MODULE, "Car")?);
+        assert!(vm.is_subclass(-1, CORE_MODULE, "Object")?);
+        vm.pop();
+
+        vm.get_class(MAIN_MODULE, "Animal")?;
+        assert!(!vm.is_subclass(-1, MAIN_MODULE, "Dog")?);
+        assert!(matches!(
+            vm.is_subclass(-1, MAIN_MODULE, "Missing"),
+            Err(Error::Runtime)
+        ));
+        vm.pop_n(2);
+
+        vm.push_number(1.0);
+        assert!(matches!(
+            vm.is_subclass(-1, MAIN_MODULE, "Animal"),
+            Err(Error::Runtime)
+        ));
+        vm.pop_n(2);
+        Ok(())
+    }
+
+    #[test]
+    fn push_native() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn id(vm) {
+            let n = vm.get_number(1).unwrap();
+            n.to_jstar(vm);
+            Ok(())
+        });
+
+        vm.push_native(MAIN_MODULE, "id", id, 1).unwrap();
+        vm.set_global(MAIN_MODULE, "id").unwrap();
+        vm.pop();
+
+        vm.eval("<string>", "std.assert(id(42) == 42)").unwrap();
+    }
+
+    #[test]
+    fn push_native_fail() {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn id(vm) {
+            let n = vm.get_number(1).unwrap();
+            n.to_jstar(vm);
+            Ok(())
+        });
+
+        let res = vm.push_native("does_not_exist", "id", id, 1);
+        assert!(matches!(res, Err(Error::Runtime)));
+    }
+
+    #[test]
+    fn register_native() {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn id(vm) {
+            let n = vm.get_number(1).unwrap();
+            n.to_jstar(vm);
+            Ok(())
+        });
+
+        vm.register_native(MAIN_MODULE, "id", id, 1).unwrap();
+
+        vm.eval("<string>", "std.assert(id(42) == 42)").unwrap();
+    }
+
+    #[test]
+    fn push_ok() {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn greet(vm) {
+            let name = JStarString::from_jstar_checked(vm, 1, "name")?;
+            vm.push_ok(format!("Hello, {}!", name.to_string_lossy()).as_str())
+        });
+
+        vm.register_native(MAIN_MODULE, "greet", greet, 1).unwrap();
+        vm.eval("<string>", "std.assert(greet('jstar') == 'Hello, jstar!')")
+            .unwrap();
+    }
+
+    #[test]
+    fn native_ret() {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn manualAdd(vm) {
+            let a = i32::from_jstar_checked(vm, 1, "a")?;
+            let b = i32::from_jstar_checked(vm, 2, "b")?;
+            (a + b).to_jstar(vm);
+            Ok(())
+        });
+
+        crate::native_ret!(fn retAdd(vm, a: i32, b: i32) -> i32 {
+            Ok(a + b)
+        });
+
+        crate::native_ret!(fn retLen(vm, str: JStarString) -> u32 {
+            Ok(str.as_bytes().len() as u32)
+        });
+
+        crate::native_ret!(fn retFail(vm) -> i32 {
+            crate::raise!(vm, "InvalidArgException", "failure");
+        });
+
+        crate::native_ret!(fn retFailFmt(vm, n: i32) -> i32 {
+            crate::raise!(vm, "InvalidArgException", "failure {} %s%d", n);
+        });
+
+        crate::native_ret!(fn retFailCapture(vm, n: i32) -> i32 {
+            crate::raise!(vm, "InvalidArgException", "failure {n}");
+        });
+
+        vm.register_native(MAIN_MODULE, "manualAdd", manualAdd, 2)
+            .unwrap();
+        vm.register_native(MAIN_MODULE, "retAdd", retAdd, 2)
+            .unwrap();
+        vm.register_native(MAIN_MODULE, "retLen", retLen, 1)
+            .unwrap();
+        vm.register_native(MAIN_MODULE, "retFail", retFail, 0)
+            .unwrap();
+        vm.register_native(MAIN_MODULE, "retFailFmt", retFailFmt, 1)
+            .unwrap();
+        vm.register_native(MAIN_MODULE, "retFailCapture", retFailCapture, 1)
+            .unwrap();
+
+        vm.eval(
+            "<string>",
+            "std.assert(retAdd(1, 2) == manualAdd(1, 2))
+            std.assert(retLen('jstar') == 5)
+            try
+                retAdd(1, 'a')
+                std.assert(false)
+            except TypeException e
+            end
+            try
+                retFail()
+                std.assert(false)
+            except InvalidArgException e
+            end
+            try
+                retFailFmt(42)
+                std.assert(false)
+            except InvalidArgException e
+                std.assert(e._err == 'failure 42 %s%d')
+            end
+            try
+                retFailCapture(42)
+                std.assert(false)
+            except InvalidArgException e
+                std.assert(e._err == 'failure 42')
+            end",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn raise_with_cause() {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn wrap(vm) {
+            vm.raise_with_cause("InvalidArgException", "wrapped", 1);
+            Err(Error::Runtime)
+        });
+
+        vm.register_native(MAIN_MODULE, "wrap", wrap, 1).unwrap();
+        vm.eval(
+            "<string>",
+            "var cause = TypeException('inner')
+            try
+                wrap(cause)
+                std.assert(false)
+            except InvalidArgException e
+                std.assert(e._err == 'wrapped')
+                std.assert(e._cause == cause)
+                std.assert(e._cause._err == 'inner')
+            end",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn create_module() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn square(vm) {
+            let n = vm.check_number(1, "n")?;
+            (n * n).to_jstar(vm);
+            Ok(())
+        });
+
+        let mut builder = vm.create_module("mathx");
+        builder
+            .global("version", 2)
+            .native("square", square, 1)
+            .source(
+                "class Vec
+                    construct(x, y)
+                        this.x = x
+                        this.y = y
+                    end
+                    fun norm2()
+                        return square(this.x) + square(this.y)
+                    end
+                end",
+            );
+        builder.install().unwrap();
+
+        vm.eval(
+            "<string>",
+            "import mathx
+            std.assert(mathx.square(3) == 9)
+            std.assert(mathx.version == 2)
+            std.assert(mathx.Vec(3, 4).norm2() == 25)",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn register_native_fail() {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn id(vm) {
+            let n = vm.get_number(1).unwrap();
+            n.to_jstar(vm);
+            Ok(())
+        });
+
+        let res = vm.register_native("does_not_exist", "id", id, 1);
+        assert!(matches!(res, Err(Error::Runtime)));
+    }
+
+    #[test]
+    fn native_call_fail() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn id(vm) {
+            let n = i32::from_jstar_checked(vm, 1, "n")?;
+            n.to_jstar(vm);
+            Ok(())
+        });
+
+        vm.register_native(MAIN_MODULE, "id", id, 1).unwrap();
+
+        vm.get_global(MAIN_MODULE, "id").unwrap();
+        "not a number".to_jstar(&vm);
+
+        let res = vm.call(1);
+        assert!(matches!(res, Err(Error::Runtime)));
+
+        vm.pop();
+
+        vm.get_global(MAIN_MODULE, "id").unwrap();
+        42.to_jstar(&vm);
+        34.to_jstar(&vm);
+
+        let res = vm.call(2);
+        assert!(matches!(res, Err(Error::Runtime)));
+    }
+
+    #[test]
+    #[ignore]
+    #[should_panic]
+    fn native_should_panic() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        native!(fn id(vm) {
+            // Try to pop past stack frame boundary
+            vm.pop_n(2);
+            Ok(())
+        });
+
+        vm.register_native(MAIN_MODULE, "id", id, 0).unwrap();
+        vm.get_global(MAIN_MODULE, "id").unwrap();
+        vm.call(0).unwrap();
+    }
+
+    #[test]
+    fn error_callback() {
+        let mut num_errors = 0;
+        let conf = Conf::new().error_callback(Box::new(|_, _, _, _| {
+            num_errors += 1;
+        }));
+
+        let vm = VM::new(conf).init_runtime();
+
+        let err = vm.eval("<string>", "raise Exception()").unwrap_err();
+        assert!(matches!(err, Error::Runtime));
+
+        let err = vm.eval("<string>", "for end").unwrap_err();
+        assert!(matches!(err, Error::Syntax));
+
+        let err = vm.eval("<string>", "begin var a; var a; end").unwrap_err();
+        assert!(matches!(err, Error::Compile));
+
+        vm.eval("<string>", "var bar = 1 + 2").unwrap();
+
+        drop(vm);
+
+        assert_eq!(num_errors, 3);
+    }
+
+    #[test]
+    fn capture_errors() {
+        let mut num_errors = 0;
+        let conf = Conf::new().error_callback(Box::new(|_, _, _, _| {
+            num_errors += 1;
+        }));
+        let mut vm = VM::new(conf).init_runtime();
+
+        let (res, errors) = vm.capture_errors(|vm| {
+            let syntax = vm.eval("syntax.jsr", "var = ");
+            let runtime = vm.eval("runtime.jsr", "raise Exception('boom')");
+            let ok = vm.eval("ok.jsr", "var ok = true");
+            (syntax, runtime, ok)
+        });
+
+        assert!(matches!(res.0, Err(Error::Syntax)));
+        assert!(matches!(res.1, Err(Error::Runtime)));
+        assert!(res.2.is_ok());
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0].error, Error::Syntax));
+        assert_eq!(errors[0].file, "syntax.jsr");
+        assert!(matches!(errors[1].error, Error::Runtime));
+        assert!(errors[1].message.contains("boom"));
+
+        // The error callback is restored after capturing
+        let _ = vm.eval("syntax.jsr", "var = ");
+
+        // Even if the closure panics
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vm.capture_errors(|_| panic!("capturing failed"))
+        }));
+        assert!(res.is_err());
+        let _ = vm.eval("syntax.jsr", "var = ");
+        drop(vm);
+
+        assert_eq!(num_errors, 2);
+    }
+
+    #[test]
+    fn format_error() {
+        let conf = Conf::new().error_callback(Box::new(|_, _, _, _| {}));
+        let mut vm = VM::new(conf).init_runtime();
+
+        let err = vm.eval("<syntax>", "for end").unwrap_err();
+        let msg = vm.format_error(&err);
+        assert!(msg.starts_with("<syntax>:1: "), "{msg}");
+
+        let err = vm
+            .eval("<runtime>", "raise TypeException('boom')")
+            .unwrap_err();
+        let msg = vm.format_error(&err);
+        assert!(msg.starts_with("Traceback"), "{msg}");
+        assert!(msg.ends_with("TypeException: boom"), "{msg}");
+
+        let err = vm.get_global(MAIN_MODULE, "does_not_exist").unwrap_err();
+        let msg = vm.format_error(&err);
+        assert!(msg.contains("NameException"), "{msg}");
+        vm.pop();
+
+        let err = vm.compile_all(&[("bad.jsr", "for end")]).unwrap_err();
+        let msg = vm.format_error(&err);
+        assert!(msg.starts_with("bad.jsr:1: "), "{msg}");
+        assert!(msg.ends_with(" (in `bad.jsr`)"), "{msg}");
+
+        let err = vm.eval_each(&["raise Exception('first')"]).unwrap_err();
+        let msg = vm.format_error(&err);
+        assert!(msg.contains("Exception: first"), "{msg}");
+        assert!(msg.ends_with(" (in fragment 0)"), "{msg}");
+
+        let err = Error::from(std::io::Error::other("disk"));
+        assert_eq!(vm.format_error(&err), err.to_string());
+        assert_eq!(vm.format_error(&Error::Version), Error::Version.to_string());
+    }
+
+    #[test]
+    fn eval_catching() {
+        let mut num_errors = 0;
+        let conf = Conf::new().error_callback(Box::new(|_, _, _, _| {
+            num_errors += 1;
+        }));
+
+        let mut vm = VM::new(conf).init_runtime();
+
+        let exc = vm
+            .eval_catching(
+                "<string>",
+                "raise TypeException('bad type')",
+                &["TypeException"],
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(exc.class(), "TypeException");
+        assert_eq!(exc.message(), "bad type");
+        assert!(exc.is_class("TypeException"));
+        assert_eq!(exc.kind(), Some(ExceptionKind::TypeException));
+
+        let err = vm
+            .eval_catching(
+                "<string>",
+                "class ValueException is Exception end
+                raise ValueException('bad value')",
+                &["TypeException"],
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::Runtime));
+
+        let res = vm.eval_catching("<string>", "var a = 1", &["TypeException"]);
+        assert!(res.unwrap().is_none());
+
+        drop(vm);
+
+        assert_eq!(num_errors, 1);
+    }
+
+    #[test]
+    fn exception_kind() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "class ValueException is Exception end
+            fun fail(type)
+                if type == 'type'
+                    raise TypeException('bad type')
+                elif type == 'arg'
+                    raise InvalidArgException('bad arg')
+                else
+                    raise ValueException('bad value')
+                end
+            end",
+        )?;
+
+        let classes = ["TypeException", "InvalidArgException", "ValueException"];
+        let mut catch = |type_| {
+            vm.eval_catching("<string>", format!("fail('{type_}')"), &classes)
+                .map(|exc| exc.expect("an exception to be raised"))
+        };
+
+        let exc = catch("type")?;
+        assert_eq!(exc.kind(), Some(ExceptionKind::TypeException));
+        assert!(!exc.is_class("InvalidArgException"));
+
+        let exc = catch("arg")?;
+        assert_eq!(exc.kind(), Some(ExceptionKind::InvalidArgException));
+        assert_eq!(exc.kind().map(|k| k.class()), Some("InvalidArgException"));
+
+        let exc = catch("value")?;
+        assert_eq!(exc.kind(), None);
+        assert!(exc.is_class("ValueException"));
+        Ok(())
+    }
+
+    #[test]
+    fn import_source() {
+        let conf = Conf::new().import_callback(Box::new(|_, module_name| {
+            if module_name == "test" {
+                Module::source("var flag = 1".to_owned(), "<test>".to_owned()).ok()
+            } else {
+                None
+            }
+        }));
+
+        let vm = VM::new(conf).init_runtime();
+
+        vm.eval(
+            "<string>",
+            "import test
+            std.assert(test.flag == 1)",
+        )
+        .unwrap();
+
+        let err = vm.eval("<string>", "import does_not_exist").unwrap_err();
+        assert!(matches!(err, Error::Runtime));
+    }
+
+    #[test]
+    fn import_observer() {
+        let mut observed = Vec::new();
+
+        let conf = Conf::new()
+            .import_callback(Box::new(|_, module_name| {
+                (module_name == "test").then(|| {
+                    Module::source("var flag = 1".to_owned(), "<test>".to_owned()).unwrap()
+                })
+            }))
+            .import_observer(Box::new(|module_name, resolved| {
+                observed.push((module_name.to_owned(), resolved));
+            }));
+
+        let mut vm = VM::new(conf).init_runtime();
+        vm.eval("<string>", "import test").unwrap();
+        let err = vm.eval("<string>", "import does_not_exist").unwrap_err();
+        assert!(matches!(err, Error::Runtime));
+
+        // Reloads are observed as well
+        vm.reload_module("test").unwrap();
+        vm.reload_module("does_not_exist").unwrap_err();
+        vm.pop();
+
+        drop(vm);
+
+        assert_eq!(
+            observed,
+            vec![
+                ("test".to_owned(), true),
+                ("does_not_exist".to_owned(), false),
+                ("test".to_owned(), true),
+                ("does_not_exist".to_owned(), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn import_binary() {
+        let mut err_called = false;
+
+        let conf = Conf::new()
+            .error_callback(Box::new(|err, path, line, msg| {
+                assert!(matches!(err, Error::Runtime));
+                assert_eq!(path, "<string>");
+                assert!(line.is_none());
+                assert_eq!(msg, "Traceback (most recent call last):\n    [line 1] module __main__ in <main>\nImportException: Cannot load module `does_not_exist`.");
+                err_called = true;
+            }))
+            .import_callback(Box::new(|vm, module_name| {
+                if module_name == "test" {
+                    Module::binary(
+                        vm.compile_in_memory("<test>", "var flag = 1").unwrap(),
+                        "<test>".to_owned(),
+                    )
+                    .ok()
+                } else {
+                    None
+                }
+            }
+        ));
+
+        let vm = VM::new(conf).init_runtime();
+
+        vm.eval(
+            "<string>",
+            "import test
+            std.assert(test.flag == 1)",
+        )
+        .unwrap();
+
+        let err = vm.eval("<string>", "import does_not_exist").unwrap_err();
+        assert!(matches!(err, Error::Runtime));
+
+        drop(vm);
+
+        assert!(err_called);
     }
-}
 
-unsafe impl<'a, State> Send for VM<'a, State> {}
+    #[test]
+    fn import_fs_resolver_add_path() {
+        let dir = std::env::temp_dir().join(format!("jstar-rs-fs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("runtime_mod.jsr"), "var flag = 1").unwrap();
 
-/// Enum that serves the purpose of tracking the ownership of a pointer to an [ffi::JStarVM].
-/// Since we need the ability to construct a new rust wrapper around a `*mut JStarVM` when it is
-/// needed (for example in callbakcs, where only a pointer to the vm is available), we need to
-/// keep track which of the `VM` wrappers is the owner of the pointer (and thus is responsible for
-/// its deallocation) and which is only a temporary wrapper (a sort of 'borrow').
-/// This enum accomplishes this need, and it also mantains all of the owned state needed for the VM
-/// to work.
-enum VMOwnership<'a> {
-    Owned(Box<Trampolines<'a>>),
-    NonOwned,
-}
+        let resolver = FsResolver::new(Vec::<std::path::PathBuf>::new());
+        let conf = Conf::new().import_callback(resolver.clone().into_callback());
+        let vm = VM::new(conf).init_runtime();
 
-/// Struct that owns the import and error callbacks called by J* during error handling or import
-/// resolution.
-/// In conjunction with [error_trampoline] and [import_trampoline] it enables the execution of these
-/// functions across an ffi boundary, lifting the requirement of having to declare them as `extern "C"`
-struct Trampolines<'a> {
-    error_callback: Option<ErrorCallback<'a>>,
-    import_callback: Option<ImportCallback<'a>>,
-}
+        let err = vm.eval("<string>", "import runtime_mod").unwrap_err();
+        assert!(matches!(err, Error::Runtime));
 
-extern "C" fn error_trampoline(
-    vm: *mut ffi::JStarVM,
-    res: ffi::JStarResult,
-    file: *const c_char,
-    line: c_int,
-    error: *const c_char,
-) {
-    // SAFETY: jsrGetCustomData() always returns a `*const Trampolines` by construction (see
-    // `NewVM::new`) and so the cast is safe.
-    // Also, the Trampolines struct is guaranteed to live as long as the vm does, as it is stored
-    // as an owned Box inside of it (`Owned` variant of `VMOwnership` enum). Since this function can
-    // only be called during the lifetime of the vm, the dereference is safe.
-    let trampolines = unsafe { &mut *(ffi::jsrGetCustomData(vm) as *mut Trampolines) };
+        resolver.add_path(&dir);
+        vm.eval(
+            "<string>",
+            "import runtime_mod
+            std.assert(runtime_mod.flag == 1)",
+        )
+        .unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reload_module() -> Result<()> {
+        use std::cell::Cell;
+
+        let version = Rc::new(Cell::new(1));
+        let conf = Conf::new().import_callback(Box::new({
+            let version = Rc::clone(&version);
+            move |_, module_name| {
+                (module_name == "reloadable").then(|| {
+                    Module::source(
+                        format!(
+                            "var version = {}
+                            fun getVersion()
+                                return version
+                            end",
+                            version.get()
+                        ),
+                        "reloadable.jsr".to_owned(),
+                    )
+                    .unwrap()
+                })
+            }
+        }));
+        let mut vm = VM::new(conf).init_runtime();
+        vm.eval(
+            "<string>",
+            "import reloadable
+            var before = reloadable.version",
+        )?;
+
+        version.set(2);
+        vm.reload_module("reloadable")?;
+        vm.eval(
+            "<string>",
+            "std.assert(before == 1)
+            std.assert(reloadable.version == 2 and reloadable.getVersion() == 2)",
+        )?;
+
+        let res = vm.reload_module("missing");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop();
+        vm.eval("<string>", "std.assert(exc is ImportException)")?;
+        Ok(())
+    }
+
+    #[test]
+    fn command_line_args() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.init_command_line_args(&["first", "second arg"]).unwrap();
+        vm.eval(
+            "<string>",
+            "import sys
+            std.assert(#sys.args == 2)
+            std.assert(sys.args[0] == 'first' and sys.args[1] == 'second arg')",
+        )
+        .unwrap();
 
-    if let Some(ref mut error_callback) = trampolines.error_callback {
-        let err = Error::try_from(res).expect("err shouldn't be JStarResult::Success");
-        let line = if line > 0 { Some(line) } else { None };
+        let res = vm.init_command_line_args(&["nul\0arg"]);
+        assert!(matches!(res, Err(Error::InvalidString(_))));
+    }
 
-        // SAFETY: `file` comes from the J* API that guarantess that is a valid cstring and utf8
-        let file = unsafe { CStr::from_ptr(file) }
-            .to_str()
-            .expect("file should be valid utf8");
+    #[test]
+    fn with_fs_imports() {
+        let dir = std::env::temp_dir().join(format!("jstar-rs-with-fs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("search_path_mod.jsr"), "var answer = 42").unwrap();
 
-        // SAFETY: `error` comes from the J* API that guarantess that is a valid cstring and utf8
-        let error = unsafe { CStr::from_ptr(error) }
-            .to_str()
-            .expect("error should be valid utf8");
+        let vm = VM::with_fs_imports(Conf::new(), std::slice::from_ref(&dir));
+        vm.eval(
+            "<string>",
+            "import search_path_mod
+            std.assert(search_path_mod.answer == 42)",
+        )
+        .unwrap();
 
-        error_callback(err, file, line, error);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
 
-extern "C" fn import_trampoline(
-    vm: *mut ffi::JStarVM,
-    module_name: *const c_char,
-) -> ffi::JStarImportResult {
-    // SAFETY: ditto
-    let trampolines = unsafe { &mut *(ffi::jsrGetCustomData(vm) as *mut Trampolines) };
+    #[test]
+    fn import_fs_resolver_cache() {
+        let dir = std::env::temp_dir().join(format!("jstar-rs-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("cached_mod.jsr");
+        std::fs::write(&file, "var version = 1").unwrap();
+        let mtime = std::fs::metadata(&file).unwrap().modified().unwrap();
+
+        let resolver = FsResolver::new([dir.clone()]).with_cache();
+        let import_version = |expected: i32| {
+            let conf = Conf::new().import_callback(resolver.clone().into_callback());
+            let vm = VM::new(conf).init_runtime();
+            vm.eval(
+                "<string>",
+                format!(
+                    "import cached_mod
+                    std.assert(cached_mod.version == {expected})"
+                ),
+            )
+            .unwrap();
+        };
+        import_version(1);
+
+        // Same mtime: the cached bytecode is reused even if the contents changed
+        std::fs::write(&file, "var version = 2").unwrap();
+        let f = std::fs::File::options().write(true).open(&file).unwrap();
+        f.set_modified(mtime).unwrap();
+        import_version(1);
+
+        // Touching the file invalidates the cache
+        f.set_modified(mtime + std::time::Duration::from_secs(1))
+            .unwrap();
+        import_version(2);
+
+        // Syntax errors are reported once, by the import, and not by the pre-compilation
+        std::fs::write(dir.join("broken_mod.jsr"), "var").unwrap();
+        let mut reported = 0;
+        let conf = Conf::new()
+            .import_callback(resolver.clone().into_callback())
+            .error_callback(Box::new(|_, file, _, _| {
+                if file.ends_with("broken_mod.jsr") {
+                    reported += 1;
+                }
+            }));
+        let vm = VM::new(conf).init_runtime();
+        assert!(vm.eval("<string>", "import broken_mod").is_err());
+        drop(vm);
+        assert_eq!(reported, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    #[should_panic]
+    fn import_stack_underflow_panic() {
+        // This should panic, as we're popping past the stack frame boundary
+        // This should mantain the invariant that `string_ref` must remain valid and not dangle
+        // by being popped off the stack (as will happen if the code below is permitted)
+        let conf = Conf::new().import_callback(Box::new(|vm, _| {
+            vm.pop();
+            vm.pop();
+            None
+        }));
+
+        let vm = VM::new(conf).init_runtime();
+        "string".to_jstar(&vm);
+
+        let string_ref = JStarString::from_jstar(&vm, -1).unwrap();
+        let _ = vm.eval("<string>", "import test");
+
+        assert_eq!(string_ref, "string");
+    }
+
+    #[test]
+    fn push_get_number() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        vm.push_number(46.0);
+        let n = vm.get_number(-1).unwrap();
+        assert_eq!(46.0, n);
+    }
+
+    #[test]
+    #[should_panic]
+    fn get_number_panic() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        let _ = vm.get_number(-1);
+    }
+
+    #[test]
+    fn get_number_none() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        vm.push_string("notanumber");
+        let n = vm.get_number(-1);
+        assert!(n.is_none());
+    }
+
+    #[test]
+    fn push_null() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        vm.push_null();
+        assert!(vm.is_null(-1));
+        assert!(vm.check_null(-1, "n").is_ok());
+
+        vm.push_number(0.0);
+        assert!(!vm.is_null(-1));
+        let res = vm.check_null(-1, "n");
+        assert!(matches!(res, Err(Error::Runtime)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn is_null_panic() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        let _ = vm.is_null(-1);
+    }
+
+    #[test]
+    fn push_get_boolean() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        vm.push_boolean(true);
+        assert!(vm.is_boolean(-1));
+        assert_eq!(vm.get_boolean(-1), Some(true));
+        assert!(bool::from_jstar(&vm, -1).unwrap());
+
+        false.to_jstar(&vm);
+        assert!(!vm.check_boolean(-1, "b").unwrap());
+    }
+
+    #[test]
+    #[should_panic]
+    fn get_boolean_panic() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        let _ = vm.get_boolean(-1);
+    }
+
+    #[test]
+    fn get_boolean_none() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        vm.push_number(1.0);
+        assert!(vm.get_boolean(-1).is_none());
+        let res = vm.check_boolean(-1, "b");
+        assert!(matches!(res, Err(Error::Runtime)));
+    }
+
+    #[test]
+    fn buffer() -> Result<()> {
+        let vm = VM::new(Conf::new()).init_runtime();
+        let mut buf = vm.new_buffer();
+        assert!(buf.is_empty());
+
+        write!(buf, "var x = {}", 1).unwrap();
+        buf.append(b" + 2");
+        assert_eq!(&*buf, b"var x = 1 + 2");
+        vm.eval("<buffer>", &*buf)?;
+        vm.eval("<string>", "std.assert(x == 3)")?;
+
+        buf.clear();
+        buf.append(b"a-b-c");
+        buf.prepend(b"--");
+        buf.append_byte(b'!');
+        assert_eq!(&*buf, b"--a-b-c!");
+        buf.cut(2);
+        buf.replace_byte(0, b'-', b'+');
+        buf.truncate(5);
+        buf.shrink_to_fit();
+        assert_eq!(&*buf, b"a+b+c");
+
+        buf.push();
+        assert_eq!(vm.get_string(-1).unwrap(), "a+b+c");
+        Ok(())
+    }
+
+    #[test]
+    fn push_i64() {
+        let vm = VM::new(Conf::new()).init_runtime();
+
+        vm.push_i64(1 << 53).unwrap();
+        vm.push_i64(-(1 << 53)).unwrap();
+        vm.push_i64(1 << 60).unwrap();
+        assert_eq!(vm.get_number(-3), Some(9007199254740992.0));
+        assert_eq!(vm.get_number(-2), Some(-9007199254740992.0));
+        assert_eq!(vm.get_number(-1), Some(1152921504606846976.0));
+
+        let res = vm.push_i64(i64::MAX);
+        assert!(matches!(res, Err(Error::InexactNumber(i64::MAX))));
+        let res = vm.push_i64((1 << 53) + 1);
+        assert!(matches!(res, Err(Error::InexactNumber(_))));
+        assert_eq!(vm.get_number(-1), Some(1152921504606846976.0));
+    }
+
+    #[test]
+    fn push_get_string() {
+        let vm = VM::new(Conf::new());
+        let vm = vm.init_runtime();
+        vm.push_string("test");
+        let s = vm.get_string(-1).unwrap();
+        assert_eq!(s, "test");
+    }
+
+    #[test]
+    fn string_to_jstar_no_copy() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.push_string("x".repeat(1 << 16));
+        vm.push_number(1.0);
+
+        let s = vm.get_string(-2).unwrap();
+        s.to_jstar(&vm);
+        let pushed = vm.get_bytes(-1).unwrap();
+        assert_eq!(pushed.as_ptr(), s.as_bytes().as_ptr());
+        assert_eq!(pushed.len(), 1 << 16);
+
+        let other = VM::new(Conf::new()).init_runtime();
+        s.to_jstar(&other);
+        assert_eq!(other.get_string(-1).unwrap(), s.as_bytes());
+        assert_ne!(other.get_bytes(-1).unwrap().as_ptr(), s.as_bytes().as_ptr());
+    }
+
+    #[test]
+    fn option_conversions() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        Some(42).to_jstar(&vm);
+        None::<i32>.to_jstar(&vm);
+        vm.push_string("str");
+
+        assert_eq!(Option::<i32>::from_jstar(&vm, -3), Some(Some(42)));
+        assert_eq!(Option::<i32>::from_jstar(&vm, -2), Some(None));
+        assert!(vm.is_null(-2));
+        assert_eq!(Option::<i32>::from_jstar(&vm, -1), None);
+
+        assert_eq!(
+            Option::<i32>::from_jstar_checked(&vm, -3, "n").unwrap(),
+            Some(42)
+        );
+        assert_eq!(
+            Option::<i32>::from_jstar_checked(&vm, -2, "n").unwrap(),
+            None
+        );
+        let res = Option::<i32>::from_jstar_checked(&vm, -1, "n");
+        assert!(matches!(res, Err(Error::Runtime)));
+    }
+
+    #[test]
+    fn infallible_to_jstar() {
+        fn push_result<T: ToJStar, E: ToJStar>(vm: &VM, res: &std::result::Result<T, E>) {
+            match res {
+                Ok(val) => val.to_jstar(vm),
+                Err(err) => err.to_jstar(vm),
+            }
+        }
+
+        let vm = VM::new(Conf::new()).init_runtime();
+        push_result(&vm, &Ok::<_, std::convert::Infallible>(42.0));
+        assert_eq!(vm.get_number(-1).unwrap(), 42.0);
+    }
+
+    #[test]
+    fn push_repeated_string() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.push_repeated_string(b"ab", 1000);
+        let s = vm.get_string(-1).unwrap();
+        assert_eq!(s.as_bytes().len(), 2000);
+        assert!(s.as_bytes().chunks(2).all(|c| c == b"ab"));
 
-    if let Some(ref mut import_callback) = trampolines.import_callback {
-        // SAFETY: this function can only be called during the lifetime of the vm, so it is
-        // guaranteed that the returned returned wrapper is safe to use
-        let mut vm = unsafe { VM::from_ptr(vm) };
+        vm.push_repeated_string(b"ab", 0);
+        assert_eq!(vm.get_string(-1).unwrap(), "");
 
-        // SAFETY: `module_name` comes from the J* API that guarantess that is a valid cstring and utf8
-        let module_name = unsafe { CStr::from_ptr(module_name) }
-            .to_str()
-            .expect("module_name is not valid utf8");
-
-        match import_callback(&mut vm, module_name) {
-            None => ffi::JStarImportResult::default(),
-            Some(module) => {
-                let (code, path, reg) = match module {
-                    Module::Source { src, path, reg } => (src.into(), path, reg),
-                    Module::Binary { code, path, reg } => (code, path, reg),
-                };
+        // Returns right away instead of looping `count` times
+        vm.push_repeated_string(b"", usize::MAX);
+        assert_eq!(vm.get_string(-1).unwrap(), "");
+    }
 
-                struct ImportData(Vec<u8>, CString);
-                let import_data = Box::new(ImportData(code, path));
+    #[test]
+    #[should_panic]
+    fn push_repeated_string_overflow() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.push_repeated_string(b"ab", usize::MAX);
+    }
 
-                // Callback function that drops data allocated during `import_callback`
-                extern "C" fn finalize_import(user_data: *mut c_void) {
-                    // SAFETY: user_data is a `*mut ImportData` obtained from a Box, so it is safe
-                    // to construct a new `Box` from it
-                    let _ = unsafe { Box::from_raw(user_data as *mut ImportData) };
-                }
+    #[test]
+    fn push_string_from_reader() {
+        let path = std::env::temp_dir().join(format!("jstar-rs-reader-{}", std::process::id()));
+        let contents: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
 
-                ffi::JStarImportResult {
-                    code: import_data.0.as_ptr() as *const c_char,
-                    code_len: import_data.0.len(),
-                    path: import_data.1.as_ptr(),
-                    reg,
-                    finalize: Some(finalize_import),
-                    user_data: Box::into_raw(import_data) as *mut _ as *mut c_void,
-                }
-            }
-        }
-    } else {
-        ffi::JStarImportResult::default()
+        let vm = VM::new(Conf::new()).init_runtime();
+        let file = std::fs::File::open(&path).unwrap();
+        vm.push_string_from_reader(file).unwrap();
+        assert_eq!(vm.get_bytes(-1), Some(&contents[..]));
+
+        std::fs::remove_file(&path).unwrap();
     }
-}
 
-#[cfg(test)]
-mod test {
-    #![allow(clippy::unwrap_used)]
+    #[test]
+    fn string_to_string_lossy() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.push_string("valid");
+        let s = vm.get_string(-1).unwrap();
+        assert!(matches!(
+            s.to_string_lossy(),
+            std::borrow::Cow::Borrowed("valid")
+        ));
 
-    use super::*;
-    use crate::{convert::ToJStar, native, CORE_MODULE, MAIN_MODULE};
+        vm.push_string(b"ok \xff\xfe end");
+        let s = vm.get_string(-1).unwrap();
+        assert!(s.as_str().is_err());
+        assert_eq!(s.to_string_lossy(), "ok \u{FFFD}\u{FFFD} end");
+    }
 
     #[test]
-    fn eval() {
+    fn get_bytes() {
         let vm = VM::new(Conf::new()).init_runtime();
-        vm.eval("<string>", "print('Hello, World!')").unwrap();
+        vm.push_string(b"\x00bin\xffary");
+        assert_eq!(vm.get_bytes(-1), Some(&b"\x00bin\xffary"[..]));
+
+        vm.push_number(2.0);
+        assert!(vm.get_bytes(-1).is_none());
     }
 
     #[test]
-    fn eval_bin() {
+    #[should_panic]
+    fn get_string_panic() {
         let vm = VM::new(Conf::new());
-        let code = vm
-            .compile_in_memory("<string>", "print('Hello, World!')")
-            .unwrap();
+        let vm = vm.init_runtime();
+        let _ = vm.get_string(-1).unwrap();
+    }
 
+    #[test]
+    fn get_string_none() {
+        let vm = VM::new(Conf::new());
         let vm = vm.init_runtime();
-        vm.eval("<string>", code).unwrap();
+        vm.push_number(2.0);
+        let s = vm.get_string(-1);
+        assert!(s.is_none());
     }
 
     #[test]
-    fn eval_in_module() {
-        let mut vm = VM::new(Conf::new()).init_runtime();
-        vm.eval_in_module("<string>", "test", "var x = 42").unwrap();
+    fn to_jstar_by_reference() {
+        fn push_ref<T: ToJStar + ?Sized>(vm: &VM, value: &T) {
+            // Goes through the blanket `impl ToJStar for &T`
+            (&value).to_jstar(vm);
+        }
 
-        vm.get_global("test", "x").unwrap();
-        assert!(i32::from_jstar(&vm, -1).unwrap() == 42);
-        vm.pop();
+        let vm = VM::new(Conf::new()).init_runtime();
+        push_ref(&vm, &42i32);
+        assert_eq!(vm.get_number(-1), Some(42.0));
+        push_ref(&vm, &"x");
+        assert_eq!(vm.get_string(-1).unwrap(), "x");
+        push_ref(&vm, "str");
+        assert_eq!(vm.get_string(-1).unwrap(), "str");
+        push_ref(&vm, &b"bytes"[..]);
+        assert_eq!(vm.get_string(-1).unwrap(), "bytes");
+    }
 
-        let res = vm.get_global(MAIN_MODULE, "x");
-        assert!(matches!(res, Err(Error::Runtime)));
+    #[test]
+    fn push_byte_list() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_byte_list(&[1, 2, 255]);
+        vm.set_global(MAIN_MODULE, "bytes").unwrap();
         vm.pop();
+
+        vm.eval(
+            "<string>",
+            "std.assert(bytes is List and #bytes == 3)
+            std.assert(bytes[0] == 1 and bytes[1] == 2 and bytes[2] == 255)",
+        )
+        .unwrap();
     }
 
     #[test]
-    fn eval_in_module_bin() {
-        let mut vm = VM::new(Conf::new()).init_runtime();
+    fn push_list_from() {
+        let vm = VM::new(Conf::new()).init_runtime();
+        vm.push_list_from(&[true, false, true]);
+        vm.set_global(MAIN_MODULE, "bools").unwrap();
+        vm.push_list_from(&["a", "b"]);
+        vm.set_global(MAIN_MODULE, "strings").unwrap();
+        vm.push_list_from(std::iter::empty::<&i32>());
+        vm.set_global(MAIN_MODULE, "empty").unwrap();
 
-        let code = vm.compile_in_memory("<test>", "var x = 42").unwrap();
-        vm.eval_in_module("<string>", "test", code).unwrap();
+        vm.eval(
+            "<string>",
+            "std.assert(bools is List and #bools == 3)
+            std.assert(bools[0] == true and bools[1] == false and bools[2] == true)
+            std.assert(strings[0] == 'a' and strings[1] == 'b')
+            std.assert(empty is List and #empty == 0)",
+        )
+        .unwrap();
+    }
 
-        vm.get_global("test", "x").unwrap();
-        assert!(i32::from_jstar(&vm, -1).unwrap() == 42);
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn push_json() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_json(
+            r#"{"name": "jstar", "version": [1, 2.5], "nested": {"ok": true, "none": null}}"#,
+        )?;
+        vm.set_global(MAIN_MODULE, "json")?;
         vm.pop();
+        vm.eval(
+            "<string>",
+            "std.assert(json['name'] == 'jstar')
+            std.assert(json['version'][0] == 1 and json['version'][1] == 2.5)
+            std.assert(json['nested']['ok'] and json['nested']['none'] == null)",
+        )?;
 
-        let res = vm.get_global(MAIN_MODULE, "x");
+        let res = vm.push_json(r#"{"unterminated": [1, 2"#);
         assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
         vm.pop();
+        vm.eval("<string>", "std.assert(exc is InvalidArgException)")?;
+        Ok(())
     }
 
     #[test]
-    fn call() -> Result<()> {
-        let vm = VM::new(Conf::new());
-        let mut vm = vm.init_runtime();
-
-        vm.eval("<string>", "var add = |a, b| => a + b")?;
-        vm.get_global(MAIN_MODULE, "add")?;
-
-        3.to_jstar(&vm);
-        2.to_jstar(&vm);
-        vm.call(2)?;
-
-        let n = i32::from_jstar(&vm, -1).ok_or(Error::Runtime)?;
-        assert_eq!(n, 5);
+    #[cfg(feature = "serde_json")]
+    fn to_json() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "var conf = {'name': 'jstar', 'tags': ['a', (1, 2.5)], 'nested': {'ok': true}}
+            var bad = {'fn': |x| => x}",
+        )?;
 
+        vm.get_global(MAIN_MODULE, "conf")?;
+        let json = vm.to_json(-1)?;
         vm.pop();
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!({"name": "jstar", "tags": ["a", [1, 2.5]], "nested": {"ok": true}})
+        );
+
+        vm.get_global(MAIN_MODULE, "bad")?;
+        let res = vm.to_json(-1);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
         Ok(())
     }
 
     #[test]
-    #[should_panic]
-    fn call_panic() {
+    #[cfg(feature = "uuid")]
+    fn uuid_roundtrip() {
         let mut vm = VM::new(Conf::new()).init_runtime();
-        vm.get_global(CORE_MODULE, "print").unwrap();
-        vm.call(2).unwrap();
+
+        let uuid = uuid::Uuid::new_v4();
+        uuid.to_jstar(&vm);
+        assert_eq!(
+            JStarString::from_jstar(&vm, -1).unwrap(),
+            uuid.hyphenated().to_string()
+        );
+        assert_eq!(
+            uuid::Uuid::from_jstar_checked(&vm, -1, "uuid").unwrap(),
+            uuid
+        );
+        vm.pop();
     }
 
     #[test]
-    fn get_global() {
+    #[cfg(feature = "uuid")]
+    fn uuid_malformed() {
         let mut vm = VM::new(Conf::new()).init_runtime();
 
-        vm.eval("<string>", "var test = 'test'").unwrap();
-        vm.get_global(MAIN_MODULE, "test").unwrap();
-        let s = JStarString::from_jstar(&vm, -1).unwrap();
-        assert_eq!(s, "test");
+        "not-a-uuid".to_jstar(&vm);
+        assert!(uuid::Uuid::from_jstar(&vm, -1).is_none());
 
-        vm.pop();
+        let res = uuid::Uuid::from_jstar_checked(&vm, -1, "uuid");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc").unwrap();
+        vm.pop_n(2);
+        vm.eval("<string>", "std.assert(exc is ValueException)")
+            .unwrap();
     }
 
     #[test]
-    fn get_global_fail() {
-        let vm = VM::new(Conf::new()).init_runtime();
-        vm.eval("<string>", "var test = 'test'").unwrap();
-        let res = vm.get_global(MAIN_MODULE, "doesnotexist").unwrap_err();
-        assert!(matches!(res, Error::Runtime));
+    fn f64_slice() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let numbers: Vec<f64> = (0..10_000).map(|n| n as f64 * 0.5).collect();
+        vm.push_f64_slice(&numbers);
+        assert_eq!(vm.get_f64_slice(-1)?, numbers);
+        vm.set_global(MAIN_MODULE, "numbers")?;
+        vm.pop();
+        vm.eval(
+            "<string>",
+            "std.assert(#numbers == 10000 and numbers[3] == 1.5)",
+        )?;
+
+        vm.eval("<string>", "var mixed = [1, 'two', 3]")?;
+        vm.get_global(MAIN_MODULE, "mixed")?;
+        assert!(matches!(vm.get_f64_slice(-1), Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
     }
 
     #[test]
-    fn get_global_fail_module() {
-        let vm = VM::new(Conf::new()).init_runtime();
-        let res = vm.get_global("does_not_exist", "doesnotexist").unwrap_err();
-        assert!(matches!(res, Error::Runtime));
+    fn table_get_or() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval("<string>", "var conf = {'port': 8080, 'host': 'localhost'}")?;
+        vm.get_global(MAIN_MODULE, "conf")?;
+
+        assert_eq!(vm.table_get_or::<i32>(-1, "port")?, 8080);
+        assert_eq!(vm.table_get_or::<i32>(-1, "timeout")?, 0);
+
+        let res = vm.table_get_or::<i32>(-1, "host");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
     }
 
     #[test]
-    fn set_global() {
-        let vm = VM::new(Conf::new());
-        let mut vm = vm.init_runtime();
+    fn index_set() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "var list = [1, 2, 3]
+            var table = {}
+            class Recorder
+                construct()
+                    this.assigned = null
+                end
+                fun __set__(key, val)
+                    this.assigned = (key, val)
+                end
+            end
+            var recorder = Recorder()",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "list")?;
+        vm.push_number(1.0);
+        vm.push_string("two");
+        vm.index_set(-3)?;
+        vm.pop_n(2);
+
+        vm.push_number(10.0);
+        vm.push_number(0.0);
+        let res = vm.index_set(-3);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(4);
 
-        vm.eval("<setglb>", "var test = 'test'").unwrap();
+        vm.get_global(MAIN_MODULE, "table")?;
+        vm.push_string("key");
+        vm.push_number(42.0);
+        vm.index_set(-3)?;
+        vm.pop_n(3);
 
-        42.to_jstar(&vm);
-        vm.set_global(MAIN_MODULE, "test").unwrap();
-        vm.pop();
+        vm.get_global(MAIN_MODULE, "recorder")?;
+        vm.push_string("key");
+        vm.push_string("val");
+        vm.index_set(-3)?;
+        vm.pop_n(3);
 
-        vm.eval("<setglb>", "std.assert(test == 42)").unwrap();
+        vm.eval(
+            "<string>",
+            "std.assert(list[1] == 'two' and #list == 3)
+            std.assert(table['key'] == 42)
+            std.assert(recorder.assigned[0] == 'key' and recorder.assigned[1] == 'val')",
+        )?;
+        Ok(())
     }
 
     #[test]
-    fn set_global_fail() {
+    fn value_owned() -> Result<()> {
         let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "var list = [1, 'two', null, (true,)]
+            var table = {'name': 'jstar', 'version': 2}",
+        )?;
 
-        42.to_jstar(&vm);
-        let res = vm.set_global("does_not_exist", "test");
+        vm.get_global(MAIN_MODULE, "list")?;
+        let list = Value::from_jstar_checked(&vm, -1, "list")?;
+        vm.pop();
+        assert_eq!(list.as_list().map(|l| l.len()), Some(4));
+        let elems: Vec<Value> = list.clone().into_iter().collect();
+        assert_eq!(
+            elems,
+            vec![
+                Value::Number(1.0),
+                Value::String(b"two".to_vec()),
+                Value::Null,
+                Value::Tuple(vec![Value::Bool(true)]),
+            ]
+        );
+        assert_eq!(elems[1].as_str(), Some("two"));
+        assert!(Value::Null.into_iter().next().is_none());
+
+        vm.get_global(MAIN_MODULE, "table")?;
+        let table = Value::from_jstar(&vm, -1).unwrap();
+        vm.pop();
+        let entries = table.as_table().unwrap();
+        assert_eq!(entries.len(), 2);
+        let name = entries.iter().find(|(k, _)| k.as_str() == Some("name"));
+        assert_eq!(name.and_then(|(_, v)| v.as_str()), Some("jstar"));
+        assert!(table.as_list().is_none());
+
+        list.to_jstar(&vm);
+        table.to_jstar(&vm);
+        vm.set_global(MAIN_MODULE, "table2")?;
+        vm.pop();
+        vm.set_global(MAIN_MODULE, "list2")?;
+        vm.pop();
+        vm.eval(
+            "<string>",
+            "std.assert(list2[1] == 'two' and list2[3][0] and table2['version'] == 2)",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn hash_map_conversions() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let map = HashMap::from([(1, "one".to_owned()), (2, "two".to_owned())]);
+        map.to_jstar(&vm);
+        vm.set_global(MAIN_MODULE, "map")?;
+        vm.eval(
+            "<string>",
+            "std.assert(map[1] == 'one' and map[2] == 'two')",
+        )?;
+
+        let res = HashMap::<i32, String>::from_jstar(&vm, -1).unwrap();
+        assert_eq!(res, map);
         vm.pop();
 
+        vm.eval("<string>", "var mixed = {1: 'one', 'two': 2}")?;
+        vm.get_global(MAIN_MODULE, "mixed")?;
+        assert!(HashMap::<i32, String>::from_jstar(&vm, -1).is_none());
+        let res = HashMap::<i32, String>::from_jstar_checked(&vm, -1, "mixed");
         assert!(matches!(res, Err(Error::Runtime)));
+        Ok(())
     }
 
     #[test]
-    fn push_native() {
-        let mut vm = VM::new(Conf::new()).init_runtime();
+    fn hash_map_unhashable_key() -> Result<()> {
+        #[derive(PartialEq, Eq, Hash)]
+        struct Unhashable;
 
-        native!(fn id(vm) {
-            let n = vm.get_number(1).unwrap();
-            n.to_jstar(vm);
-            Ok(())
-        });
+        impl ToJStar for Unhashable {
+            fn to_jstar(&self, vm: &VM) {
+                vm.get_global(MAIN_MODULE, "unhashable").unwrap();
+            }
+        }
 
-        vm.push_native(MAIN_MODULE, "id", id, 1).unwrap();
-        vm.set_global(MAIN_MODULE, "id").unwrap();
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "class Unhashable
+                fun __hash__()
+                    raise Exception('unhashable')
+                end
+            end
+            var unhashable = Unhashable()",
+        )?;
+        let map = HashMap::from([(Unhashable, 1)]);
+        assert!(matches!(vm.push_table_from(&map), Err(Error::Runtime)));
+        // Only the exception is left, the partially built table is discarded
+        assert!(vm.validate_slot(-1));
+        assert!(!vm.validate_slot(-2));
+        vm.set_global(MAIN_MODULE, "exc")?;
         vm.pop();
-
-        vm.eval("<string>", "std.assert(id(42) == 42)").unwrap();
+        vm.eval("<string>", "std.assert(exc is Exception)")?;
+        Ok(())
     }
 
     #[test]
-    fn push_native_fail() {
-        let vm = VM::new(Conf::new()).init_runtime();
+    fn list_sort_by() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval("<string>", "var list = [3, 1, 4, 1, 5, 9, 2, 6, 5, 3]")?;
+        vm.get_global(MAIN_MODULE, "list")?;
+
+        vm.list_sort_by(-1, |vm| {
+            let a = vm.check_number(-2, "a")?;
+            let b = vm.check_number(-1, "b")?;
+            Ok(b.total_cmp(&a))
+        })?;
+        assert_eq!(
+            vm.get_f64_slice(-1)?,
+            vec![9.0, 6.0, 5.0, 5.0, 4.0, 3.0, 3.0, 2.0, 1.0, 1.0]
+        );
+        vm.eval("<string>", "std.assert(list[0] == 9 and list[9] == 1)")?;
+        vm.pop();
 
-        native!(fn id(vm) {
-            let n = vm.get_number(1).unwrap();
-            n.to_jstar(vm);
-            Ok(())
+        vm.eval("<string>", "var mixed = [2, 'a', 1]")?;
+        vm.get_global(MAIN_MODULE, "mixed")?;
+        let res = vm.list_sort_by(-1, |vm| {
+            let a = vm.check_number(-2, "a")?;
+            let b = vm.check_number(-1, "b")?;
+            Ok(a.total_cmp(&b))
         });
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+        vm.eval("<string>", "std.assert(mixed[0] == 2 and mixed[2] == 1)")?;
 
-        let res = vm.push_native("does_not_exist", "id", id, 1);
+        // Comparators that pop or push values raise instead of corrupting the stack
+        vm.get_global(MAIN_MODULE, "list")?;
+        let res = vm.list_sort_by(-1, |vm| {
+            vm.pop_n(2);
+            Ok(std::cmp::Ordering::Equal)
+        });
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+        let res = vm.list_sort_by(-1, |vm| {
+            vm.push_null();
+            Ok(std::cmp::Ordering::Equal)
+        });
         assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop_n(3);
+        vm.eval(
+            "<string>",
+            "std.assert(exc._err == '`cmp` must leave the stack unchanged')",
+        )?;
+        Ok(())
     }
 
     #[test]
-    fn register_native() {
-        let vm = VM::new(Conf::new()).init_runtime();
+    fn list_map_filter() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_f64_slice(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
 
-        native!(fn id(vm) {
-            let n = vm.get_number(1).unwrap();
-            n.to_jstar(vm);
+        vm.list_map(-1, |vm| {
+            let n = vm.check_number(-1, "n")?;
+            vm.push_number(n * n);
             Ok(())
-        });
+        })?;
+        assert_eq!(vm.get_f64_slice(-1)?, vec![1.0, 4.0, 9.0, 16.0, 25.0, 36.0]);
+        vm.pop();
 
-        vm.register_native(MAIN_MODULE, "id", id, 1).unwrap();
+        vm.list_filter(-1, |vm| Ok(vm.check_number(-1, "n")? % 2.0 == 0.0))?;
+        assert_eq!(vm.get_f64_slice(-1)?, vec![2.0, 4.0, 6.0]);
+        vm.pop();
 
-        vm.eval("<string>", "std.assert(id(42) == 42)").unwrap();
+        // The source list is left untouched
+        assert_eq!(vm.get_f64_slice(-1)?, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        vm.pop();
+
+        vm.eval("<string>", "var mixed = [1, 'a']")?;
+        vm.get_global(MAIN_MODULE, "mixed")?;
+        let res = vm.list_filter(-1, |vm| Ok(vm.check_number(-1, "n")? > 0.0));
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+
+        // Callbacks that leave the stack unbalanced raise instead of panicking
+        let res = vm.list_map(-1, |_| Ok(()));
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+        let res = vm.list_filter(-1, |vm| {
+            vm.push_null();
+            Ok(true)
+        });
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
     }
 
     #[test]
-    fn register_native_fail() {
+    fn list_map_panic() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_f64_slice(&[1.0, 2.0]);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vm.list_map(-1, |_| panic!("boom"))
+        }));
+        let payload = res.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    }
+
+    #[test]
+    fn list_iter() -> Result<()> {
         let vm = VM::new(Conf::new()).init_runtime();
+        vm.eval("<string>", "var list = [1, 'two', 3]")?;
+        vm.get_global(MAIN_MODULE, "list")?;
+        vm.push_number(0.0);
+        assert!(vm.list_iter::<f64>(-1).is_none());
+
+        let numbers: Vec<_> = vm.list_iter::<f64>(-2).unwrap().collect();
+        assert_eq!(numbers, [Some(1.0), None, Some(3.0)]);
+        assert_eq!(vm.get_number(-1), Some(0.0));
+
+        let values: Vec<_> = vm.list_iter::<Value>(-2).unwrap().flatten().collect();
+        assert_eq!(values[1], Value::String(b"two".to_vec()));
+        assert_eq!(vm.get_number(-1), Some(0.0));
+
+        // The list can be shrunk by J* code while it is being iterated
+        let mut iter = vm.list_iter::<f64>(-2).unwrap();
+        assert_eq!(iter.next(), Some(Some(1.0)));
+        vm.eval("<string>", "list.clear()")?;
+        assert_eq!(iter.next(), None);
+        assert_eq!(vm.get_number(-1), Some(0.0));
+        Ok(())
+    }
 
-        native!(fn id(vm) {
-            let n = vm.get_number(1).unwrap();
-            n.to_jstar(vm);
-            Ok(())
-        });
+    #[test]
+    fn list_construction() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_list();
+        for n in 1..=3 {
+            vm.push_number(n as f64);
+            vm.list_append(-2)?;
+        }
+        assert_eq!(vm.get_list_len(-1), Some(3));
+
+        vm.push_string("two");
+        vm.list_set(-2, 1)?;
+        vm.list_get(-1, 1)?;
+        assert_eq!(vm.get_string(-1).unwrap(), "two");
+        vm.pop();
+        vm.set_global(MAIN_MODULE, "list")?;
+        vm.eval(
+            "<string>",
+            "std.assert(list[0] == 1 and list[1] == 'two' and list[2] == 3)",
+        )?;
+
+        let res = vm.list_get(-1, 3);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop();
+        vm.eval("<string>", "std.assert(exc is IndexOutOfBoundException)")?;
+
+        vm.push_string("value");
+        let res = vm.list_set(-2, 5);
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop();
+        assert_eq!(vm.get_list_len(-1), Some(3));
 
-        let res = vm.register_native("does_not_exist", "id", id, 1);
+        vm.push_number(0.0);
+        vm.push_number(1.0);
+        let res = vm.list_append(-2);
         assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop_n(3);
+        vm.eval("<string>", "std.assert(exc is TypeException)")?;
+        Ok(())
     }
 
     #[test]
-    fn native_call_fail() {
+    fn vec_conversions() -> Result<()> {
         let mut vm = VM::new(Conf::new()).init_runtime();
 
-        native!(fn id(vm) {
-            let n = i32::from_jstar_checked(vm, 1, "n")?;
-            n.to_jstar(vm);
-            Ok(())
+        crate::native_ret!(fn numbers(vm) -> Vec<i32> {
+            Ok(vec![1, 2, 3])
         });
+        vm.register_native(MAIN_MODULE, "numbers", numbers, 0)?;
+        vm.eval(
+            "<string>",
+            "var nums = numbers()
+            std.assert(nums is List and #nums == 3 and nums[2] == 3)",
+        )?;
 
-        vm.register_native(MAIN_MODULE, "id", id, 1).unwrap();
-
-        vm.get_global(MAIN_MODULE, "id").unwrap();
-        "not a number".to_jstar(&vm);
-
-        let res = vm.call(1);
-        assert!(matches!(res, Err(Error::Runtime)));
-
+        vm.get_global(MAIN_MODULE, "nums")?;
+        assert_eq!(Vec::<i32>::from_jstar(&vm, -1), Some(vec![1, 2, 3]));
+        assert_eq!(Vec::<String>::from_jstar(&vm, -1), None);
         vm.pop();
 
-        vm.get_global(MAIN_MODULE, "id").unwrap();
-        42.to_jstar(&vm);
-        34.to_jstar(&vm);
-
-        let res = vm.call(2);
+        vm.push_number(1.0);
+        assert_eq!(Vec::<i32>::from_jstar(&vm, -1), None);
+        let res = Vec::<i32>::from_jstar_checked(&vm, -1, "nums");
         assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop_n(2);
+        vm.eval("<string>", "std.assert(exc is TypeException)")?;
+        Ok(())
     }
 
     #[test]
-    #[ignore]
-    #[should_panic]
-    fn native_should_panic() {
-        let mut vm = VM::new(Conf::new()).init_runtime();
+    fn vec_deque() -> Result<()> {
+        use std::collections::VecDeque;
 
-        native!(fn id(vm) {
-            // Try to pop past stack frame boundary
-            vm.pop_n(2);
-            Ok(())
-        });
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let queue: VecDeque<i32> = (1..=5).collect();
+        queue.to_jstar(&vm);
+        vm.set_global(MAIN_MODULE, "queue")?;
+        assert_eq!(VecDeque::<i32>::from_jstar(&vm, -1), Some(queue));
+        vm.pop();
+        vm.eval("<string>", "std.assert(queue[0] == 1 and queue[4] == 5)")?;
 
-        vm.register_native(MAIN_MODULE, "id", id, 0).unwrap();
-        vm.get_global(MAIN_MODULE, "id").unwrap();
-        vm.call(0).unwrap();
+        vm.eval("<string>", "var mixed = [1, 'two']")?;
+        vm.get_global(MAIN_MODULE, "mixed")?;
+        assert_eq!(VecDeque::<i32>::from_jstar(&vm, -1), None);
+        let res = VecDeque::<i32>::from_jstar_checked(&vm, -1, "mixed");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
     }
 
     #[test]
-    fn error_callback() {
-        let mut num_errors = 0;
-        let conf = Conf::new().error_callback(Box::new(|_, _, _, _| {
-            num_errors += 1;
-        }));
+    fn range_pair() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        (2usize..5).to_jstar(&vm);
+        assert_eq!(vm.get_tuple_len(-1), Some(2));
+        let range: std::ops::Range<usize> = vm.get_top().get().unwrap();
+        assert_eq!(range, 2..5);
+        vm.pop();
 
-        let vm = VM::new(conf).init_runtime();
+        vm.eval(
+            "<string>",
+            "var valid, invalid, bad = (-1, 3), (3, 1), (1, 2.5)",
+        )?;
+        vm.get_global(MAIN_MODULE, "valid")?;
+        let range = std::ops::Range::<i32>::from_jstar_checked(&vm, -1, "range")?;
+        assert_eq!(range, -1..3);
+        vm.pop();
 
-        let err = vm.eval("<string>", "raise Exception()").unwrap_err();
-        assert!(matches!(err, Error::Runtime));
+        vm.get_global(MAIN_MODULE, "invalid")?;
+        assert_eq!(std::ops::Range::<i32>::from_jstar(&vm, -1), None);
+        let res = std::ops::Range::<i32>::from_jstar_checked(&vm, -1, "range");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
 
-        let err = vm.eval("<string>", "for end").unwrap_err();
-        assert!(matches!(err, Error::Syntax));
+        vm.get_global(MAIN_MODULE, "bad")?;
+        let res = std::ops::Range::<i32>::from_jstar_checked(&vm, -1, "range");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
 
-        let err = vm.eval("<string>", "begin var a; var a; end").unwrap_err();
-        assert!(matches!(err, Error::Compile));
+        // Bounds that don't fit in the integer type are rejected instead of saturating
+        vm.get_global(MAIN_MODULE, "valid")?;
+        assert_eq!(std::ops::Range::<usize>::from_jstar(&vm, -1), None);
+        let res = std::ops::Range::<usize>::from_jstar_checked(&vm, -1, "range");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop_n(2);
+        vm.eval("<string>", "std.assert(exc is InvalidArgException)")?;
 
-        vm.eval("<string>", "var bar = 1 + 2").unwrap();
+        (0.0, 256.0).to_jstar(&vm);
+        assert_eq!(std::ops::Range::<u8>::from_jstar(&vm, -1), None);
+        assert_eq!(std::ops::Range::<u16>::from_jstar(&vm, -1), Some(0..256));
+        vm.pop();
+        Ok(())
+    }
 
-        drop(vm);
+    #[test]
+    fn tuple_conversions() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        (1, "two").to_jstar(&vm);
+        assert_eq!(vm.get_tuple_len(-1), Some(2));
+        let tuple = <(i32, String)>::from_jstar(&vm, -1).unwrap();
+        assert_eq!(tuple, (1, "two".to_owned()));
+        vm.pop();
 
-        assert_eq!(num_errors, 3);
+        vm.eval("<string>", "var pair, triple = [1, 'two'], [1, 'two', 3]")?;
+        vm.get_global(MAIN_MODULE, "pair")?;
+        let tuple = <(i32, String)>::from_jstar_checked(&vm, -1, "pair")?;
+        assert_eq!(tuple, (1, "two".to_owned()));
+        assert!(<(String, i32)>::from_jstar(&vm, -1).is_none());
+        vm.pop();
+
+        vm.get_global(MAIN_MODULE, "triple")?;
+        assert!(<(i32, String)>::from_jstar(&vm, -1).is_none());
+        let res = <(i32, String)>::from_jstar_checked(&vm, -1, "triple");
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.pop_n(2);
+        Ok(())
     }
 
     #[test]
-    fn import_source() {
-        let conf = Conf::new().import_callback(Box::new(|_, module_name| {
-            if module_name == "test" {
-                Some(Module::source(
-                    "var flag = 1".to_owned(),
-                    "<test>".to_owned(),
-                ))
-            } else {
-                None
-            }
-        }));
-
-        let vm = VM::new(conf).init_runtime();
+    fn tuple_from_native() -> Result<()> {
+        let vm = VM::new(Conf::new()).init_runtime();
 
+        crate::native_ret!(fn person(vm) -> (&'static str, i32) {
+            Ok(("Ada", 36))
+        });
+        vm.register_native(MAIN_MODULE, "person", person, 0)?;
         vm.eval(
             "<string>",
-            "import test
-            std.assert(test.flag == 1)",
-        )
-        .unwrap();
-
-        let err = vm.eval("<string>", "import does_not_exist").unwrap_err();
-        assert!(matches!(err, Error::Runtime));
+            "var name, age = person()
+            std.assert(person() is Tuple and name == 'Ada' and age == 36)",
+        )?;
+
+        let octuple = (1, 2, 3, 4, 5, 6, 7, "eight".to_owned());
+        octuple.to_jstar(&vm);
+        assert_eq!(vm.get_tuple_len(-1), Some(8));
+        assert_eq!(FromJStar::from_jstar(&vm, -1), Some(octuple));
+        Ok(())
     }
 
     #[test]
-    fn import_binary() {
-        let mut err_called = false;
-
-        let conf = Conf::new()
-            .error_callback(Box::new(|err, path, line, msg| {
-                assert!(matches!(err, Error::Runtime));
-                assert_eq!(path, "<string>");
-                assert!(line.is_none());
-                assert_eq!(msg, "Traceback (most recent call last):\n    [line 1] module __main__ in <main>\nImportException: Cannot load module `does_not_exist`.");
-                err_called = true;
-            }))
-            .import_callback(Box::new(|vm, module_name| {
-                if module_name == "test" {
-                    Some(Module::binary(
-                        vm.compile_in_memory("<test>", "var flag = 1").unwrap(),
-                        "<test>".to_owned(),
-                    ))
-                } else {
-                    None
-                }
-            }
-        ));
-
-        let vm = VM::new(conf).init_runtime();
-
+    fn set_path() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
         vm.eval(
             "<string>",
-            "import test
-            std.assert(test.flag == 1)",
-        )
-        .unwrap();
-
-        let err = vm.eval("<string>", "import does_not_exist").unwrap_err();
-        assert!(matches!(err, Error::Runtime));
-
-        drop(vm);
+            "class Server end
+            class Config
+                construct()
+                    this.server = Server()
+                end
+            end
+            var cfg = {}
+            var obj = Config()",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "cfg")?;
+        vm.get_global(MAIN_MODULE, "obj")?;
+        vm.push_number(8080.0);
+        vm.set_path(-3, "server.port")?;
+        vm.set_path(-2, "server.port")?;
+        vm.set_path(-3, "port")?;
+        vm.eval(
+            "<string>",
+            "std.assert(cfg['server']['port'] == 8080 and cfg['port'] == 8080)
+            std.assert(obj.server.port == 8080)",
+        )?;
 
-        assert!(err_called);
+        assert!(matches!(
+            vm.set_path(-2, "client.port"),
+            Err(Error::Runtime)
+        ));
+        vm.pop();
+        assert!(matches!(
+            vm.set_path(-3, "server..port"),
+            Err(Error::Runtime)
+        ));
+        vm.pop_n(4);
+        Ok(())
     }
 
     #[test]
-    #[ignore]
-    #[should_panic]
-    fn import_stack_underflow_panic() {
-        // This should panic, as we're popping past the stack frame boundary
-        // This should mantain the invariant that `string_ref` must remain valid and not dangle
-        // by being popped off the stack (as will happen if the code below is permitted)
-        let conf = Conf::new().import_callback(Box::new(|vm, _| {
-            vm.pop();
-            vm.pop();
-            None
-        }));
-
-        let vm = VM::new(conf).init_runtime();
-        "string".to_jstar(&vm);
+    fn get_path() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.eval(
+            "<string>",
+            "class Server
+                construct()
+                    this.ports = {'http': 80}
+                end
+            end
+            var cfg = {'server': Server()}",
+        )?;
+
+        vm.get_global(MAIN_MODULE, "cfg")?;
+        vm.get_path(-1, "server.ports.http")?;
+        assert_eq!(vm.get_number(-1), Some(80.0));
+        vm.pop();
 
-        let string_ref = JStarString::from_jstar(&vm, -1).unwrap();
-        let _ = vm.eval("<string>", "import test");
+        assert!(matches!(
+            vm.get_path(-1, "server.host"),
+            Err(Error::Runtime)
+        ));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop();
+        vm.eval("<string>", "std.assert(exc is FieldException)")?;
 
-        assert_eq!(string_ref, "string");
+        assert!(matches!(
+            vm.get_path(-1, "server.ports.https"),
+            Err(Error::Runtime)
+        ));
+        vm.pop_n(2);
+        Ok(())
     }
 
     #[test]
-    fn push_get_number() {
-        let vm = VM::new(Conf::new());
-        let vm = vm.init_runtime();
-        vm.push_number(46.0);
-        let n = vm.get_number(-1).unwrap();
-        assert_eq!(46.0, n);
+    fn string_interner() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let keys = ["id", "name", "email", "age", "country"];
+        {
+            let mut interner = vm.string_interner();
+            assert!(interner.is_empty());
+            for row in 0..1000 {
+                let key = keys[row % keys.len()];
+                interner.push(key);
+                assert_eq!(JStarString::from_jstar(&interner, -1).unwrap(), key);
+                interner.pop();
+            }
+            assert_eq!(interner.len(), keys.len());
+
+            // A cache hit pushes the very same `String` object, without allocating a new one
+            interner.push("name");
+            // SAFETY: the top of the stack is a String
+            let first = unsafe { ffi::jsrGetString(interner.vm.vm, -1) };
+            interner.pop();
+            interner.push("name");
+            // SAFETY: the top of the stack is a String
+            let second = unsafe { ffi::jsrGetString(interner.vm.vm, -1) };
+            assert_eq!(first, second);
+            interner.push("other");
+        }
+        // The list is emptied as a value was left above it
+        assert_eq!(vm.get_list_len(-3), Some(0));
+        vm.pop_n(3);
+
+        // The list is popped if it's on top of the stack
+        vm.push_number(0.0);
+        {
+            let mut interner = vm.string_interner();
+            interner.push("key");
+            interner.pop();
+        }
+        assert_eq!(vm.get_number(-1), Some(0.0));
+        vm.pop();
     }
 
     #[test]
     #[should_panic]
-    fn get_number_panic() {
-        let vm = VM::new(Conf::new());
-        let vm = vm.init_runtime();
-        let _ = vm.get_number(-1);
+    fn string_interner_pop_cache() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        let mut interner = vm.string_interner();
+        interner.pop();
     }
 
     #[test]
-    fn get_number_none() {
-        let vm = VM::new(Conf::new());
-        let vm = vm.init_runtime();
-        vm.push_string("notanumber");
-        let n = vm.get_number(-1);
-        assert!(n.is_none());
-    }
+    fn string_builder() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_number(1.5);
 
-    #[test]
-    fn push_get_string() {
-        let vm = VM::new(Conf::new());
-        let vm = vm.init_runtime();
-        vm.push_string("test");
-        let s = vm.get_string(-1).unwrap();
-        assert_eq!(s, "test");
-    }
+        let mut builder = vm.string_builder();
+        builder.str("x = ").number(42.0)?.str(", y = ").value(-1)?;
+        builder.finish();
 
-    #[test]
-    #[should_panic]
-    fn get_string_panic() {
-        let vm = VM::new(Conf::new());
-        let vm = vm.init_runtime();
-        let _ = vm.get_string(-1).unwrap();
-    }
+        let s = JStarString::from_jstar(&vm, -1).ok_or(Error::Runtime)?;
+        assert_eq!(s, "x = 42, y = 1.5");
 
-    #[test]
-    fn get_string_none() {
-        let vm = VM::new(Conf::new());
-        let vm = vm.init_runtime();
-        vm.push_number(2.0);
-        let s = vm.get_string(-1);
-        assert!(s.is_none());
+        vm.pop_n(2);
+        Ok(())
     }
 
     #[test]
@@ -1297,6 +7951,101 @@ mod test {
         vm.pop_n(5);
     }
 
+    #[test]
+    fn checkpoint_restore() {
+        let vm = VM::new(Conf::new());
+        let mut vm = vm.init_runtime();
+        vm.push_number(1.0);
+        let cp = vm.checkpoint();
+        vm.push_number(2.0);
+        vm.push_string("temp");
+        vm.push_number(3.0);
+        vm.restore(cp);
+        assert_eq!(vm.checkpoint(), cp);
+        assert_eq!(vm.get_number(-1).unwrap(), 1.0);
+
+        // Restoring with nothing pushed is a no-op
+        vm.restore(cp);
+        assert_eq!(vm.checkpoint(), cp);
+    }
+
+    #[test]
+    fn set_top() {
+        let vm = VM::new(Conf::new());
+        let mut vm = vm.init_runtime();
+        let depth = vm.stack_depth();
+        vm.push_number(1.0);
+        vm.push_number(2.0);
+        vm.push_number(3.0);
+        assert_eq!(vm.stack_depth(), depth + 3);
+
+        vm.set_top(depth + 3);
+        assert_eq!(vm.stack_depth(), depth + 3);
+        assert_eq!(vm.get_number(-1).unwrap(), 3.0);
+
+        vm.set_top(depth + 1);
+        assert_eq!(vm.stack_depth(), depth + 1);
+        assert_eq!(vm.get_number(-1).unwrap(), 1.0);
+
+        vm.set_top(depth + 3);
+        assert_eq!(vm.stack_depth(), depth + 3);
+        assert!(vm.get_number(-1).is_none());
+        assert_eq!(vm.get_number(-3).unwrap(), 1.0);
+
+        vm.set_top(depth);
+        assert_eq!(vm.stack_depth(), depth);
+    }
+
+    #[test]
+    #[should_panic]
+    fn restore_panic() {
+        let vm = VM::new(Conf::new());
+        let mut vm = vm.init_runtime();
+        vm.push_number(1.0);
+        let cp = vm.checkpoint();
+        vm.pop();
+        vm.restore(cp);
+    }
+
+    #[test]
+    fn pop_to() -> Result<()> {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+        vm.push_string("rest");
+        vm.push_number(42.0);
+        assert_eq!(vm.pop_to::<i32>()?, 42);
+
+        vm.push_boolean(true);
+        let res = vm.pop_to::<i32>();
+        assert!(matches!(res, Err(Error::Runtime)));
+        vm.set_global(MAIN_MODULE, "exc")?;
+        vm.pop();
+        vm.eval("<string>", "std.assert(exc is TypeException)")?;
+
+        assert_eq!(vm.pop_to::<std::string::String>()?, "rest");
+        assert!(!vm.validate_slot(-1));
+        Ok(())
+    }
+
+    #[test]
+    fn push_value_and_dup() {
+        let mut vm = VM::new(Conf::new()).init_runtime();
+
+        vm.push_number(1.0);
+        vm.push_string("two");
+        vm.push_value(-2);
+        assert_eq!(vm.get_number(-1), Some(1.0));
+
+        vm.push_value(-2);
+        vm.dup();
+        assert_eq!(vm.get_string(-1).unwrap(), "two");
+        assert_eq!(vm.get_string(-2).unwrap(), "two");
+        assert_eq!(vm.get_number(-3), Some(1.0));
+        assert_eq!(vm.get_string(-4).unwrap(), "two");
+
+        vm.pop_n(5);
+        assert!(!vm.validate_slot(-1));
+    }
+
     #[test]
     fn validate_slot_success() {
         let vm = VM::new(Conf::new());